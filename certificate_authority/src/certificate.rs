@@ -4,54 +4,53 @@ use aes_gcm_siv::{
     Aes256GcmSiv, KeyInit,
 };
 use chrono::{prelude::*, Duration};
+use liserk_shared::{certificate::Certificate, session::CipherSuite};
 use pqc_kyber::*;
 use pqcrypto_falcon::falcon512;
-use serde::{Deserialize, Serialize};
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
 use std::{fs::File, io::Write};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Certificate {
-    pub public_key: Vec<u8>,
-    pub identity_info: String,
-    pub issuer_info: String,
-    pub signature: Vec<u8>,
-    pub valid_from: DateTime<Utc>,
-    pub valid_to: DateTime<Utc>,
-    pub serial_number: Uuid,
-    pub cipher_suits: Vec<String>,
-}
+/// care year % 4 BUT
+fn new_certificate(
+    public_key: Vec<u8>,
+    secret_key: &falcon512::SecretKey,
+) -> Certificate {
+    let issuer = String::from("Stuga Cloud Certificate Authority");
+    let server = String::from("Server");
+    let now = Utc::now();
+    let end_of_validity = now + Duration::days(365);
 
-fn as_bytes<T>(input: &T) -> &[u8] {
-    let ptr = input as *const T as *const u8;
-    unsafe { std::slice::from_raw_parts(ptr, std::mem::size_of::<T>()) }
+    let mut certificate = Certificate {
+        public_key,
+        identity_info: server,
+        issuer_info: issuer,
+        signature: vec![],
+        valid_from: now,
+        valid_to: end_of_validity,
+        serial_number: Uuid::new_v4(),
+        cipher_suits: CipherSuite::SUPPORTED
+            .iter()
+            .map(|suite| suite.name().to_string())
+            .collect(),
+    };
+    let signature = falcon512::detached_sign(&certificate.signed_content(), secret_key);
+    certificate.signature = signature.as_bytes().to_vec();
+    certificate
 }
 
-impl Certificate {
-    /// care year % 4 BUT
-    fn new(public_key: Vec<u8>) -> Self {
-        let issuer = String::from("Stuga Cloud Certificate Authority");
-        let server = String::from("Server");
-        let now = Utc::now();
-        let end_of_validity = now + Duration::days(365);
-        let (_falcon_public_key, secret_key) = falcon512::keypair();
-        let sign = falcon512::sign(&public_key, &secret_key);
-        let sign_as_bytes = as_bytes(&sign);
-
-        Certificate {
-            public_key,
-            identity_info: server,
-            issuer_info: issuer,
-            signature: sign_as_bytes.to_vec(),
-            valid_from: now,
-            valid_to: end_of_validity,
-            serial_number: Uuid::new_v4(),
-            cipher_suits: vec![String::from("kyber768"), String::from("falcon")],
-        }
-    }
+/// Generates a Falcon keypair for the authority, to be copied in the configuration.
+pub fn create_authority_keys() -> String {
+    let (public_key, secret_key) = falcon512::keypair();
+    format!(
+        "falcon_public_key = {:?}\nfalcon_secret_key = {:?}\n",
+        public_key.as_bytes(),
+        secret_key.as_bytes()
+    )
 }
 
-pub fn create_certificate() -> Result<(), Error> {
+/// Returns the path of the public key of the authority, which clients must trust.
+pub fn create_certificate() -> Result<String, Error> {
     let mut rng = rand::thread_rng();
     let alice_keys = keypair(&mut rng);
 
@@ -64,11 +63,17 @@ pub fn create_certificate() -> Result<(), Error> {
         .encrypt(GenericArray::from_slice(&nonce), alice_keys.secret.as_ref())
         .expect("encryption failure!");
 
-    let certificate = Certificate::new(alice_keys.public.to_vec());
+    let secret_key =
+        falcon512::SecretKey::from_bytes(&SETTINGS.cipher.falcon_secret_key)?;
+    let certificate = new_certificate(alice_keys.public.to_vec(), &secret_key);
     store_certificate(&SETTINGS.cipher.certificates_path, certificate)?;
-    store_kyber_private_key(&SETTINGS.cipher.certificates_path, ciphertext)?;
+    let authority_path = store_authority_public_key(
+        &SETTINGS.cipher.certificates_path,
+        &SETTINGS.cipher.falcon_public_key,
+    )?;
+    store_kyber_private_key(&SETTINGS.cipher.certificates_path, &nonce, ciphertext)?;
 
-    Ok(())
+    Ok(authority_path)
 }
 
 fn store_certificate(path: &String, certificate: Certificate) -> Result<(), Error> {
@@ -80,12 +85,28 @@ fn store_certificate(path: &String, certificate: Certificate) -> Result<(), Erro
     Ok(())
 }
 
-//fn store_flacon_private_key() {}
+/// Clients need the public key of the authority to verify the certificate, returns the
+/// path it is stored at.
+fn store_authority_public_key(
+    path: &String,
+    public_key: &[u8],
+) -> Result<String, Error> {
+    let file_path = format!("{}authority.falcon", path);
+    let mut file = File::create(&file_path)?;
+    file.write_all(public_key)?;
+    Ok(file_path)
+}
 
-fn store_kyber_private_key(path: &String, ciphertext: Vec<u8>) -> Result<(), Error> {
+/// The nonce is stored in front of the encrypted key, the server needs it to decrypt it.
+fn store_kyber_private_key(
+    path: &String,
+    nonce: &[u8],
+    ciphertext: Vec<u8>,
+) -> Result<(), Error> {
     let file_path = format!("{}encrypted.kyber", path);
     println!("path file: {}", file_path);
     let mut file = File::create(file_path)?;
+    file.write_all(nonce)?;
     file.write_all(&ciphertext)?;
     Ok(())
 }
@@ -8,4 +8,5 @@ pub enum Error {
     Io(#[from] io::Error),
     Config(#[from] ConfigError),
     Serialization(#[from] toml::ser::Error),
+    Falcon(#[from] pqcrypto_traits::Error),
 }
@@ -3,7 +3,7 @@ extern crate pqcrypto_falcon;
 use std::net::SocketAddr;
 
 use axum::{http::StatusCode, response::IntoResponse, routing::post, Router};
-use certificate::create_certificate;
+use certificate::{create_authority_keys, create_certificate};
 use settings::SETTINGS;
 
 mod certificate;
@@ -16,6 +16,7 @@ async fn main() {
     let app = Router::new().nest(
         "/certificate",
         Router::new()
+            .route("/create_authority_keys", post(create_authority_keys_handler))
             .route("/create_certificate", post(create_certificate_handler))
             .route("/verify_certificate", post(verify_certificate_handler)),
     );
@@ -31,9 +32,9 @@ async fn main() {
 
 async fn create_certificate_handler() -> impl IntoResponse {
     match create_certificate() {
-        Ok(_) => {
+        Ok(authority_path) => {
             let certificates_path = &SETTINGS.cipher.certificates_path;
-            let message = format!("Certificate created successfully at {}/certificate.crt and {}/encrypted.kyber", certificates_path, certificates_path);
+            let message = format!("Certificate created successfully at {}/certificate.crt and {}/encrypted.kyber, clients trust {}", certificates_path, certificates_path, authority_path);
             (StatusCode::OK, message)
         }
         Err(e) => (
@@ -43,6 +44,10 @@ async fn create_certificate_handler() -> impl IntoResponse {
     }
 }
 
+async fn create_authority_keys_handler() -> impl IntoResponse {
+    (StatusCode::OK, create_authority_keys())
+}
+
 async fn verify_certificate_handler() -> impl IntoResponse {
     // TODO verify cert
     (StatusCode::OK, "Verification endpoint")
@@ -15,6 +15,7 @@ pub struct Logging {
 pub struct Cipher {
     pub aes_key: Vec<u8>,
     pub certificates_path: String,
+    pub falcon_public_key: Vec<u8>,
     pub falcon_secret_key: Vec<u8>,
 }
 
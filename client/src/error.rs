@@ -1,5 +1,12 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
 use config::ConfigError;
+use liserk_ope::ope::OpeError;
+use liserk_shared::codec::CodecError;
+use liserk_shared::error::ErrorCode;
 use liserk_shared::message_type::MessageTypeError;
+use liserk_shared::session::HandshakeError;
 
 /// Enum representing the possible errors that can be encountered by the client.
 #[derive(Debug, thiserror::Error)]
@@ -14,11 +21,90 @@ pub enum Error {
     /// Represents an error encountered during serialization using CBOR format.
     SerializationError(#[from] serde_cbor::Error),
 
+    /// Represents an invalid frame received from or sent to the server.
+    CodecError(#[from] CodecError),
+
+    /// Represents a failure to establish the secure session.
+    HandshakeError(#[from] HandshakeError),
+
+    /// Represents a server certificate refused by the trust anchor.
+    CertificateError(#[from] CertificateError),
+
+    /// The server closed the connection before answering.
+    ConnectionClosed,
+
+    /// The server refused the username and password.
+    AuthenticationFailed,
+
     /// Represents an error regarding the type of message.
     MessageTypeError(#[from] MessageTypeError),
 
     /// Represents an encryption error when using AES-GCM-SIV.
     EcryptionError(AesError),
+
+    /// A document was returned with a nonce which is not 12 bytes long, such as a
+    /// malformed document shared by another user through the ACL.
+    InvalidNonce,
+
+    /// Represents a failure of the order preserving encryption of a value.
+    OpeError(OpeError),
+
+    /// The settings of a usecase give OPE ciphertexts of this many bytes, wider than
+    /// the server accepts.
+    CiphertextTooWide(usize),
+
+    /// The usecases of an OPE insertion have different settings, no ciphertext fits
+    /// all of them.
+    InconsistentOpeSettings(Vec<String>),
+
+    /// Represents an error reported by the server for a request.
+    Server(ServerError),
+}
+
+/// Error sent by the server in `Message::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerError {
+    /// Kind of failure, e.g. `ErrorCode::NotFound`.
+    pub code: ErrorCode,
+    /// Human readable description of the failure.
+    pub message: String,
+    /// Index of the failed request among the messages sent after the handshake.
+    pub request_id: u64,
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "server error {} ({}) on request {}: {}",
+            self.code.code(),
+            self.code,
+            self.request_id,
+            self.message
+        )
+    }
+}
+
+/// Reason why the certificate of the server is refused.
+#[derive(Debug, thiserror::Error)]
+pub enum CertificateError {
+    #[error("invalid falcon public key for the certificate authority")]
+    InvalidAuthorityKey,
+
+    #[error("certificate is not signed by the certificate authority")]
+    BadSignature,
+
+    #[error("certificate is not valid before {0}")]
+    NotYetValid(DateTime<Utc>),
+
+    #[error("certificate expired on {0}")]
+    Expired(DateTime<Utc>),
+
+    #[error("certificate is issued to {actual}, expected {expected}")]
+    IdentityMismatch { expected: String, actual: String },
+
+    #[error("certificate does not allow cipher suite {0}")]
+    CipherSuiteNotAllowed(String),
 }
 
 #[derive(Debug)]
@@ -11,7 +11,10 @@ use error::{AesError, Error};
 use serde::{Deserialize, Serialize};
 
 pub mod error;
+pub mod ope;
+pub mod sse;
 pub mod stream;
+pub mod trust;
 
 /// Serializes a data structure into a Vec<u8> using CBOR format.
 ///
@@ -1,18 +1,15 @@
-use liserk_shared::message::{ClientSetupSecureConnection, Message};
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
-
 use liserk_client::error::Error;
-use pqc_kyber::keypair;
+use liserk_client::stream::UnconnectedClient;
+use liserk_client::trust::TrustAnchor;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let mut rng = rand::thread_rng();
-    let alice_keys = keypair(&mut rng);
-    let first_message = ClientSetupSecureConnection::new(alice_keys.public.to_vec());
-    let mut stream = TcpStream::connect("127.0.0.1:5545").await?;
-    let message = Message::ClientSetup(first_message);
-    let request = message.setup_for_network()?;
-    stream.write_all(&request).await?;
+    let authority_key = std::fs::read("authority.falcon")?;
+    let trust_anchor = TrustAnchor::new(&authority_key, "Server")?;
+    let client = UnconnectedClient.connect("127.0.0.1:5545", &trust_anchor).await?;
+    let mut client = client
+        .authenticate(String::from("Bob"), String::from("Pomme"), [0; 32])
+        .await?;
+    client.terminate_connection().await?;
     Ok(())
 }
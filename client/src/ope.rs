@@ -0,0 +1,353 @@
+//! Keyed order preserving encryption of the values inserted with `insert_ope`.
+//!
+//! The OPE key is derived from the AES key of the client, so there is no other key
+//! to store. The encoder of a value is picked from its type, see [`OpeValue`], and
+//! each usecase can declare the settings of its encoders and choose order revealing
+//! encryption (ORE) instead of OPE, the default ones are used otherwise.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use liserk_ope::encode::{
+    Ciphertext, DateEncoder, EncodedOpe, FixedPoint, OpeEncode, OreEncode, SignedOffset,
+    StringPrefix, TimestampEncoder,
+};
+use liserk_ope::ope::OpeError;
+use liserk_ope::ore::Ore;
+use liserk_shared::query::{RangeScheme, OPE_CIPHERTEXT_WIDTH};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+
+use crate::error::Error;
+
+/// Label of the HMAC deriving the OPE key from the AES key.
+const OPE_KEY_LABEL: &[u8] = b"liserk ope key";
+
+/// Decimal digits kept from the floats by default.
+pub const DEFAULT_SCALE: u32 = 2;
+
+/// Bytes of the strings ordered by default.
+pub const DEFAULT_PREFIX_LEN: usize = 8;
+
+/// Settings of the encoders of a usecase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpeSettings {
+    /// Decimal digits kept from the floats, see [`FixedPoint`].
+    pub scale: u32,
+    /// Bytes of the strings that are ordered, see [`StringPrefix`].
+    pub prefix_len: usize,
+    /// Scheme of the ciphertexts the server compares.
+    pub scheme: RangeScheme,
+}
+
+impl Default for OpeSettings {
+    fn default() -> Self {
+        Self {
+            scale: DEFAULT_SCALE,
+            prefix_len: DEFAULT_PREFIX_LEN,
+            scheme: RangeScheme::Ope,
+        }
+    }
+}
+
+/// Type of the values that can be inserted with OPE, which picks their encoder.
+///
+/// With ORE, the value itself is serialized and stored encrypted with AES, as its
+/// encoding may be lossy.
+pub trait OpeValue: Sized + Serialize + DeserializeOwned {
+    type Encoder: OreEncode<Value = Self>;
+
+    fn encoder(settings: &OpeSettings) -> Self::Encoder;
+}
+
+impl OpeValue for f64 {
+    type Encoder = FixedPoint;
+
+    fn encoder(settings: &OpeSettings) -> FixedPoint {
+        FixedPoint { scale: settings.scale }
+    }
+}
+
+impl OpeValue for i64 {
+    type Encoder = SignedOffset;
+
+    fn encoder(_: &OpeSettings) -> SignedOffset {
+        SignedOffset
+    }
+}
+
+impl OpeValue for NaiveDate {
+    type Encoder = DateEncoder;
+
+    fn encoder(_: &OpeSettings) -> DateEncoder {
+        DateEncoder
+    }
+}
+
+impl OpeValue for DateTime<Utc> {
+    type Encoder = TimestampEncoder;
+
+    fn encoder(_: &OpeSettings) -> TimestampEncoder {
+        TimestampEncoder
+    }
+}
+
+impl OpeValue for String {
+    type Encoder = StringPrefix;
+
+    fn encoder(settings: &OpeSettings) -> StringPrefix {
+        StringPrefix { len: settings.prefix_len }
+    }
+}
+
+/// OPE key of a client and settings of its usecases.
+#[derive(Debug, Clone)]
+pub struct OpeKeyring {
+    key: [u8; 32],
+    settings: HashMap<String, OpeSettings>,
+}
+
+impl OpeKeyring {
+    /// Derives the OPE key from the AES key of the client.
+    pub fn new(aes_key: &[u8; 32]) -> Self {
+        let mut mac = Hmac::<Sha256>::new_from_slice(aes_key)
+            .expect("HMAC accepts keys of any size");
+        mac.update(OPE_KEY_LABEL);
+        Self {
+            key: mac.finalize().into_bytes().into(),
+            settings: HashMap::new(),
+        }
+    }
+
+    /// Sets the settings of the encoders of `usecase`.
+    ///
+    /// They must stay the same for the whole life of the usecase, the ciphertexts of
+    /// different settings can't be compared. With OPE, the strings can't have a
+    /// prefix whose ciphertexts are wider than [`OPE_CIPHERTEXT_WIDTH`], the server
+    /// refuses them.
+    pub fn set_settings(
+        &mut self,
+        usecase: impl Into<String>,
+        settings: OpeSettings,
+    ) -> Result<(), Error> {
+        if settings.prefix_len == 0 || !10f64.powi(settings.scale as i32).is_finite() {
+            return Err(Error::OpeError(OpeError::InvalidRangeLimitsError));
+        }
+        if settings.scheme == RangeScheme::Ope {
+            let ope = self.cipher::<String>(&settings)?.ope;
+            let width = (ope.out_range.end.significant_bits() as usize).div_ceil(8);
+            if width > OPE_CIPHERTEXT_WIDTH {
+                return Err(Error::CiphertextTooWide(width));
+            }
+        }
+        self.settings.insert(usecase.into(), settings);
+        Ok(())
+    }
+
+    /// Settings of `usecase`, the default ones if none were set.
+    pub fn settings(&self, usecase: &str) -> OpeSettings {
+        self.settings.get(usecase).copied().unwrap_or_default()
+    }
+
+    fn cipher<T: OpeValue>(
+        &self,
+        settings: &OpeSettings,
+    ) -> Result<EncodedOpe<T::Encoder>, Error> {
+        EncodedOpe::new(&self.key, T::encoder(settings)).map_err(Error::OpeError)
+    }
+
+    /// Settings of a record of `usecases`.
+    ///
+    /// A record has a single ciphertext, so all its usecases must have the same
+    /// settings.
+    pub fn usecases_settings(&self, usecases: &[String]) -> Result<OpeSettings, Error> {
+        match usecases.split_first() {
+            Some((usecase, others)) => {
+                let settings = self.settings(usecase);
+                if others.iter().any(|other| self.settings(other) != settings) {
+                    return Err(Error::InconsistentOpeSettings(usecases.to_vec()));
+                }
+                Ok(settings)
+            }
+            None => Ok(OpeSettings::default()),
+        }
+    }
+
+    /// Encrypts `value` for a record of `usecases` into the ciphertext the server
+    /// compares: the OPE ciphertext, or the ORE left ciphertext used as a bound.
+    pub fn encrypt<T: OpeValue>(
+        &self,
+        usecases: &[String],
+        value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        let settings = self.usecases_settings(usecases)?;
+        match settings.scheme {
+            RangeScheme::Ope => {
+                let ciphertext = self
+                    .cipher::<T>(&settings)?
+                    .encrypt(value)
+                    .map_err(Error::OpeError)?;
+                Ok(ciphertext.to_string().into_bytes())
+            }
+            RangeScheme::Ore => {
+                let plaintext = Self::ore_plaintext(&settings, value)?;
+                Ok(Ore::new(&self.key).encrypt_left(&plaintext).to_bytes())
+            }
+        }
+    }
+
+    /// Encrypts `value` into the ORE right ciphertext stored with a record of
+    /// `usecases`.
+    pub fn encrypt_right<T: OpeValue>(
+        &self,
+        usecases: &[String],
+        value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        let plaintext = Self::ore_plaintext(&self.usecases_settings(usecases)?, value)?;
+        Ok(Ore::new(&self.key).encrypt_right(&plaintext).to_bytes())
+    }
+
+    fn ore_plaintext<T: OpeValue>(
+        settings: &OpeSettings,
+        value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        let encoder = T::encoder(settings);
+        let plaintext = encoder.encode(value).map_err(Error::OpeError)?;
+        Ok(encoder.ore_bytes(&plaintext))
+    }
+
+    /// Decrypts the ciphertext of a record of `usecase`, as returned by the server.
+    ///
+    /// The value is decoded from the OPE plaintext, so floats come back rounded to
+    /// the `scale` of the usecase and strings truncated to its `prefix_len`.
+    pub fn decrypt<T: OpeValue>(
+        &self,
+        usecase: &str,
+        ciphertext: &[u8],
+    ) -> Result<T, Error> {
+        let ciphertext = std::str::from_utf8(ciphertext)
+            .ok()
+            .and_then(|ciphertext| ciphertext.parse::<Ciphertext<T::Encoder>>().ok())
+            .ok_or(Error::OpeError(OpeError::InvalidCiphertextError))?;
+        self.cipher::<T>(&self.settings(usecase))?
+            .decrypt(ciphertext)
+            .map_err(Error::OpeError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use liserk_ope::ore::{compare, LeftCiphertext, RightCiphertext};
+
+    use super::*;
+    use crate::{basic_decrypt, basic_encrypt, deserialize, serialize};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let mut keyring = OpeKeyring::new(&[7; 32]);
+        keyring
+            .set_settings("price", OpeSettings { scale: 1, ..Default::default() })
+            .unwrap();
+        let usecases = vec![String::from("price")];
+        for number in [-100.0, -1.5, 0.0, 42.0, 100.0] {
+            let ciphertext = keyring.encrypt(&usecases, &number).unwrap();
+            assert_eq!(keyring.decrypt::<f64>("price", &ciphertext).unwrap(), number);
+        }
+        let ciphertext = keyring.encrypt(&usecases, &1.23).unwrap();
+        assert_eq!(keyring.decrypt::<f64>("price", &ciphertext).unwrap(), 1.2);
+
+        let date = NaiveDate::from_ymd_opt(2023, 6, 1).unwrap();
+        let ciphertext = keyring.encrypt(&usecases, &date).unwrap();
+        assert_eq!(keyring.decrypt::<NaiveDate>("price", &ciphertext).unwrap(), date);
+
+        assert!(matches!(
+            keyring.encrypt(&usecases, &f64::NAN),
+            Err(Error::OpeError(OpeError::OutOfRangeError))
+        ));
+        assert!(matches!(
+            keyring.encrypt(&[String::from("price"), String::from("size")], &1.0),
+            Err(Error::InconsistentOpeSettings(_))
+        ));
+        assert!(matches!(
+            keyring.decrypt::<f64>("price", b"abc"),
+            Err(Error::OpeError(OpeError::InvalidCiphertextError))
+        ));
+        assert!(matches!(
+            keyring.set_settings(
+                "size",
+                OpeSettings { prefix_len: 0, ..Default::default() }
+            ),
+            Err(Error::OpeError(OpeError::InvalidRangeLimitsError))
+        ));
+    }
+
+    #[test]
+    fn test_prefix_fits_the_range_index() {
+        let mut keyring = OpeKeyring::new(&[7; 32]);
+        let widest = OpeSettings {
+            prefix_len: OPE_CIPHERTEXT_WIDTH / 2,
+            ..Default::default()
+        };
+        keyring.set_settings("name", widest).unwrap();
+        let ope = keyring.cipher::<String>(&widest).unwrap().ope;
+        assert_eq!(
+            ope.out_range.end.significant_bits() as usize,
+            8 * OPE_CIPHERTEXT_WIDTH
+        );
+
+        let wider = OpeSettings { prefix_len: widest.prefix_len + 1, ..widest };
+        assert!(matches!(
+            keyring.set_settings("name", wider),
+            Err(Error::CiphertextTooWide(66))
+        ));
+        let ore = OpeSettings { scheme: RangeScheme::Ore, ..wider };
+        keyring.set_settings("name", ore).unwrap();
+    }
+
+    #[test]
+    fn test_ore_scheme() {
+        let mut keyring = OpeKeyring::new(&[7; 32]);
+        let settings = OpeSettings { scheme: RangeScheme::Ore, ..Default::default() };
+        keyring.set_settings("name", settings).unwrap();
+        let usecases = vec![String::from("name")];
+
+        let name = String::from("bob");
+        let bound = keyring.encrypt(&usecases, &name).unwrap();
+        let bound = LeftCiphertext::from_bytes(&bound).unwrap();
+        for (other, ordering) in [("alice", Ordering::Greater), ("bob", Ordering::Equal)]
+        {
+            let right = keyring.encrypt_right(&usecases, &other.to_string()).unwrap();
+            let right = RightCiphertext::from_bytes(&right).unwrap();
+            assert_eq!(compare(&bound, &right), Ok(ordering));
+        }
+        assert!(matches!(
+            keyring.encrypt(&[String::from("name"), String::from("size")], &name),
+            Err(Error::InconsistentOpeSettings(_))
+        ));
+    }
+
+    #[test]
+    fn test_ore_values_round_trip() {
+        let mut keyring = OpeKeyring::new(&[7; 32]);
+        let settings = OpeSettings { scheme: RangeScheme::Ore, ..Default::default() };
+        keyring.set_settings("name", settings).unwrap();
+        let usecases = vec![String::from("name")];
+        let (key, nonce) = ([3; 32], [5; 12]);
+
+        let name = String::from("bartholomew the third");
+        assert!(name.len() > DEFAULT_PREFIX_LEN);
+        keyring.encrypt_right(&usecases, &name).unwrap();
+        let data = basic_encrypt(&key, &nonce, &serialize(&name).unwrap(), &[]).unwrap();
+        let data = basic_decrypt(&key, &nonce, &data, &[]).unwrap();
+        assert_eq!(deserialize::<String>(&data).unwrap(), name);
+
+        let price = 1.23456;
+        keyring.encrypt_right(&usecases, &price).unwrap();
+        let data = basic_encrypt(&key, &nonce, &serialize(&price).unwrap(), &[]).unwrap();
+        let data = basic_decrypt(&key, &nonce, &data, &[]).unwrap();
+        assert_eq!(deserialize::<f64>(&data).unwrap(), price);
+    }
+}
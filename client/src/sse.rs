@@ -0,0 +1,92 @@
+//! Searchable symmetric encryption of the usecases and of the searchable fields of a
+//! record.
+//!
+//! The client replaces each usecase name and each `(field, value)` pair by a token,
+//! the HMAC of its label under a key derived from the AES key of the client. Tokens
+//! are deterministic, so the server can index the records by token and find the
+//! records of a token with `Query::Equals`, but it can't tell which usecase or value a
+//! token stands for. It still sees which records share a token.
+
+use hmac::{Hmac, Mac};
+use liserk_shared::query::Query;
+use sha2::Sha256;
+
+/// Label of the HMAC deriving the SSE key from the AES key.
+const SSE_KEY_LABEL: &[u8] = b"liserk sse key";
+
+/// Domains of the tokens, so a usecase and a field can't have the same token.
+const USECASE_DOMAIN: u8 = 0;
+const FIELD_DOMAIN: u8 = 1;
+
+/// SSE key of a client.
+#[derive(Debug, Clone)]
+pub struct SseKey {
+    key: [u8; 32],
+}
+
+impl SseKey {
+    /// Derives the SSE key from the AES key of the client.
+    pub fn new(aes_key: &[u8; 32]) -> Self {
+        Self { key: hmac(aes_key, &[SSE_KEY_LABEL]) }
+    }
+
+    /// Token standing for `usecase` in the indexes of the server.
+    pub fn usecase_token(&self, usecase: &str) -> String {
+        to_hex(&hmac(&self.key, &[&[USECASE_DOMAIN], usecase.as_bytes()]))
+    }
+
+    /// Token of the records whose `field` is `value`.
+    pub fn field_token(&self, field: &str, value: &[u8]) -> String {
+        // The length of the field keeps ("ab", "c") and ("a", "bc") apart.
+        let field_len = (field.len() as u32).to_be_bytes();
+        to_hex(&hmac(&self.key, &[&[FIELD_DOMAIN], &field_len, field.as_bytes(), value]))
+    }
+
+    /// Query of the records of `collection` inserted with `usecase`.
+    pub fn usecase_query(&self, collection: String, usecase: &str) -> Query {
+        Query::Equals { collection, token: self.usecase_token(usecase) }
+    }
+
+    /// Query of the records of `collection` whose `field` is `value`.
+    pub fn field_query(&self, collection: String, field: &str, value: &[u8]) -> Query {
+        Query::Equals { collection, token: self.field_token(field, value) }
+    }
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Tokens are sent as usecases, which are strings.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let key = SseKey::new(&[7; 32]);
+        let token = key.field_token("city", b"Paris");
+        assert_eq!(token.len(), 64);
+        assert_eq!(token, key.field_token("city", b"Paris"));
+        assert_ne!(token, key.field_token("city", b"Lyon"));
+        assert_ne!(token, SseKey::new(&[8; 32]).field_token("city", b"Paris"));
+        assert_ne!(key.field_token("ab", b"c"), key.field_token("a", b"bc"));
+        assert_ne!(key.usecase_token("city"), key.field_token("city", b""));
+        assert_eq!(
+            key.usecase_query(String::from("users"), "city"),
+            Query::Equals {
+                collection: String::from("users"),
+                token: key.usecase_token("city"),
+            }
+        );
+    }
+}
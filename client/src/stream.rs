@@ -1,23 +1,33 @@
-use liserk_ope::simplified_version::encrypt_ope;
+use chrono::Utc;
+use futures::{SinkExt, StreamExt};
 use liserk_shared::{
+    acl::AclEntry,
+    catalog::CollectionInfo,
+    codec::LiserkCodec,
     message::{
-        ClientAuthentication, ClientSetupSecureConnection, Delete, Insertion,
-        InsertionOpe, Message, Update,
+        AuthenticationResponse, ClientAuthentication, ClientKeyExchange,
+        ClientSetupSecureConnection, Delete, DropSubject, Insertion, InsertionOpe,
+        InsertionOre, Message, NewUser, PasswordChange, Update, UpdateOptions,
     },
-    message_type::{MessageType, MessageTypeError},
-    query::Query,
+    message_type::{MessageTypeError, PROTOCOL_VERSION},
+    query::{Query, QueryPlan, RangeBound, RangeScheme, SingleQueryBuilder},
+    session::{CipherSuite, HandshakeError, SessionCipher, SessionKeys},
 };
 use rand::Rng;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
-    },
-};
-use tracing::{debug, info, trace};
+use std::ops::Bound;
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+use tracing::{debug, info};
+use uuid::Uuid;
 
-use crate::{basic_decrypt, basic_encrypt, error::Error};
+use crate::{
+    basic_decrypt, basic_encrypt, deserialize,
+    error::{Error, ServerError},
+    ope::{OpeKeyring, OpeValue},
+    serialize,
+    sse::SseKey,
+    trust::TrustAnchor,
+};
 
 #[derive(Debug)]
 pub enum QueryResult {
@@ -30,23 +40,30 @@ pub enum QueryResult {
 #[derive(Debug, Default)]
 pub struct UnconnectedClient;
 
-/// Represents a client that has established a connection to the server but is not yet authenticated.
+/// Represents a client that has established a secure session with the server but is not yet authenticated.
 #[derive(Debug)]
 pub struct ConnectedClient {
-    /// The TCP stream representing the connection to the server.
-    pub stream: TcpStream,
+    /// The connection to the server.
+    pub stream: Framed<TcpStream, LiserkCodec>,
 }
 
 /// Represents a client that has been authenticated.
 #[derive(Debug)]
 pub struct AuthenticatedClient {
-    /// The read half of the TCP stream.
-    pub read: OwnedReadHalf,
-
-    /// The write half of the TCP stream.
-    pub write: OwnedWriteHalf,
+    /// The connection to the server.
+    pub stream: Framed<TcpStream, LiserkCodec>,
 
     pub key: [u8; 32],
+
+    /// OPE key, derived from `key`, and settings of the usecases.
+    pub ope: OpeKeyring,
+
+    /// SSE key, derived from `key`, which hides the usecases and the searchable
+    /// fields of the records inserted with [`Self::insert_searchable`].
+    pub sse: SseKey,
+
+    /// Identifier of the authenticated session, given by the server.
+    pub session_id: Uuid,
 }
 
 impl UnconnectedClient {
@@ -55,16 +72,50 @@ impl UnconnectedClient {
     /// # Arguments
     ///
     /// * `url` - The URL of the server to connect to.
-    pub async fn connect(self, url: &str) -> Result<ConnectedClient, Error> {
-        let mut rng = rand::thread_rng();
-        let kyber_key = pqc_kyber::keypair(&mut rng);
-        let mut stream = TcpStream::connect(url).await?;
+    /// * `trust_anchor` - The certificate authority which must have signed the
+    ///   certificate of the server. The connection is refused with
+    ///   `Error::CertificateError` when the certificate does not pass the checks.
+    pub async fn connect(
+        self,
+        url: &str,
+        trust_anchor: &TrustAnchor,
+    ) -> Result<ConnectedClient, Error> {
+        let kyber_key = pqc_kyber::keypair(&mut rand::thread_rng());
+        let stream = TcpStream::connect(url).await?;
+        let mut stream = Framed::new(stream, LiserkCodec::default());
         let setup_security = Message::ClientSetup(ClientSetupSecureConnection::new(
             kyber_key.public.to_vec(),
         ));
-        let message = setup_security.setup_for_network()?;
+        stream.send(setup_security).await?;
 
-        stream.write_all(&message).await?;
+        let setup = match stream.next().await.ok_or(Error::ConnectionClosed)?? {
+            Message::ServerSetup(setup) => setup,
+            message => return Err(unexpected_response(message)),
+        };
+        if setup.protocol_version != PROTOCOL_VERSION as u32 {
+            return Err(HandshakeError::UnsupportedVersion(setup.protocol_version).into());
+        }
+        let cipher_suit =
+            CipherSuite::from_name(&setup.cipher_suit).ok_or_else(|| {
+                HandshakeError::NoCommonCipherSuite(vec![setup.cipher_suit.clone()])
+            })?;
+        trust_anchor.verify(&setup.certificate, cipher_suit, Utc::now())?;
+        let client_secret = pqc_kyber::decapsulate(&setup.ciphertext, &kyber_key.secret)
+            .map_err(|_| HandshakeError::KeyEncapsulation)?;
+        let (ciphertext, server_secret) = pqc_kyber::encapsulate(
+            &setup.certificate.public_key,
+            &mut rand::thread_rng(),
+        )
+        .map_err(|_| HandshakeError::KeyEncapsulation)?;
+        let exchange = ClientKeyExchange { ciphertext: ciphertext.to_vec() };
+        stream.send(Message::ClientKeyExchange(exchange)).await?;
+
+        let keys =
+            SessionKeys::derive(&[&client_secret, &server_secret], setup.session_id);
+        let codec = stream.codec_mut();
+        codec.encrypt_with(SessionCipher::new(&keys.client_to_server));
+        codec.decrypt_with(SessionCipher::new(&keys.server_to_client));
+        info!("session {} established with {}", setup.session_id, cipher_suit);
         Ok(ConnectedClient { stream })
     }
 }
@@ -80,14 +131,16 @@ impl ConnectedClient {
     /// # Returns
     ///
     /// * `Result<AuthenticatedClient, Error>` - If successful, returns an instance of AuthenticatedClient.
-    ///                                          Otherwise, returns an Error indicating what went wrong.
+    ///                                          Otherwise, returns an Error indicating what went wrong,
+    ///                                          `Error::AuthenticationFailed` for invalid credentials.
     ///
     /// # Example
     ///
     /// ```
     /// # async fn run_example() -> Result<(), Error> {
     /// let unconnected_client = UnconnectedClient;
-    /// let connected_client = unconnected_client.connect("127.0.0.1:12345").await?;
+    /// let trust_anchor = TrustAnchor::new(&authority_key, "Server")?;
+    /// let connected_client = unconnected_client.connect("127.0.0.1:12345", &trust_anchor).await?;
     /// let authenticated_client = connected_client.authenticate("username".to_string(), "password".to_string()).await?;
     /// # Ok(()) }
     /// ```
@@ -99,13 +152,27 @@ impl ConnectedClient {
     ) -> Result<AuthenticatedClient, Error> {
         let client_authentication = ClientAuthentication { username, password };
         let message = Message::ClientAuthentification(client_authentication);
-        let message = message.setup_for_network()?;
-        // debug!("message {:?}", message);
-        self.stream.write_all(&message).await?;
+        self.stream.send(message).await?;
 
-        let (read, write) = self.stream.into_split();
-        let auth_client = AuthenticatedClient { read, write, key };
-        Ok(auth_client)
+        let message = self.stream.next().await.ok_or(Error::ConnectionClosed)??;
+        match message {
+            Message::AuthenticationResponse(AuthenticationResponse::Success {
+                session_id,
+            }) => {
+                info!("authenticated, session {}", session_id);
+                Ok(AuthenticatedClient {
+                    stream: self.stream,
+                    key,
+                    ope: OpeKeyring::new(&key),
+                    sse: SseKey::new(&key),
+                    session_id,
+                })
+            }
+            Message::AuthenticationResponse(AuthenticationResponse::Failure) => {
+                Err(Error::AuthenticationFailed)
+            }
+            message => Err(unexpected_response(message)),
+        }
     }
 }
 
@@ -121,13 +188,17 @@ impl AuthenticatedClient {
 
     /// Terminates the connection of the client.
     pub async fn terminate_connection(&mut self) -> Result<(), Error> {
-        let message = Message::EndOfCommunication;
-        let message = message.setup_for_network()?;
-        // debug!("terminate Connection {:?}", message);
-        self.write.write_all(&message).await?;
+        self.stream.send(Message::EndOfCommunication).await?;
         Ok(())
     }
 
+    /// Waits for the next message of the server.
+    async fn receive(&mut self) -> Result<Message, Error> {
+        let message = self.stream.next().await.ok_or(Error::ConnectionClosed)??;
+        debug!("parsed message: {:#?}", message);
+        Ok(message)
+    }
+
     /// Inserts data into a specified collection.
     ///
     /// # Arguments
@@ -135,15 +206,31 @@ impl AuthenticatedClient {
     /// * `collection` - The name of the collection to insert the data into.
     /// * `data` - The data to be inserted.
     /// * `associated_data` - The associated data to be verified.
-    /// * `acl` - The access control list.
+    /// * `acl` - Who may read or modify the data, the current user is always given
+    ///   [`Permission::Admin`](liserk_shared::acl::Permission::Admin).
     /// * `usecases` - The use cases associated with the data.
     pub async fn insert(
         &mut self,
         collection: String,
         data: Vec<u8>,
         associated_data: Vec<u8>,
-        acl: Vec<String>,
+        acl: Vec<AclEntry>,
+        usecases: Vec<String>,
+    ) -> Result<String, Error> {
+        self.send_insertion(collection, data, associated_data, acl, usecases, false)
+            .await
+    }
+
+    /// Encrypts and inserts data, `searchable` tells the server its usecases are SSE
+    /// tokens.
+    async fn send_insertion(
+        &mut self,
+        collection: String,
+        data: Vec<u8>,
+        associated_data: Vec<u8>,
+        acl: Vec<AclEntry>,
         usecases: Vec<String>,
+        searchable: bool,
     ) -> Result<String, Error> {
         let mut nonce = [0u8; 12];
         rand::thread_rng().fill(&mut nonce);
@@ -154,65 +241,208 @@ impl AuthenticatedClient {
             data: encrypt_data,
             usecases,
             nonce: nonce.to_vec(),
+            searchable,
         });
-        let message = message.setup_for_network()?;
-        self.write.write_all(&message).await?;
-        let message = parse_message_from_tcp_stream(&mut self.read).await?;
+        self.stream.send(message).await?;
+        let message = self.receive().await?;
         info!("message: {:?}", message);
         match message {
-            Message::InsertResponse { inserted_id } => Ok(inserted_id),
-            _ => Err(Error::MessageTypeError(MessageTypeError::default())),
+            Message::InsertResponse { inserted_id, .. } => Ok(inserted_id),
+            message => Err(unexpected_response(message)),
         }
     }
 
-    /// Inserts a number into the database with Order Preserving Encryption (OPE).
+    /// Inserts data like [`Self::insert`], with its usecases and its searchable fields
+    /// replaced by SSE tokens so the server can't read them.
+    ///
+    /// The records are found with [`Self::query_equals`], or with the queries of
+    /// [`SseKey::usecase_query`] for a usecase.
     ///
     /// # Arguments
     ///
-    /// * `number_to_encrypt` - The number to be encrypted and inserted.
-    /// * `acl` - The access control list.
+    /// * `collection` - The name of the collection to insert the data into.
+    /// * `data` - The data to be inserted.
+    /// * `associated_data` - The associated data to be verified.
+    /// * `acl` - Who may read or modify the data, the current user is always given
+    ///   [`Permission::Admin`](liserk_shared::acl::Permission::Admin).
+    /// * `usecases` - The use cases associated with the data.
+    /// * `fields` - The fields the data can be searched by, and their values.
+    pub async fn insert_searchable(
+        &mut self,
+        collection: String,
+        data: Vec<u8>,
+        associated_data: Vec<u8>,
+        acl: Vec<AclEntry>,
+        usecases: Vec<String>,
+        fields: Vec<(String, Vec<u8>)>,
+    ) -> Result<String, Error> {
+        let usecase_tokens =
+            usecases.iter().map(|usecase| self.sse.usecase_token(usecase));
+        let field_tokens =
+            fields.iter().map(|(field, value)| self.sse.field_token(field, value));
+        let tokens = usecase_tokens.chain(field_tokens).collect();
+        self.send_insertion(collection, data, associated_data, acl, tokens, true)
+            .await
+    }
+
+    /// Queries the records inserted with [`Self::insert_searchable`] whose `field`
+    /// is `value`.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The name of the collection to query.
+    /// * `field` - The searchable field.
+    /// * `value` - The value the field must have.
+    pub async fn query_equals(
+        &mut self,
+        collection: String,
+        field: &str,
+        value: &[u8],
+    ) -> Result<QueryResult, Error> {
+        let query = self.sse.field_query(collection, field, value);
+        self.query(query).await
+    }
+
+    /// Inserts a value into the database with Order Preserving Encryption (OPE).
+    ///
+    /// The value is encoded by the encoder of its type, see [`OpeValue`], with the
+    /// settings of [`Self::ope`] for its usecases, which must all have the same
+    /// settings. If they choose ORE, the record holds the ORE right ciphertext of the
+    /// value, and the value serialized with CBOR and encrypted with AES, so it is
+    /// read back whole while the encoding may be lossy.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to be encrypted and inserted.
+    /// * `acl` - Who may read or modify the data, the current user is always given
+    ///   [`Permission::Admin`](liserk_shared::acl::Permission::Admin).
     /// * `usecases` - The use cases associated with the data.
     /// * `collection` - The name of the collection to insert the data into.
-    pub async fn insert_ope(
+    pub async fn insert_ope<T: OpeValue>(
         &mut self,
-        number_to_encrypt: f64,
-        acl: Vec<String>,
+        value: T,
+        acl: Vec<AclEntry>,
         usecases: Vec<String>,
         collection: String,
     ) -> Result<String, Error> {
-        let encrypted_number = encrypt_ope(number_to_encrypt);
-        let data = encrypted_number.to_string().as_bytes().to_vec();
-
-        let message =
-            Message::InsertOpe(InsertionOpe { acl, collection, data, usecases });
-        let message = message.setup_for_network()?;
-        self.write.write_all(&message).await?;
-        let message = parse_message_from_tcp_stream(&mut self.read).await?;
+        let message = match self.ope.usecases_settings(&usecases)?.scheme {
+            RangeScheme::Ope => {
+                let data = self.ope.encrypt(&usecases, &value)?;
+                Message::InsertOpe(InsertionOpe { acl, collection, data, usecases })
+            }
+            RangeScheme::Ore => {
+                let ciphertext = self.ope.encrypt_right(&usecases, &value)?;
+                let mut nonce = [0u8; 12];
+                rand::thread_rng().fill(&mut nonce);
+                let data = basic_encrypt(&self.key, &nonce, &serialize(&value)?, &[])?;
+                Message::InsertOre(InsertionOre {
+                    collection,
+                    acl,
+                    data,
+                    usecases,
+                    nonce: nonce.to_vec(),
+                    ciphertext,
+                })
+            }
+        };
+        self.stream.send(message).await?;
+        let message = self.receive().await?;
         info!("message: {:?}", message);
         match message {
-            Message::InsertResponse { inserted_id } => Ok(inserted_id),
-            _ => Err(Error::MessageTypeError(MessageTypeError::default())),
+            Message::InsertResponse { inserted_id, .. } => Ok(inserted_id),
+            message => Err(unexpected_response(message)),
         }
     }
 
+    /// Encrypts the bound of a range query on `usecase`, so the server only sees its
+    /// ciphertext.
+    ///
+    /// Returns `None` for an unbounded range.
+    pub fn encrypt_bound<T: OpeValue>(
+        &self,
+        usecase: &str,
+        bound: Bound<T>,
+    ) -> Result<Option<RangeBound>, Error> {
+        let usecases = [usecase.to_string()];
+        let bound = match bound {
+            Bound::Included(value) => {
+                RangeBound::inclusive(self.ope.encrypt(&usecases, &value)?)
+            }
+            Bound::Excluded(value) => {
+                RangeBound::exclusive(self.ope.encrypt(&usecases, &value)?)
+            }
+            Bound::Unbounded => return Ok(None),
+        };
+        Ok(Some(bound.with_scheme(self.ope.settings(usecase).scheme)))
+    }
+
+    /// Queries the records inserted with [`Self::insert_ope`] for a usecase whose
+    /// value is within the bounds, and decrypts their values.
+    ///
+    /// The values of the usecase must all have the type of the bounds.
+    /// With OPE, the values are decoded from their ciphertext, so they are lossy,
+    /// see [`OpeKeyring::decrypt`]. With ORE, they are the values inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The name of the collection to query.
+    /// * `usecase` - The use case the values were inserted with.
+    /// * `lower` - The lower bound of the values.
+    /// * `upper` - The upper bound of the values.
+    pub async fn query_range<T: OpeValue>(
+        &mut self,
+        collection: String,
+        usecase: String,
+        lower: Bound<T>,
+        upper: Bound<T>,
+    ) -> Result<Vec<T>, Error> {
+        let mut query = SingleQueryBuilder::default()
+            .with_collection(collection)
+            .with_usecase(usecase.clone())
+            .build();
+        query.lower_limit = self.encrypt_bound(&usecase, lower)?;
+        query.upper_limit = self.encrypt_bound(&usecase, upper)?;
+        let values = match self.query(Query::Single(query)).await? {
+            QueryResult::EmptyResult => vec![],
+            QueryResult::SingleValue(value) => vec![value],
+            QueryResult::MultipleValues(values) => values,
+        };
+        // The ORE records are decrypted with AES by `query`.
+        values
+            .iter()
+            .map(|value| match self.ope.settings(&usecase).scheme {
+                RangeScheme::Ope => self.ope.decrypt(&usecase, value),
+                RangeScheme::Ore => deserialize(value),
+            })
+            .collect()
+    }
+
     /// Queries the database and returns the results.
     ///
+    /// Records inserted with [`Self::insert_ope`] are not decrypted, their OPE
+    /// ciphertext is returned, see [`OpeKeyring::decrypt`].
+    ///
     /// # Arguments
     ///
     /// * `query` - The query object representing the database query.
     pub async fn query(&mut self, query: Query) -> Result<QueryResult, Error> {
         let message = Message::Query(query);
-        let message = message.setup_for_network()?;
-        self.write.write_all(&message).await?;
-        let message = parse_message_from_tcp_stream(&mut self.read).await?;
+        self.stream.send(message).await?;
+        let message = self.receive().await?;
         info!("message: {:?}", message);
         match message {
-            Message::QueryResponse((data, nonces)) => {
+            Message::QueryResponse((data, None)) => Ok(QueryResult::MultipleValues(data)),
+            Message::QueryResponse((data, Some(nonces))) => {
                 let mut values = Vec::with_capacity(data.len());
-                for (cipher, nonce) in data.iter().zip(nonces.unwrap().iter()) {
+                for (cipher, nonce) in data.into_iter().zip(nonces) {
+                    let Some(nonce) = nonce else {
+                        // An OPE record, returned as its ciphertext.
+                        values.push(cipher);
+                        continue;
+                    };
                     let value = basic_decrypt(
                         &self.key,
-                        convert_to_array12(&nonce).expect("12 elements"),
+                        convert_to_array12(&nonce).ok_or(Error::InvalidNonce)?,
                         &cipher,
                         &[],
                     )?;
@@ -220,19 +450,87 @@ impl AuthenticatedClient {
                 }
                 Ok(QueryResult::MultipleValues(values))
             }
-            Message::SingleValueResponse { data, nonce } => {
-                if data.is_none() || nonce.is_none() {
-                    return Ok(QueryResult::EmptyResult);
+            Message::SingleValueResponse { data, nonce, .. } => {
+                match self.decrypt_single_value(data, nonce)? {
+                    Some(value) => Ok(QueryResult::SingleValue(value)),
+                    None => Ok(QueryResult::EmptyResult),
                 }
-                let value = basic_decrypt(
-                    &self.key,
-                    convert_to_array12(&nonce.expect("Not ope")).expect("12 elements"),
-                    &data.expect("if is none reutrn empty result"),
-                    &[],
-                )?;
-                Ok(QueryResult::SingleValue(value))
             }
-            _ => Err(Error::MessageTypeError(MessageTypeError::default())),
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    fn decrypt_single_value(
+        &self,
+        data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (Some(data), Some(nonce)) = (data, nonce) else {
+            return Ok(None);
+        };
+        let nonce = convert_to_array12(&nonce).ok_or(Error::InvalidNonce)?;
+        Ok(Some(basic_decrypt(&self.key, nonce, &data, &[])?))
+    }
+
+    /// Reads a document and its version, which can be given as the expected version
+    /// of [`Self::modify_with`] and [`Self::delete_at_version`].
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The identifier of the document.
+    /// * `collection` - The name of the collection containing the document.
+    pub async fn get_with_version(
+        &mut self,
+        id: String,
+        collection: String,
+    ) -> Result<Option<(Vec<u8>, u64)>, Error> {
+        self.stream
+            .send(Message::Query(Query::GetById { id, collection }))
+            .await?;
+        match self.receive().await? {
+            Message::SingleValueResponse { data, nonce, version } => {
+                let value = self.decrypt_single_value(data, nonce)?;
+                Ok(value.zip(version))
+            }
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Reads a document at `version`, its current version or a previous one kept by
+    /// the history of the collection, see [`Self::set_history`].
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The identifier of the document.
+    /// * `collection` - The name of the collection containing the document.
+    /// * `version` - The version to read.
+    pub async fn get_version(
+        &mut self,
+        id: String,
+        collection: String,
+        version: u64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let query = Query::GetVersion { id, collection, version };
+        self.stream.send(Message::Query(query)).await?;
+        match self.receive().await? {
+            Message::SingleValueResponse { data, nonce, .. } => {
+                self.decrypt_single_value(data, nonce)
+            }
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Returns the plan the server would use to evaluate a query, without evaluating it.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to explain.
+    pub async fn explain(&mut self, query: Query) -> Result<QueryPlan, Error> {
+        let message = Message::Query(Query::Explain(Box::new(query)));
+        self.stream.send(message).await?;
+        match self.receive().await? {
+            Message::ExplainResponse(plan) => Ok(plan),
+            message => Err(unexpected_response(message)),
         }
     }
 
@@ -249,20 +547,56 @@ impl AuthenticatedClient {
         collection: String,
         new_value: Vec<u8>,
     ) -> Result<Message, Error> {
-        let update = Update { collection, id, new_value };
+        self.modify_with(id, collection, new_value, UpdateOptions::default())
+            .await
+    }
+
+    /// Modifies an existing document, encrypting its new value with a new nonce and no
+    /// associated data, as the queries decrypt it, and changes its use cases and its
+    /// ACL.
+    ///
+    /// The server answers `UpdateStatus::Conflict` with the current version of the
+    /// document if it is not at `options.expected_version`. The documents inserted
+    /// with OPE or ORE can't be modified, the server refuses them with an
+    /// `ErrorCode::Unsupported` error.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The identifier of the document to be modified.
+    /// * `collection` - The name of the collection containing the document.
+    /// * `new_value` - The new value to be set in the document.
+    /// * `options` - The use cases to add and remove, the new ACL and the expected
+    ///   version.
+    pub async fn modify_with(
+        &mut self,
+        id: String,
+        collection: String,
+        new_value: Vec<u8>,
+        options: UpdateOptions,
+    ) -> Result<Message, Error> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill(&mut nonce);
+        let new_value = basic_encrypt(&self.key, &nonce, &new_value, &[])?;
+        let update = Update {
+            collection,
+            id,
+            new_value,
+            nonce: nonce.to_vec(),
+            options,
+        };
         let message = Message::Update(update);
-        let message = message.setup_for_network()?;
-        self.write.write_all(&message).await?;
-        let message = parse_message_from_tcp_stream(&mut self.read).await?;
+        self.stream.send(message).await?;
+        let message = self.receive().await?;
 
         info!("message: {:?}", message);
         match message {
             Message::UpdateResponse { .. } => Ok(message),
-            _ => Err(Error::MessageTypeError(MessageTypeError::default())),
+            message => Err(unexpected_response(message)),
         }
     }
 
-    /// Deletes a document from the database.
+    /// Deletes a document from the database, with its entries in the use case
+    /// indexes.
     ///
     /// # Arguments
     ///
@@ -273,48 +607,207 @@ impl AuthenticatedClient {
         id: String,
         collection: String,
     ) -> Result<Message, Error> {
-        let delete = Delete { collection, id };
+        self.send_delete(Delete { collection, id, expected_version: None })
+            .await
+    }
+
+    /// Deletes a document like [`Self::delete`] if it is still at `expected_version`,
+    /// the server answers `DeleteStatus::Conflict` with its current version otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The identifier of the document to be deleted.
+    /// * `collection` - The name of the collection containing the document.
+    /// * `expected_version` - The version read with [`Self::get_with_version`].
+    pub async fn delete_at_version(
+        &mut self,
+        id: String,
+        collection: String,
+        expected_version: u64,
+    ) -> Result<Message, Error> {
+        let expected_version = Some(expected_version);
+        self.send_delete(Delete { collection, id, expected_version }).await
+    }
+
+    async fn send_delete(&mut self, delete: Delete) -> Result<Message, Error> {
         let message = Message::Delete(delete);
-        let message = message.setup_for_network()?;
-        self.write.write_all(&message).await?;
-        let message = parse_message_from_tcp_stream(&mut self.read).await?;
+        self.stream.send(message).await?;
+        let message = self.receive().await?;
+
+        info!("message: {:?}", message);
+        match message {
+            Message::DeleteResult(_) => Ok(message),
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Removes a document from a use case, it is no longer returned by the queries on
+    /// the use case but can still be read by id.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The identifier of the document.
+    /// * `collection` - The name of the collection containing the document.
+    /// * `usecase` - The use case to remove the document from.
+    pub async fn delete_for_usecase(
+        &mut self,
+        id: String,
+        collection: String,
+        usecase: String,
+    ) -> Result<Message, Error> {
+        let message = Message::DeleteForUsecase { collection, usecase, id };
+        self.stream.send(message).await?;
+        let message = self.receive().await?;
 
         info!("message: {:?}", message);
         match message {
             Message::DeleteResult(_) => Ok(message),
-            _ => Err(Error::MessageTypeError(MessageTypeError::default())),
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Drops a collection or a usecase, only allowed to administrators.
+    ///
+    /// The server deletes the keys by batches and reports its progress after each
+    /// one, `on_progress` is called with the number of keys, or of records for a
+    /// usecase, dropped so far. Returns `true` if something was dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - What to drop.
+    /// * `on_progress` - Called with the progress reported by the server.
+    pub async fn drop(
+        &mut self,
+        subject: DropSubject,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<bool, Error> {
+        self.stream.send(Message::Drop(subject)).await?;
+        loop {
+            match self.receive().await? {
+                Message::DropProgress { dropped } => on_progress(dropped),
+                Message::DropResult(dropped) => return Ok(dropped),
+                message => return Err(unexpected_response(message)),
+            }
+        }
+    }
+
+    /// Returns the names of the collections owned by the user, or of every collection
+    /// for an administrator, in order.
+    pub async fn list_collections(&mut self) -> Result<Vec<String>, Error> {
+        self.stream.send(Message::ListCollections).await?;
+        match self.receive().await? {
+            Message::CollectionList(collections) => Ok(collections),
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Returns the metadata of a collection, `None` if it doesn't exist. Only allowed
+    /// to the owner of the collection and to administrators.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The name of the collection.
+    pub async fn describe_collection(
+        &mut self,
+        collection: String,
+    ) -> Result<Option<CollectionInfo>, Error> {
+        self.stream.send(Message::DescribeCollection { collection }).await?;
+        match self.receive().await? {
+            Message::CollectionDescription(info) => Ok(info),
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Keeps the last `versions` previous values of each document of a collection,
+    /// readable with [`Self::get_version`], 0 disables the history. Only allowed to
+    /// the owner of the collection and to administrators.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The name of the collection, which only administrators can
+    ///   create before its first insertion.
+    /// * `versions` - The number of previous versions to keep.
+    pub async fn set_history(
+        &mut self,
+        collection: String,
+        versions: u32,
+    ) -> Result<CollectionInfo, Error> {
+        self.stream.send(Message::SetHistory { collection, versions }).await?;
+        match self.receive().await? {
+            Message::CollectionDescription(Some(info)) => Ok(info),
+            message => Err(unexpected_response(message)),
+        }
+    }
+
+    /// Creates a user, only allowed to administrators.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The name of the new user.
+    /// * `password` - The password of the new user.
+    /// * `admin` - Whether the new user can manage the other users.
+    /// * `groups` - The groups the new user belongs to, used by the ACL of the data.
+    pub async fn create_user(
+        &mut self,
+        username: String,
+        password: String,
+        admin: bool,
+        groups: Vec<String>,
+    ) -> Result<(), Error> {
+        let message = Message::CreateUser(NewUser { username, password, admin, groups });
+        self.send_user_request(message).await
+    }
+
+    /// Deletes a user, only allowed to administrators.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The name of the user to delete.
+    pub async fn delete_user(&mut self, username: String) -> Result<(), Error> {
+        self.send_user_request(Message::DeleteUser { username }).await
+    }
+
+    /// Changes the password of a user.
+    ///
+    /// Users can change their own password by giving the current one, administrators
+    /// can reset the password of any user.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The name of the user whose password changes.
+    /// * `current_password` - The current password, when changing your own password.
+    /// * `new_password` - The new password.
+    pub async fn change_password(
+        &mut self,
+        username: String,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), Error> {
+        let change = PasswordChange { username, current_password, new_password };
+        self.send_user_request(Message::ChangePassword(change)).await
+    }
+
+    async fn send_user_request(&mut self, message: Message) -> Result<(), Error> {
+        self.stream.send(message).await?;
+        match self.receive().await? {
+            Message::UserResponse { username } => {
+                info!("user {} updated", username);
+                Ok(())
+            }
+            message => Err(unexpected_response(message)),
         }
     }
 }
 
-/// Parses a message from a TCP stream.
-///
-/// # Arguments
-///
-/// * `stream` - A mutable reference to the read half of a TCP stream.
-///
-/// # Returns
-///
-/// * `Result<Message, Error>` - The parsed message, or an error if parsing fails.
-pub async fn parse_message_from_tcp_stream(
-    stream: &mut OwnedReadHalf,
-) -> Result<Message, Error> {
-    let mut buffer = [0; 1];
-    let _ = stream.read(&mut buffer).await;
-    let message_type = MessageType::try_from(buffer[0]);
-    info!("messageType: {:?}", message_type);
-
-    let mut message_size = [0; 4];
-    let _size_error = stream.read(&mut message_size).await;
-    let decimal_size = u32::from_be_bytes(message_size);
-    trace!("message size: {}", decimal_size);
-
-    let mut slice = vec![0; decimal_size as usize];
-    let _size_read = stream.read_exact(&mut slice).await;
-    trace!("slice: {:?}", slice);
-    let message: Message = serde_cbor::from_slice(&slice)?;
-    debug!("parsed message: {:#?}", message);
-    Ok(message)
+/// Converts a response the client did not expect into an error, keeping the error
+/// reported by the server if there is one.
+fn unexpected_response(message: Message) -> Error {
+    match message {
+        Message::Error { code, message, request_id } => {
+            Error::Server(ServerError { code, message, request_id })
+        }
+        _ => Error::MessageTypeError(MessageTypeError::default()),
+    }
 }
 
 fn convert_to_array12(slice: &Vec<u8>) -> Option<&[u8; 12]> {
@@ -0,0 +1,166 @@
+//! Verification of the certificate presented by the server during the handshake.
+
+use chrono::{DateTime, Utc};
+use liserk_shared::{certificate::Certificate, session::CipherSuite};
+use pqcrypto_falcon::falcon512;
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _};
+
+use crate::error::CertificateError;
+
+/// Certificate authority trusted by the client and identity expected from the server.
+#[derive(Clone)]
+pub struct TrustAnchor {
+    authority_key: falcon512::PublicKey,
+    server_identity: String,
+}
+
+impl TrustAnchor {
+    /// Creates a trust anchor from the Falcon public key of the certificate authority.
+    ///
+    /// # Arguments
+    ///
+    /// * `authority_key` - The Falcon-512 public key of the certificate authority.
+    /// * `server_identity` - The `identity_info` the certificate of the server must have.
+    pub fn new(
+        authority_key: &[u8],
+        server_identity: impl Into<String>,
+    ) -> Result<Self, CertificateError> {
+        let authority_key = falcon512::PublicKey::from_bytes(authority_key)
+            .map_err(|_| CertificateError::InvalidAuthorityKey)?;
+        Ok(Self {
+            authority_key,
+            server_identity: server_identity.into(),
+        })
+    }
+
+    /// Checks that `certificate` is signed by the authority, valid at `now`, issued to
+    /// the expected server and allows the negotiated cipher suite.
+    pub fn verify(
+        &self,
+        certificate: &Certificate,
+        cipher_suit: CipherSuite,
+        now: DateTime<Utc>,
+    ) -> Result<(), CertificateError> {
+        let signature = falcon512::DetachedSignature::from_bytes(&certificate.signature)
+            .map_err(|_| CertificateError::BadSignature)?;
+        falcon512::verify_detached_signature(
+            &signature,
+            &certificate.signed_content(),
+            &self.authority_key,
+        )
+        .map_err(|_| CertificateError::BadSignature)?;
+
+        if now < certificate.valid_from {
+            return Err(CertificateError::NotYetValid(certificate.valid_from));
+        }
+        if now > certificate.valid_to {
+            return Err(CertificateError::Expired(certificate.valid_to));
+        }
+        if certificate.identity_info != self.server_identity {
+            return Err(CertificateError::IdentityMismatch {
+                expected: self.server_identity.clone(),
+                actual: certificate.identity_info.clone(),
+            });
+        }
+        if !certificate
+            .cipher_suits
+            .iter()
+            .any(|suite| suite == cipher_suit.name())
+        {
+            return Err(CertificateError::CipherSuiteNotAllowed(cipher_suit.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for TrustAnchor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrustAnchor")
+            .field("server_identity", &self.server_identity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    use super::*;
+
+    const SUITE: CipherSuite = CipherSuite::Kyber768Aes256GcmSiv;
+
+    fn signed_certificate(secret_key: &falcon512::SecretKey) -> Certificate {
+        let now = Utc::now();
+        let mut certificate = Certificate {
+            public_key: vec![1, 2, 3],
+            identity_info: String::from("Server"),
+            issuer_info: String::from("Test authority"),
+            signature: vec![],
+            valid_from: now - Duration::hours(1),
+            valid_to: now + Duration::hours(1),
+            serial_number: Uuid::new_v4(),
+            cipher_suits: vec![SUITE.to_string()],
+        };
+        let signature =
+            falcon512::detached_sign(&certificate.signed_content(), secret_key);
+        certificate.signature = signature.as_bytes().to_vec();
+        certificate
+    }
+
+    #[test]
+    fn test_verify_certificate() {
+        let (public_key, secret_key) = falcon512::keypair();
+        let anchor = TrustAnchor::new(public_key.as_bytes(), "Server").unwrap();
+        let certificate = signed_certificate(&secret_key);
+        let now = Utc::now();
+        assert!(anchor.verify(&certificate, SUITE, now).is_ok());
+
+        let tampered = Certificate { public_key: vec![4, 5, 6], ..certificate.clone() };
+        assert!(matches!(
+            anchor.verify(&tampered, SUITE, now),
+            Err(CertificateError::BadSignature)
+        ));
+        assert!(matches!(
+            anchor.verify(&certificate, SUITE, now + Duration::days(1)),
+            Err(CertificateError::Expired(_))
+        ));
+        assert!(matches!(
+            anchor.verify(&certificate, SUITE, now - Duration::days(1)),
+            Err(CertificateError::NotYetValid(_))
+        ));
+
+        let other = TrustAnchor::new(public_key.as_bytes(), "Other").unwrap();
+        assert!(matches!(
+            other.verify(&certificate, SUITE, now),
+            Err(CertificateError::IdentityMismatch { .. })
+        ));
+
+        let (other_key, _) = falcon512::keypair();
+        let other = TrustAnchor::new(other_key.as_bytes(), "Server").unwrap();
+        assert!(matches!(
+            other.verify(&certificate, SUITE, now),
+            Err(CertificateError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn test_reject_suite_missing_from_certificate() {
+        let (public_key, secret_key) = falcon512::keypair();
+        let anchor = TrustAnchor::new(public_key.as_bytes(), "Server").unwrap();
+        let mut certificate = signed_certificate(&secret_key);
+        certificate.cipher_suits = vec![String::from("rot13")];
+        let signature =
+            falcon512::detached_sign(&certificate.signed_content(), &secret_key);
+        certificate.signature = signature.as_bytes().to_vec();
+
+        assert!(matches!(
+            anchor.verify(&certificate, SUITE, Utc::now()),
+            Err(CertificateError::CipherSuiteNotAllowed(_))
+        ));
+        assert!(matches!(
+            TrustAnchor::new(&[], "Server"),
+            Err(CertificateError::InvalidAuthorityKey)
+        ));
+    }
+}
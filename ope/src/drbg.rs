@@ -0,0 +1,95 @@
+//! HMAC-DRBG with SHA-256, as specified in NIST SP 800-90A, used as the tape of
+//! random coins of [`Ope`](crate::ope::Ope).
+//!
+//! The generator is deterministic: the same seed always gives the same coins, which
+//! is what lets the decryption replay the samplings of the encryption.
+
+use hmac::Mac;
+
+use crate::ope::HmacSha256;
+
+const OUTPUT_LEN: usize = 32;
+
+/// Unbounded stream of pseudorandom bits, most significant bit of each byte first.
+pub struct HmacDrbg {
+    key: [u8; OUTPUT_LEN],
+    value: [u8; OUTPUT_LEN],
+    block: [u8; OUTPUT_LEN],
+    /// Index of the next bit of `block` to return.
+    position: usize,
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; OUTPUT_LEN] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+impl HmacDrbg {
+    /// Instantiates the generator from `seed`, without nonce nor personalization.
+    pub fn new(seed: &[u8]) -> Self {
+        let mut drbg = Self {
+            key: [0x00; OUTPUT_LEN],
+            value: [0x01; OUTPUT_LEN],
+            block: [0; OUTPUT_LEN],
+            position: OUTPUT_LEN * 8,
+        };
+        drbg.update(seed);
+        drbg
+    }
+
+    fn update(&mut self, provided_data: &[u8]) {
+        self.key = hmac(&self.key, &[&self.value, &[0x00], provided_data]);
+        self.value = hmac(&self.key, &[&self.value]);
+        if !provided_data.is_empty() {
+            self.key = hmac(&self.key, &[&self.value, &[0x01], provided_data]);
+            self.value = hmac(&self.key, &[&self.value]);
+        }
+    }
+
+    /// Generates the next block of output, each block is a request of its own.
+    fn generate(&mut self) {
+        self.value = hmac(&self.key, &[&self.value]);
+        self.block = self.value;
+        self.update(&[]);
+        self.position = 0;
+    }
+}
+
+impl Iterator for HmacDrbg {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        if self.position == OUTPUT_LEN * 8 {
+            self.generate();
+        }
+        let byte = self.block[self.position / 8];
+        let bit = (byte >> (7 - self.position % 8)) & 1 == 1;
+        self.position += 1;
+        Some(bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(drbg: &mut HmacDrbg, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| drbg.by_ref().take(8).fold(0, |byte, bit| byte << 1 | bit as u8))
+            .collect()
+    }
+
+    #[test]
+    fn test_deterministic_and_unbounded() {
+        let first = bytes(&mut HmacDrbg::new(b"seed"), 1000);
+        assert_eq!(first, bytes(&mut HmacDrbg::new(b"seed"), 1000));
+        assert_ne!(first, bytes(&mut HmacDrbg::new(b"other seed"), 1000));
+        // Consecutive blocks differ.
+        assert_ne!(first[..OUTPUT_LEN], first[OUTPUT_LEN..2 * OUTPUT_LEN]);
+        let ones = first.iter().map(|byte| byte.count_ones()).sum::<u32>();
+        assert!((3600..4400).contains(&ones));
+    }
+}
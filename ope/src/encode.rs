@@ -0,0 +1,370 @@
+//! Order preserving encoders of values into the plaintexts of [`Ope`].
+//!
+//! [`Ope`] only encrypts integers, the encoders map floats, signed integers, dates,
+//! timestamps and strings to integers without changing their order. Values that are
+//! too close to be told apart by an encoder, e.g. floats rounded to the same
+//! fixed-point number, get the same plaintext.
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use rug::integer::Order;
+use rug::Integer;
+
+use crate::ope::{Ope, OpeError, OpePlaintext, ValueRange};
+
+/// Order preserving mapping of values to the plaintexts of an [`Ope`].
+pub trait OpeEncode {
+    type Value;
+    type Plaintext: OpePlaintext;
+
+    /// Range of the plaintexts, `None` for the default range of the type.
+    fn in_range(&self) -> Option<ValueRange<Self::Plaintext>> {
+        None
+    }
+
+    /// Fails with [`OpeError::OutOfRangeError`] if `value` can't be encoded.
+    fn encode(&self, value: &Self::Value) -> Result<Self::Plaintext, OpeError>;
+
+    /// Value of `plaintext`, which is not a round trip of [`Self::encode`] for the
+    /// lossy encoders: it is the value rounded by [`FixedPoint`] or truncated by
+    /// [`StringPrefix`].
+    ///
+    /// Fails with [`OpeError::InvalidCiphertextError`] if `plaintext` is not the
+    /// encoding of a value.
+    fn decode(&self, plaintext: Self::Plaintext) -> Result<Self::Value, OpeError>;
+}
+
+/// Floats as fixed-point numbers with `scale` decimal digits.
+///
+/// The floats are rounded to `scale` digits, so the rounded float is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedPoint {
+    pub scale: u32,
+}
+
+impl FixedPoint {
+    fn factor(&self) -> f64 {
+        10f64.powi(self.scale as i32)
+    }
+}
+
+impl OpeEncode for FixedPoint {
+    type Value = f64;
+    type Plaintext = i64;
+
+    fn encode(&self, value: &f64) -> Result<i64, OpeError> {
+        let scaled = (value * self.factor()).round();
+        // i64::MAX is not a float, its successor 2^63 is.
+        if !scaled.is_finite() || scaled < i64::MIN as f64 || scaled >= i64::MAX as f64 {
+            return Err(OpeError::OutOfRangeError);
+        }
+        Ok(scaled as i64)
+    }
+
+    fn decode(&self, plaintext: i64) -> Result<f64, OpeError> {
+        Ok(plaintext as f64 / self.factor())
+    }
+}
+
+/// Signed integers shifted by 2^63 into the unsigned integers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignedOffset;
+
+impl OpeEncode for SignedOffset {
+    type Value = i64;
+    type Plaintext = u64;
+
+    fn encode(&self, value: &i64) -> Result<u64, OpeError> {
+        Ok((*value as u64) ^ (1 << 63))
+    }
+
+    fn decode(&self, plaintext: u64) -> Result<i64, OpeError> {
+        Ok((plaintext ^ (1 << 63)) as i64)
+    }
+}
+
+/// Dates as their number of days since the first day of the common era.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateEncoder;
+
+impl OpeEncode for DateEncoder {
+    type Value = NaiveDate;
+    type Plaintext = i64;
+
+    fn in_range(&self) -> Option<ValueRange<i64>> {
+        Some(ValueRange {
+            start: NaiveDate::MIN.num_days_from_ce().into(),
+            end: NaiveDate::MAX.num_days_from_ce().into(),
+        })
+    }
+
+    fn encode(&self, value: &NaiveDate) -> Result<i64, OpeError> {
+        Ok(value.num_days_from_ce().into())
+    }
+
+    fn decode(&self, plaintext: i64) -> Result<NaiveDate, OpeError> {
+        i32::try_from(plaintext)
+            .ok()
+            .and_then(NaiveDate::from_num_days_from_ce_opt)
+            .ok_or(OpeError::InvalidCiphertextError)
+    }
+}
+
+/// Timestamps as their number of microseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimestampEncoder;
+
+impl OpeEncode for TimestampEncoder {
+    type Value = DateTime<Utc>;
+    type Plaintext = i64;
+
+    fn encode(&self, value: &DateTime<Utc>) -> Result<i64, OpeError> {
+        Ok(value.timestamp_micros())
+    }
+
+    fn decode(&self, plaintext: i64) -> Result<DateTime<Utc>, OpeError> {
+        let seconds = plaintext.div_euclid(1_000_000);
+        let nanoseconds = plaintext.rem_euclid(1_000_000) as u32 * 1000;
+        Utc.timestamp_opt(seconds, nanoseconds)
+            .single()
+            .ok_or(OpeError::InvalidCiphertextError)
+    }
+}
+
+/// Strings as the big-endian number made of their first `len` bytes, padded with
+/// zeros.
+///
+/// The bytes of UTF-8 compare like the code points they encode, so the encoding
+/// keeps the lexicographic order of the strings. Strings sharing their first `len`
+/// bytes get the same plaintext, and only that prefix is decoded, so the decoding
+/// is not a round trip for longer strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringPrefix {
+    pub len: usize,
+}
+
+impl OpeEncode for StringPrefix {
+    type Value = String;
+    type Plaintext = Integer;
+
+    fn in_range(&self) -> Option<ValueRange<Integer>> {
+        let end = (Integer::from(1) << (8 * self.len as u32)) - 1u32;
+        Some(ValueRange { start: Integer::new(), end })
+    }
+
+    fn encode(&self, value: &String) -> Result<Integer, OpeError> {
+        if self.len == 0 {
+            return Err(OpeError::OutOfRangeError);
+        }
+        let mut prefix =
+            value.as_bytes().iter().take(self.len).copied().collect::<Vec<_>>();
+        prefix.resize(self.len, 0);
+        Ok(Integer::from_digits(&prefix, Order::Msf))
+    }
+
+    fn decode(&self, plaintext: Integer) -> Result<String, OpeError> {
+        if plaintext < 0 || plaintext.significant_bits() as usize > 8 * self.len {
+            return Err(OpeError::InvalidCiphertextError);
+        }
+        let digits = plaintext.to_digits::<u8>(Order::Msf);
+        let mut prefix = vec![0; self.len - digits.len()];
+        prefix.extend(digits);
+        while prefix.last() == Some(&0) {
+            prefix.pop();
+        }
+        // The prefix may end in the middle of a character.
+        Ok(String::from_utf8_lossy(&prefix).into_owned())
+    }
+}
+
+/// Encoder whose plaintexts can also be encrypted with [`Ore`](crate::ore::Ore).
+pub trait OreEncode: OpeEncode {
+    /// Big-endian bytes of `plaintext`, of the same length for every plaintext, which
+    /// compare like the plaintexts.
+    fn ore_bytes(&self, plaintext: &Self::Plaintext) -> Vec<u8>;
+}
+
+/// Bytes of a signed plaintext, shifted like [`SignedOffset`] so they compare like
+/// it.
+fn signed_ore_bytes(plaintext: &i64) -> Vec<u8> {
+    ((*plaintext as u64) ^ (1 << 63)).to_be_bytes().to_vec()
+}
+
+impl OreEncode for FixedPoint {
+    fn ore_bytes(&self, plaintext: &i64) -> Vec<u8> {
+        signed_ore_bytes(plaintext)
+    }
+}
+
+impl OreEncode for SignedOffset {
+    fn ore_bytes(&self, plaintext: &u64) -> Vec<u8> {
+        plaintext.to_be_bytes().to_vec()
+    }
+}
+
+impl OreEncode for DateEncoder {
+    fn ore_bytes(&self, plaintext: &i64) -> Vec<u8> {
+        signed_ore_bytes(plaintext)
+    }
+}
+
+impl OreEncode for TimestampEncoder {
+    fn ore_bytes(&self, plaintext: &i64) -> Vec<u8> {
+        signed_ore_bytes(plaintext)
+    }
+}
+
+impl OreEncode for StringPrefix {
+    fn ore_bytes(&self, plaintext: &Integer) -> Vec<u8> {
+        let digits = plaintext.to_digits::<u8>(Order::Msf);
+        let padding = self.len.saturating_sub(digits.len());
+        [vec![0; padding], digits].concat()
+    }
+}
+
+/// [`Ope`] of the values of an encoder.
+pub struct EncodedOpe<E: OpeEncode> {
+    pub encoder: E,
+    pub ope: Ope<E::Plaintext>,
+}
+
+/// Ciphertext of the values of an encoder.
+pub type Ciphertext<E> = <<E as OpeEncode>::Plaintext as OpePlaintext>::Ciphertext;
+
+impl<E: OpeEncode> EncodedOpe<E> {
+    /// Creates the [`Ope`] of the plaintexts of `encoder`, with the default output
+    /// range.
+    pub fn new(key: &[u8], encoder: E) -> Result<Self, OpeError> {
+        let ope = Ope::new(key, encoder.in_range(), None)?;
+        Ok(Self { encoder, ope })
+    }
+
+    pub fn encrypt(&self, value: &E::Value) -> Result<Ciphertext<E>, OpeError> {
+        self.ope.encrypt(self.encoder.encode(value)?)
+    }
+
+    /// Decrypts `ciphertext` and decodes its plaintext, which may be lossy, see
+    /// [`OpeEncode::decode`].
+    pub fn decrypt(&self, ciphertext: Ciphertext<E>) -> Result<E::Value, OpeError> {
+        self.encoder.decode(self.ope.decrypt(ciphertext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_order_preserved<E: OpeEncode>(encoder: &E, sorted_values: &[E::Value]) {
+        let plaintexts = sorted_values
+            .iter()
+            .map(|value| encoder.encode(value).unwrap())
+            .collect::<Vec<_>>();
+        assert!(plaintexts.windows(2).all(|pair| pair[0] <= pair[1]));
+        if let Some(in_range) = encoder.in_range() {
+            assert!(plaintexts.iter().all(|plaintext| in_range.contains(plaintext)));
+        }
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        let encoder = FixedPoint { scale: 2 };
+        assert_order_preserved(
+            &encoder,
+            &[-1e15, -2.5, -0.004, 0.0, 0.014, 1.23456, 1e15],
+        );
+        assert_eq!(encoder.encode(&-2.5), Ok(-250));
+        assert_eq!(encoder.decode(123), Ok(1.23));
+        for value in [f64::NAN, f64::INFINITY, 1e17] {
+            assert_eq!(encoder.encode(&value), Err(OpeError::OutOfRangeError));
+        }
+    }
+
+    #[test]
+    fn test_signed_offset() {
+        let values = [i64::MIN, -1, 0, 1, i64::MAX];
+        assert_order_preserved(&SignedOffset, &values);
+        assert_eq!(SignedOffset.encode(&i64::MIN), Ok(0));
+        assert_eq!(SignedOffset.encode(&i64::MAX), Ok(u64::MAX));
+        for value in values {
+            assert_eq!(
+                SignedOffset.decode(SignedOffset.encode(&value).unwrap()),
+                Ok(value)
+            );
+        }
+    }
+
+    #[test]
+    fn test_dates() {
+        let dates = [
+            NaiveDate::MIN,
+            NaiveDate::from_ymd_opt(-44, 3, 15).unwrap(),
+            NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            NaiveDate::MAX,
+        ];
+        assert_order_preserved(&DateEncoder, &dates);
+        for date in dates {
+            assert_eq!(DateEncoder.decode(DateEncoder.encode(&date).unwrap()), Ok(date));
+        }
+
+        let timestamps = [
+            Utc.timestamp_opt(-1, 999_999_000).unwrap(),
+            Utc.timestamp_opt(0, 0).unwrap(),
+            Utc.timestamp_opt(0, 1000).unwrap(),
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        ];
+        assert_order_preserved(&TimestampEncoder, &timestamps);
+        for timestamp in timestamps {
+            let plaintext = TimestampEncoder.encode(&timestamp).unwrap();
+            assert_eq!(TimestampEncoder.decode(plaintext), Ok(timestamp));
+        }
+    }
+
+    #[test]
+    fn test_string_prefix() {
+        let encoder = StringPrefix { len: 4 };
+        let strings =
+            ["", "a", "ab", "abcd", "abcde", "b", "z", "é", "日本"].map(String::from);
+        assert_order_preserved(&encoder, &strings);
+        assert_eq!(encoder.encode(&"abcd".into()), encoder.encode(&"abcde".into()));
+        assert_eq!(
+            encoder.decode(encoder.encode(&"ab".into()).unwrap()),
+            Ok("ab".into())
+        );
+        assert_eq!(
+            encoder.decode(encoder.encode(&"abcde".into()).unwrap()),
+            Ok("abcd".into())
+        );
+        assert_eq!(
+            StringPrefix { len: 0 }.encode(&"a".into()),
+            Err(OpeError::OutOfRangeError)
+        );
+    }
+
+    #[test]
+    fn test_ore_bytes() {
+        let ore_bytes = |encoder: &FixedPoint, value: f64| {
+            encoder.ore_bytes(&encoder.encode(&value).unwrap())
+        };
+        let encoder = FixedPoint { scale: 0 };
+        assert!(ore_bytes(&encoder, -1.0) < ore_bytes(&encoder, 0.0));
+        assert!(ore_bytes(&encoder, 0.0) < ore_bytes(&encoder, 1.0));
+
+        let encoder = StringPrefix { len: 3 };
+        let bytes = encoder.ore_bytes(&encoder.encode(&"a".into()).unwrap());
+        assert_eq!(bytes, b"a\0\0");
+        assert_eq!(encoder.ore_bytes(&Integer::new()), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encoded_ope() {
+        let ope = EncodedOpe::new(b"test_key", StringPrefix { len: 2 }).unwrap();
+        let words = ["apple", "banana", "cherry"].map(String::from);
+        let ciphertexts = words
+            .iter()
+            .map(|word| ope.encrypt(word).unwrap())
+            .collect::<Vec<_>>();
+        assert!(ciphertexts.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ope.decrypt(ciphertexts[1].clone()), Ok("ba".into()));
+    }
+}
@@ -1,30 +1,42 @@
+//! Hypergeometric sampling driven by a tape of coins, with the HYP and HRUA
+//! algorithms used by numpy.
+//!
+//! The computations are done on `rug` floats whose precision grows with the
+//! population, so the samples stay exact for populations far larger than what an
+//! `f64` can count.
+
 use std::f64;
 use std::iter::Iterator;
 
+use rug::{Float, Integer};
+
+/// Bits of precision used on top of the size of the population.
+const EXTRA_PRECISION: u32 = 64;
+
 pub struct PRNG {
     pub coins: Box<dyn Iterator<Item = bool>>,
+    precision: u32,
 }
 
 impl PRNG {
-    pub fn new(coins: Box<dyn Iterator<Item = bool>>) -> PRNG {
-        PRNG { coins }
+    /// Draws floats of `precision` bits from `coins`.
+    pub fn new(coins: Box<dyn Iterator<Item = bool>>, precision: u32) -> PRNG {
+        PRNG { coins, precision }
     }
 
-    pub fn draw(&mut self) -> f64 {
-        let mut bits = Vec::new();
-        for _ in 0..32 {
-            bits.push(self.coins.next().unwrap());
-        }
-        assert_eq!(bits.len(), 32);
-
-        let mut out = 0;
-        for &b in &bits {
-            out = (out << 1) | (b as u32);
+    /// Uniform float of [0, 1] made of `precision` coins.
+    pub fn draw(&mut self) -> Float {
+        let mut out = Integer::new();
+        for _ in 0..self.precision {
+            let coin = self.coins.next().expect("Not enough coins");
+            out = (out << 1u32) + u32::from(coin);
         }
+        let max = (Integer::from(1) << self.precision) - 1u32;
+        Float::with_val(self.precision, &out) / Float::with_val(self.precision, &max)
+    }
 
-        let res = out as f64 / ((1u64 << 32) - 1) as f64;
-        assert!(res >= 0.0 && res <= 1.0);
-        res
+    fn float(&self, integer: &Integer) -> Float {
+        Float::with_val(self.precision, integer)
     }
 }
 
@@ -43,142 +55,150 @@ pub fn afc(i: i32) -> f64 {
     (i + 0.5) * i.ln() - i + frac_12 / i - frac_360 / (i * i * i) + frac_pi
 }
 
-fn loggam(x: f64) -> f64 {
-    let a = [
-        8.333333333333333e-02,
-        -2.777777777777778e-03,
-        7.936507936507937e-04,
-        -5.952380952380952e-04,
-        8.417508417508418e-04,
-        -1.917526917526918e-03,
-        6.410256410256410e-03,
-        -2.955065359477124e-02,
-        1.796443723688307e-01,
-        -1.39243221690590,
-    ];
-
-    let mut x0 = x;
-    let mut n = 0;
-
-    if x0 == 1.0 || x0 == 2.0 {
-        return 0.0;
-    } else if x0 <= 7.0 {
-        n = (7.0 - x0) as i32;
-        x0 += n as f64;
-    }
-
-    let x2 = 1.0 / (x0 * x0);
-    let xp = 2.0 * f64::consts::PI;
-
-    let mut gl0 = a[9];
-    for k in (0..=8).rev() {
-        gl0 = gl0 * x2 + a[k];
-    }
-
-    let mut gl = gl0 / x0 + 0.5 * xp.ln() + (x0 - 0.5) * x0.ln() - x0;
-    if x <= 7.0 {
-        for _k in 1..=n {
-            gl -= (x0 - 1.0).ln();
-            x0 -= 1.0;
-        }
-    }
-    gl
-}
-
-pub fn hypergeometric_hyp(prng: &mut PRNG, good: f64, bad: f64, sample: f64) -> i32 {
-    let d1 = bad + good - sample;
-    let d2 = f64::min(bad, good);
-
-    let mut y = d2;
-    let mut k = sample;
-    while y > 0.0 {
+pub fn hypergeometric_hyp(
+    prng: &mut PRNG,
+    good: &Integer,
+    bad: &Integer,
+    sample: &Integer,
+) -> Integer {
+    let d1 = prng.float(&(Integer::from(bad + good) - sample));
+    let d2 = prng.float(good.min(bad));
+
+    let mut y = d2.clone();
+    let mut k = sample.clone();
+    while y > 0 {
         let u = prng.draw();
-        y -= (u + y / (d1 + k)).floor();
-        k -= 1.0;
-        if k == 0.0 {
+        y -= (u + y.clone() / (d1.clone() + prng.float(&k))).floor();
+        k -= 1;
+        if k == 0 {
             break;
         }
     }
 
-    let mut z = (d2 - y) as i32;
+    let mut z = (d2 - y).to_integer().expect("samples are finite");
     if good > bad {
-        z = sample as i32 - z;
+        z = Integer::from(sample - &z);
     }
     z
 }
 
-pub fn hypergeometric_hrua(prng: &mut PRNG, good: f64, bad: f64, sample: f64) -> i32 {
+pub fn hypergeometric_hrua(
+    prng: &mut PRNG,
+    good: &Integer,
+    bad: &Integer,
+    sample: &Integer,
+) -> Integer {
     const D1: f64 = 1.7155277699214135;
     const D2: f64 = 0.8989161620588988;
 
-    let mingoodbad = good.min(bad);
-    let popsize = good + bad;
-    let maxgoodbad = good.max(bad);
-    let m = sample.min(popsize - sample);
-    let d4 = mingoodbad / popsize;
-    let d5 = 1.0 - d4;
-    let d6 = m * d4 + 0.5;
-    let d7 = ((popsize - m) * sample * d4 * d5 / (popsize - 1.0) + 0.5).sqrt();
-    let d8 = D1 * d7 + D2;
-    let d9 = ((m + 1.0) * (mingoodbad + 1.0) / (popsize + 2.0)).floor() as i32;
-    let d10 = loggam(d9 as f64 + 1.0)
-        + loggam(mingoodbad - d9 as f64 + 1.0)
-        + loggam(m - d9 as f64 + 1.0)
-        + loggam(maxgoodbad - m + d9 as f64 + 1.0);
-    let d11 = m.min(mingoodbad) + 1.0_f64.min((d6 + 16.0 * d7).floor());
-
-    loop {
+    let mingoodbad = good.min(bad).clone();
+    let maxgoodbad = good.max(bad).clone();
+    let popsize = Integer::from(good + bad);
+    let m = sample.clone().min(Integer::from(&popsize - sample));
+    let (mingoodbad_f, maxgoodbad_f) = (prng.float(&mingoodbad), prng.float(&maxgoodbad));
+    let (popsize_f, m_f) = (prng.float(&popsize), prng.float(&m));
+
+    let d4 = mingoodbad_f.clone() / &popsize_f;
+    let d5 = 1.0 - d4.clone();
+    let d6 = m_f.clone() * &d4 + 0.5;
+    let d7 = ((popsize_f.clone() - &m_f) * prng.float(sample) * d4 * d5
+        / (popsize_f.clone() - 1.0)
+        + 0.5)
+        .sqrt();
+    let d8 = d7.clone() * D1 + D2;
+    let d9 =
+        ((m_f.clone() + 1.0) * (mingoodbad_f.clone() + 1.0) / (popsize_f + 2.0)).floor();
+    let d10 = (d9.clone() + 1.0).ln_gamma()
+        + (mingoodbad_f.clone() - &d9 + 1.0).ln_gamma()
+        + (m_f.clone() - &d9 + 1.0).ln_gamma()
+        + (maxgoodbad_f.clone() - &m_f + &d9 + 1.0).ln_gamma();
+    let d11 = (prng.float(&m.clone().min(mingoodbad.clone())) + 1.0)
+        .min(&(d6.clone() + d7 * 16.0).floor());
+
+    let z = loop {
         let x = prng.draw();
         let y = prng.draw();
-        let w = d6 + d8 * (y - 0.5) / x;
+        if x.is_zero() {
+            continue;
+        }
+        let w = d6.clone() + d8.clone() * (y - 0.5) / &x;
 
-        if w < 0.0 || w >= d11 {
+        if w < 0 || w >= d11 {
             continue;
         }
 
-        let z = w.floor() as i32;
-        let t = d10
-            - (loggam(z as f64 + 1.0)
-                + loggam(mingoodbad - z as f64 + 1.0)
-                + loggam(m - z as f64 + 1.0)
-                + loggam(maxgoodbad - m + z as f64 + 1.0));
-
-        if (x * (4.0 - x) - 3.0) <= t {
-            let mut result = z;
-            if good > bad {
-                result = m as i32 - result;
-            }
-            if m < sample {
-                result = good as i32 - result;
-            }
-            return result;
+        let z = w.floor();
+        let t = d10.clone()
+            - ((z.clone() + 1.0).ln_gamma()
+                + (mingoodbad_f.clone() - &z + 1.0).ln_gamma()
+                + (m_f.clone() - &z + 1.0).ln_gamma()
+                + (maxgoodbad_f.clone() - &m_f + &z + 1.0).ln_gamma());
+
+        if x.clone() * (4.0 - x.clone()) - 3.0 <= t {
+            break z;
         }
 
-        if x * (x - t) >= 1.0 {
+        if x.clone() * (x.clone() - &t) >= 1.0 {
             continue;
         }
 
-        if 2.0 * x.ln() <= t {
-            let mut result = z;
-            if good > bad {
-                result = m as i32 - result;
-            }
-            if m < sample {
-                result = good as i32 - result;
-            }
-            return result;
+        if x.ln() * 2.0 <= t {
+            break z;
         }
+    };
+
+    let mut result = z.to_integer().expect("samples are finite");
+    if good > bad {
+        result = m.clone() - result;
     }
+    if m < *sample {
+        result = good.clone() - result;
+    }
+    result
 }
 
 pub type Coins = Box<dyn Iterator<Item = bool> + 'static>;
 
-pub fn rhyper(kk: i32, nn1: f64, nn2: f64, coins: Coins) -> i32 {
-    let mut prng = PRNG::new(coins);
+/// Number of good balls among `kk` balls drawn without replacement from an urn of
+/// `nn1` good and `nn2` bad balls.
+pub fn rhyper(kk: &Integer, nn1: &Integer, nn2: &Integer, coins: Coins) -> Integer {
+    let popsize = Integer::from(nn1 + nn2);
+    let mut prng = PRNG::new(coins, popsize.significant_bits() + EXTRA_PRECISION);
 
-    if kk > 10 {
-        hypergeometric_hrua(&mut prng, nn1, nn2, kk as f64)
+    if *kk > 10 {
+        hypergeometric_hrua(&mut prng, nn1, nn2, kk)
     } else {
-        hypergeometric_hyp(&mut prng, nn1, nn2, kk as f64)
+        hypergeometric_hyp(&mut prng, nn1, nn2, kk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drbg::HmacDrbg;
+
+    #[test]
+    fn test_rhyper_large_population() {
+        // Half of an urn of 2^128 balls, 2^64 of them good: about 2^63 good balls are
+        // drawn, with a standard deviation of 2^31.
+        let good = Integer::from(1) << 64u32;
+        let bad = (Integer::from(1) << 128u32) - &good;
+        let sample = Integer::from(1) << 127u32;
+        let expected = Integer::from(1) << 63u32;
+        let tolerance = Integer::from(1) << 36u32;
+        for seed in 0u8..8 {
+            let coins = Box::new(HmacDrbg::new(&[seed]));
+            let drawn = rhyper(&sample, &good, &bad, coins);
+            let deviation = (drawn - &expected).abs();
+            assert!(deviation < tolerance, "deviation {}", deviation);
+        }
+    }
+
+    #[test]
+    fn test_rhyper_small_sample() {
+        let coins = Box::new(HmacDrbg::new(b"small"));
+        let drawn =
+            rhyper(&Integer::from(5), &Integer::from(3), &Integer::from(20), coins);
+        assert!((0..=3).contains(&drawn));
     }
 }
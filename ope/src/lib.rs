@@ -1,12 +1,19 @@
+pub mod drbg;
+pub mod encode;
 pub mod hgd;
 pub mod ope;
+pub mod ore;
 pub mod simplified_version;
 pub mod stats;
 pub mod utils;
 
 #[cfg(test)]
 mod tests {
-    use crate::ope::{Ope, ValueRange};
+    use crate::ope::{
+        Ope, OpeError, ValueRange, DEFAULT_IN_RANGE_END, DEFAULT_IN_RANGE_START,
+        DEFAULT_OUT_RANGE_END,
+    };
+    use rug::Integer;
 
     #[test]
     fn test_value_range_new() {
@@ -26,8 +33,8 @@ mod tests {
     #[test]
     fn test_value_range_contains() {
         let range = ValueRange::new(3, 5).unwrap();
-        assert!(range.contains(4));
-        assert!(!range.contains(6));
+        assert!(range.contains(&4));
+        assert!(!range.contains(&6));
     }
 
     #[test]
@@ -35,7 +42,7 @@ mod tests {
     fn test_ope_new() {
         let key = b"test_key";
         assert!(Ope::new(key, Some(ValueRange::new(5, 3).unwrap()), None).is_err());
-        assert!(Ope::new(key, None, None).is_err());
+        assert!(Ope::<i32>::new(key, None, None).is_err());
     }
 
     #[test]
@@ -45,7 +52,7 @@ mod tests {
 
         let plaintext = 5;
         let ciphertext = ope.encrypt(plaintext).unwrap();
-        assert!(ope.out_range.contains(ciphertext));
+        assert!(ope.out_range.contains(&ciphertext));
 
         let decrypted = ope.decrypt(ciphertext).unwrap();
         assert_eq!(plaintext, decrypted);
@@ -75,4 +82,109 @@ mod tests {
             assert_eq!(original_ciphertext, ciphertexts[index]);
         }
     }
+
+    #[test]
+    fn test_ope_depends_on_key() {
+        let encrypt_all = |key: &[u8]| {
+            let in_range = ValueRange::new(0, 100).unwrap();
+            let out_range = ValueRange::new(0, 1 << 20).unwrap();
+            let ope = Ope::<i32>::new(key, Some(in_range), Some(out_range)).unwrap();
+            (0..=100)
+                .map(|plaintext| ope.encrypt(plaintext).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let first = encrypt_all(b"first key");
+        assert!(first.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(first, encrypt_all(b"first key"));
+        assert_ne!(first, encrypt_all(b"second key"));
+    }
+
+    #[test]
+    fn test_ope_round_trip_default_range() {
+        let out_range = ValueRange::new(0, (1 << 20) - 1).unwrap();
+        let ope = Ope::new(b"test_key", None, Some(out_range)).unwrap();
+
+        // Every plaintext samples about 20 hypergeometric laws, spread the samples over
+        // the range.
+        let plaintexts = (DEFAULT_IN_RANGE_START..DEFAULT_IN_RANGE_END)
+            .step_by(331)
+            .chain([DEFAULT_IN_RANGE_END]);
+        let mut ciphertexts = Vec::new();
+        for plaintext in plaintexts {
+            let ciphertext = ope.encrypt(plaintext).unwrap();
+            assert_eq!(ope.decrypt(ciphertext), Ok(plaintext));
+            ciphertexts.push(ciphertext);
+        }
+        assert!(ciphertexts.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let unused = (0..).find(|c| ciphertexts.binary_search(c).is_err()).unwrap();
+        assert_eq!(ope.decrypt(unused), Err(OpeError::InvalidCiphertextError));
+    }
+
+    #[test]
+    fn test_ope_default_out_range() {
+        let ope = Ope::<i32>::new(b"test_key", None, None).unwrap();
+        assert_eq!(ope.out_range, ValueRange { start: 0, end: DEFAULT_OUT_RANGE_END });
+
+        let in_range = ValueRange::new(0, 1000).unwrap();
+        let out_range = ValueRange::new(0, 1000).unwrap();
+        assert!(Ope::<i32>::new(b"test_key", Some(in_range), Some(out_range)).is_ok());
+        let in_range = ValueRange::new(0, 1001).unwrap();
+        let out_range = ValueRange::new(0, 1000).unwrap();
+        assert_eq!(
+            Ope::<i32>::new(b"test_key", Some(in_range), Some(out_range)).err(),
+            Some(OpeError::OutOfRangeError)
+        );
+    }
+
+    #[test]
+    fn test_ope_i64_full_range() {
+        let ope = Ope::<i64>::new(b"test_key", None, None).unwrap();
+        assert_eq!(ope.out_range, ValueRange { start: 0, end: u128::MAX });
+
+        let plaintexts = [i64::MIN, i64::MIN + 1, -1 << 40, -1, 0, 1, 1 << 40, i64::MAX];
+        let ciphertexts = plaintexts.map(|plaintext| ope.encrypt(plaintext).unwrap());
+        assert!(ciphertexts.windows(2).all(|pair| pair[0] < pair[1]));
+        for (plaintext, ciphertext) in plaintexts.into_iter().zip(ciphertexts) {
+            assert_eq!(ope.decrypt(ciphertext), Ok(plaintext));
+        }
+    }
+
+    #[test]
+    fn test_ope_u64_full_range() {
+        let ope = Ope::<u64>::new(b"test_key", None, None).unwrap();
+
+        let plaintexts = [0, 1, u64::MAX / 2, u64::MAX - 1, u64::MAX];
+        let ciphertexts = plaintexts.map(|plaintext| ope.encrypt(plaintext).unwrap());
+        assert!(ciphertexts.windows(2).all(|pair| pair[0] < pair[1]));
+        for (plaintext, ciphertext) in plaintexts.into_iter().zip(ciphertexts) {
+            assert_eq!(ope.decrypt(ciphertext), Ok(plaintext));
+        }
+    }
+
+    #[test]
+    fn test_ope_arbitrary_precision() {
+        let key = b"test_key";
+        assert_eq!(
+            Ope::<Integer>::new(key, None, None).err(),
+            Some(OpeError::InvalidRangeLimitsError)
+        );
+
+        let limit = Integer::from(1) << 100u32;
+        let in_range = ValueRange::new(-limit.clone(), limit.clone()).unwrap();
+        let ope = Ope::new(key, Some(in_range), None).unwrap();
+
+        let plaintexts = [-limit.clone(), Integer::from(-1), Integer::new(), limit];
+        let ciphertexts = plaintexts
+            .iter()
+            .map(|plaintext| ope.encrypt(plaintext.clone()).unwrap())
+            .collect::<Vec<_>>();
+        assert!(ciphertexts.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ciphertexts
+            .iter()
+            .all(|ciphertext| ope.out_range.contains(ciphertext)));
+        for (plaintext, ciphertext) in plaintexts.into_iter().zip(ciphertexts) {
+            assert_eq!(ope.decrypt(ciphertext), Ok(plaintext));
+        }
+    }
 }
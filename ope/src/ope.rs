@@ -1,15 +1,20 @@
-use std::cell::RefCell;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
 
+use crate::drbg::HmacDrbg;
+use crate::hgd::Coins;
 use crate::stats::{sample_hgd, sample_uniform};
-use hmac::Hmac;
+use hmac::{Hmac, Mac};
+use rug::integer::Order;
+use rug::Integer;
 use sha2::Sha256;
 
 pub type HmacSha256 = Hmac<Sha256>;
 
 pub const DEFAULT_IN_RANGE_START: i32 = 0;
 pub const DEFAULT_IN_RANGE_END: i32 = 2i32.pow(15) - 1;
-pub const DEFAULT_OUT_RANGE_START: i32 = 0;
-pub const DEFAULT_OUT_RANGE_END: i32 = 2i32.pow(15) - 1;
+pub const DEFAULT_OUT_RANGE_START: u64 = 0;
+pub const DEFAULT_OUT_RANGE_END: u64 = 2u64.pow(30) - 1;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OpeError {
@@ -20,45 +25,167 @@ pub enum OpeError {
     NotEnoughCoinsError,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct ValueRange {
-    pub start: i32,
-    pub end: i32,
+/// Integer type the ranges of an [`Ope`] can be made of.
+///
+/// The decimal representation of `Display` and `FromStr` is how the ciphertexts are
+/// stored.
+pub trait OpeInteger: Clone + Ord + Debug + Display + FromStr {
+    fn to_integer(&self) -> Integer;
+
+    /// Returns `None` if `integer` does not fit in the type.
+    fn from_integer(integer: &Integer) -> Option<Self>;
+}
+
+macro_rules! impl_ope_integer {
+    ($($integer:ty => $to:ident),*) => {
+        $(
+            impl OpeInteger for $integer {
+                fn to_integer(&self) -> Integer {
+                    Integer::from(*self)
+                }
+
+                fn from_integer(integer: &Integer) -> Option<Self> {
+                    integer.$to()
+                }
+            }
+        )*
+    };
+}
+
+impl_ope_integer!(i32 => to_i32, i64 => to_i64, u64 => to_u64, u128 => to_u128);
+
+impl OpeInteger for Integer {
+    fn to_integer(&self) -> Integer {
+        self.clone()
+    }
+
+    fn from_integer(integer: &Integer) -> Option<Self> {
+        Some(integer.clone())
+    }
 }
 
-impl ValueRange {
-    pub fn new(start: i32, end: i32) -> Result<Self, OpeError> {
+/// Type of the plaintexts of an [`Ope`].
+pub trait OpePlaintext: OpeInteger {
+    /// Type of the ciphertexts, large enough for the default output range of any input
+    /// range.
+    type Ciphertext: OpeInteger;
+
+    /// Input range used when none is given, `None` if the type is unbounded.
+    fn default_in_range() -> Option<ValueRange<Self>>;
+}
+
+impl OpePlaintext for i32 {
+    type Ciphertext = u64;
+
+    fn default_in_range() -> Option<ValueRange<Self>> {
+        Some(ValueRange {
+            start: DEFAULT_IN_RANGE_START,
+            end: DEFAULT_IN_RANGE_END,
+        })
+    }
+}
+
+impl OpePlaintext for i64 {
+    type Ciphertext = u128;
+
+    fn default_in_range() -> Option<ValueRange<Self>> {
+        Some(ValueRange { start: i64::MIN, end: i64::MAX })
+    }
+}
+
+impl OpePlaintext for u64 {
+    type Ciphertext = u128;
+
+    fn default_in_range() -> Option<ValueRange<Self>> {
+        Some(ValueRange { start: u64::MIN, end: u64::MAX })
+    }
+}
+
+impl OpePlaintext for Integer {
+    type Ciphertext = Integer;
+
+    fn default_in_range() -> Option<ValueRange<Self>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueRange<T = i32> {
+    pub start: T,
+    pub end: T,
+}
+
+impl<T: OpeInteger> ValueRange<T> {
+    pub fn new(start: T, end: T) -> Result<Self, OpeError> {
         if start > end {
             return Err(OpeError::InvalidRangeLimitsError);
         }
         Ok(ValueRange { start, end })
     }
 
-    pub fn size(&self) -> i32 {
-        self.end - self.start + 1
+    pub fn size(&self) -> Integer {
+        self.end.to_integer() - self.start.to_integer() + 1u32
+    }
+
+    pub fn contains(&self, number: &T) -> bool {
+        self.start <= *number && *number <= self.end
     }
 
-    pub fn contains(&self, number: i32) -> bool {
-        self.start <= number && number <= self.end
+    fn to_integers(&self) -> ValueRange<Integer> {
+        ValueRange {
+            start: self.start.to_integer(),
+            end: self.end.to_integer(),
+        }
     }
 }
 
-pub struct Ope {
+/// Output range with twice as many bits as `in_range`, the usual expansion for this
+/// scheme: each ciphertext then leaks about half of the bits of its plaintext.
+fn default_out_range<T: OpePlaintext>(
+    in_range: &ValueRange<T>,
+) -> Result<ValueRange<T::Ciphertext>, OpeError> {
+    let bits = (in_range.size() - 1u32).significant_bits().max(1);
+    let end = (Integer::from(1) << (2 * bits)) - 1u32;
+    Ok(ValueRange {
+        start: T::Ciphertext::from_integer(&Integer::new())
+            .ok_or(OpeError::OutOfRangeError)?,
+        end: T::Ciphertext::from_integer(&end).ok_or(OpeError::OutOfRangeError)?,
+    })
+}
+
+/// Unambiguous encoding of an integer: its sign, the length of its magnitude and its
+/// magnitude.
+fn encode_integer(integer: &Integer) -> Vec<u8> {
+    let digits: Vec<u8> = integer.to_digits(Order::Msf);
+    let sign = u8::from(*integer < 0);
+    [&[sign][..], &(digits.len() as u32).to_be_bytes(), &digits].concat()
+}
+
+pub struct Ope<T: OpePlaintext = i32> {
     pub key: Vec<u8>,
-    pub in_range: ValueRange,
-    pub out_range: ValueRange,
+    pub in_range: ValueRange<T>,
+    pub out_range: ValueRange<T::Ciphertext>,
 }
 
-impl Ope {
+impl<T: OpePlaintext> Ope<T> {
+    /// Creates an OPE for the plaintexts of `in_range`.
+    ///
+    /// Without `in_range`, the whole type is used, except for `i32` where it defaults to
+    /// `DEFAULT_IN_RANGE_START..=DEFAULT_IN_RANGE_END` and for `Integer` which requires
+    /// one. Without `out_range`, the ciphertexts have twice as many bits as the
+    /// plaintexts.
     pub fn new(
         key: &[u8],
-        in_range: Option<ValueRange>,
-        out_range: Option<ValueRange>,
+        in_range: Option<ValueRange<T>>,
+        out_range: Option<ValueRange<T::Ciphertext>>,
     ) -> Result<Self, OpeError> {
         let in_range = in_range
-            .unwrap_or(ValueRange::new(DEFAULT_IN_RANGE_START, DEFAULT_IN_RANGE_END)?);
-        let out_range = out_range
-            .unwrap_or(ValueRange::new(DEFAULT_OUT_RANGE_START, DEFAULT_OUT_RANGE_END)?);
+            .or_else(T::default_in_range)
+            .ok_or(OpeError::InvalidRangeLimitsError)?;
+        let out_range = match out_range {
+            Some(out_range) => out_range,
+            None => default_out_range(&in_range)?,
+        };
 
         if in_range.size() > out_range.size() {
             return Err(OpeError::OutOfRangeError);
@@ -67,92 +194,111 @@ impl Ope {
         Ok(Ope { key: key.to_vec(), in_range, out_range })
     }
 
-    pub fn encrypt(&self, plaintext: i32) -> Result<i32, OpeError> {
-        if !self.in_range.contains(plaintext) {
+    pub fn encrypt(&self, plaintext: T) -> Result<T::Ciphertext, OpeError> {
+        if !self.in_range.contains(&plaintext) {
             return Err(OpeError::OutOfRangeError);
         }
-        self.encrypt_recursive(plaintext, self.in_range.clone(), self.out_range.clone())
+        let ciphertext = self.encrypt_recursive(
+            &plaintext.to_integer(),
+            self.in_range.to_integers(),
+            self.out_range.to_integers(),
+        )?;
+        T::Ciphertext::from_integer(&ciphertext).ok_or(OpeError::OutOfRangeError)
     }
 
     fn encrypt_recursive(
         &self,
-        plaintext: i32,
-        mut in_range: ValueRange,
-        mut out_range: ValueRange,
-    ) -> Result<i32, OpeError> {
+        plaintext: &Integer,
+        mut in_range: ValueRange<Integer>,
+        mut out_range: ValueRange<Integer>,
+    ) -> Result<Integer, OpeError> {
         let in_size = in_range.size();
         let out_size = out_range.size();
-        let in_edge = in_range.start.wrapping_sub(1);
-        let out_edge = out_range.start.wrapping_sub(1);
-        let mid = out_edge + ((out_size + 1) / 2);
+        let in_edge = in_range.start.clone() - 1u32;
+        let out_edge = out_range.start.clone() - 1u32;
+        let mid = out_edge.clone() + (out_size.clone() + 1u32) / 2u32;
 
-        if in_range.size() == 1 {
-            let coins = RefCell::new(self.tape_gen(plaintext));
-            let ciphertext = sample_uniform(out_range, Box::new(coins));
+        if in_size == 1 {
+            let coins = self.tape_gen(&in_range, &out_range, plaintext);
+            let ciphertext = sample_uniform(&out_range, coins);
             return Ok(ciphertext);
         }
 
-        let coins = self.tape_gen(mid);
-        let x = sample_hgd(in_range, out_range, mid, Box::new(coins));
+        let coins = self.tape_gen(&in_range, &out_range, &mid);
+        let x = sample_hgd(&in_range, &out_range, &mid, coins);
 
-        if plaintext <= x {
-            in_range = ValueRange::new(in_edge + 1, x)?;
-            out_range = ValueRange::new(out_edge + 1, mid)?;
+        if *plaintext <= x {
+            in_range = ValueRange::new(in_edge + 1u32, x)?;
+            out_range = ValueRange::new(out_edge + 1u32, mid)?;
         } else {
-            in_range = ValueRange::new(x + 1, in_edge + in_size)?;
-            out_range = ValueRange::new(mid + 1, out_edge + out_size)?;
+            in_range = ValueRange::new(x + 1u32, in_edge + in_size)?;
+            out_range = ValueRange::new(mid + 1u32, out_edge + out_size)?;
         }
         self.encrypt_recursive(plaintext, in_range, out_range)
     }
 
-    pub fn decrypt(&self, ciphertext: i32) -> Result<i32, OpeError> {
-        if !self.out_range.contains(ciphertext) {
+    pub fn decrypt(&self, ciphertext: T::Ciphertext) -> Result<T, OpeError> {
+        if !self.out_range.contains(&ciphertext) {
             return Err(OpeError::OutOfRangeError);
         }
-        self.decrypt_recursive(ciphertext, self.in_range.clone(), self.out_range.clone())
+        let plaintext = self.decrypt_recursive(
+            &ciphertext.to_integer(),
+            self.in_range.to_integers(),
+            self.out_range.to_integers(),
+        )?;
+        T::from_integer(&plaintext).ok_or(OpeError::InvalidCiphertextError)
     }
 
     fn decrypt_recursive(
         &self,
-        ciphertext: i32,
-        mut in_range: ValueRange,
-        mut out_range: ValueRange,
-    ) -> Result<i32, OpeError> {
+        ciphertext: &Integer,
+        mut in_range: ValueRange<Integer>,
+        mut out_range: ValueRange<Integer>,
+    ) -> Result<Integer, OpeError> {
         let in_size = in_range.size();
         let out_size = out_range.size();
-        let in_edge = in_range.start.wrapping_sub(1);
-        let out_edge = out_range.start.wrapping_sub(1);
-        let mid = out_edge + ((out_size + 1) / 2);
+        let in_edge = in_range.start.clone() - 1u32;
+        let out_edge = out_range.start.clone() - 1u32;
+        let mid = out_edge.clone() + (out_size.clone() + 1u32) / 2u32;
 
-        if out_range.size() == 1 {
-            let coins = RefCell::new(self.tape_gen(ciphertext));
-            let plaintext = sample_uniform(in_range, Box::new(coins)); // sample_uniform needs to be implemented
+        if in_size == 1 {
+            // Only one plaintext is left, check that it encrypts to `ciphertext`.
+            let plaintext = in_range.start.clone();
+            let coins = self.tape_gen(&in_range, &out_range, &plaintext);
+            if sample_uniform(&out_range, coins) != *ciphertext {
+                return Err(OpeError::InvalidCiphertextError);
+            }
             return Ok(plaintext);
         }
 
-        let coins = self.tape_gen(mid);
-        let x = sample_hgd(in_range, out_range, mid, Box::new(coins)); // sample_hgd needs to be implemented
+        let coins = self.tape_gen(&in_range, &out_range, &mid);
+        let x = sample_hgd(&in_range, &out_range, &mid, coins);
 
-        if ciphertext <= mid {
-            out_range = ValueRange::new(out_edge + 1, mid)?;
-            in_range = ValueRange::new(in_edge + 1, x)?;
+        if *ciphertext <= mid {
+            out_range = ValueRange::new(out_edge + 1u32, mid)?;
+            in_range = ValueRange::new(in_edge + 1u32, x)?;
         } else {
-            out_range = ValueRange::new(mid + 1, out_edge + out_size)?;
-            in_range = ValueRange::new(x + 1, in_edge + in_size)?;
+            out_range = ValueRange::new(mid + 1u32, out_edge + out_size)?;
+            in_range = ValueRange::new(x + 1u32, in_edge + in_size)?;
         }
         self.decrypt_recursive(ciphertext, in_range, out_range)
     }
 
-    fn tape_gen(&self, data: i32) -> impl Iterator<Item = bool> {
-        let data_bytes = data.to_le_bytes();
-        let mut bits = Vec::new();
-
-        for &byte in data_bytes.iter() {
-            for i in 0..8 {
-                bits.push((byte >> i) & 1 == 1);
-            }
+    /// Coins of the sampling of `data` in the current ranges, seeded with
+    /// HMAC(key, in_range, out_range, data) so they depend on the key.
+    fn tape_gen(
+        &self,
+        in_range: &ValueRange<Integer>,
+        out_range: &ValueRange<Integer>,
+        data: &Integer,
+    ) -> Coins {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        for value in
+            [&in_range.start, &in_range.end, &out_range.start, &out_range.end, data]
+        {
+            mac.update(&encode_integer(value));
         }
-
-        bits.into_iter()
+        Box::new(HmacDrbg::new(&mac.finalize().into_bytes()))
     }
 }
@@ -0,0 +1,284 @@
+//! Order revealing encryption with the block scheme of Lewi and Wu, "Order-Revealing
+//! Encryption: New Constructions, Applications, and Lower Bounds" (CCS 2016).
+//!
+//! The plaintexts are big-endian byte strings of the same length, compared
+//! lexicographically, and each byte is a block. A value has two ciphertexts:
+//!
+//! - the left ciphertext holds, for each block, the PRF of the block under a keyed
+//!   permutation depending on the previous blocks, and its permuted position.
+//! - the right ciphertext holds, for each block and each of its 256 values, the
+//!   comparison of that value with the block, masked with the PRF of the value.
+//!
+//! [`compare`] needs no key: the left ciphertext of `x` unmasks, in the right
+//! ciphertext of `y`, the comparison of the first block where `x` and `y` differ and
+//! nothing else. Right ciphertexts alone are semantically secure, so the records only
+//! store them and the bounds of the queries are left ciphertexts. Unlike OPE, the
+//! stored ciphertexts don't leak anything until they are compared.
+
+use std::cmp::Ordering;
+
+use hmac::Mac;
+use rand::Rng;
+
+use crate::drbg::HmacDrbg;
+use crate::ope::{HmacSha256, OpeError};
+
+/// Number of values of a block.
+const BLOCK_VALUES: usize = 256;
+
+const KEY_LEN: usize = 32;
+
+const NONCE_LEN: usize = 16;
+
+/// Length of an encoded left block, its PRF then its permuted position.
+const LEFT_BLOCK_LEN: usize = KEY_LEN + 1;
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; KEY_LEN] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Hash of `nonce` under `key` reduced modulo 3, which masks a comparison.
+fn mask(key: &[u8], nonce: &[u8]) -> u8 {
+    let hash = hmac(key, &[nonce]);
+    let hash = u64::from_be_bytes(hash[..8].try_into().expect("hash is long enough"));
+    (hash % 3) as u8
+}
+
+/// Comparison of two block values, the encoding is the one of Lewi and Wu.
+fn comparison(x: u8, y: u8) -> u8 {
+    match x.cmp(&y) {
+        Ordering::Equal => 0,
+        Ordering::Less => 1,
+        Ordering::Greater => 2,
+    }
+}
+
+/// Random permutation of the values of a block drawn from `seed`, with the
+/// Fisher-Yates shuffle.
+fn permutation(seed: &[u8]) -> [u8; BLOCK_VALUES] {
+    let mut coins = HmacDrbg::new(seed);
+    let mut values = [0u8; BLOCK_VALUES];
+    for (index, value) in values.iter_mut().enumerate() {
+        *value = index as u8;
+    }
+    for last in (1..BLOCK_VALUES).rev() {
+        // Uniform index of [0, last], by rejection of the larger draws.
+        let bits = usize::BITS - last.leading_zeros();
+        let index = loop {
+            let index = coins
+                .by_ref()
+                .take(bits as usize)
+                .fold(0, |index, coin| index << 1 | usize::from(coin));
+            if index <= last {
+                break index;
+            }
+        };
+        values.swap(index, last);
+    }
+    values
+}
+
+/// Left ciphertext, the bound of a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeftCiphertext {
+    /// PRF and permuted position of each block.
+    pub blocks: Vec<([u8; KEY_LEN], u8)>,
+}
+
+impl LeftCiphertext {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.blocks
+            .iter()
+            .flat_map(|(key, position)| key.iter().chain([position]).copied())
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OpeError> {
+        let blocks = bytes.chunks_exact(LEFT_BLOCK_LEN);
+        if !blocks.remainder().is_empty() {
+            return Err(OpeError::InvalidCiphertextError);
+        }
+        let blocks = blocks
+            .map(|block| {
+                let key = block[..KEY_LEN].try_into().expect("block has a key");
+                (key, block[KEY_LEN])
+            })
+            .collect();
+        Ok(Self { blocks })
+    }
+}
+
+/// Right ciphertext, the one stored with a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RightCiphertext {
+    pub nonce: [u8; NONCE_LEN],
+    /// Masked comparisons of each block with every value, by permuted position.
+    pub blocks: Vec<[u8; BLOCK_VALUES]>,
+}
+
+impl RightCiphertext {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let blocks = self.blocks.iter().flatten().copied();
+        self.nonce.iter().copied().chain(blocks).collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OpeError> {
+        if bytes.len() < NONCE_LEN {
+            return Err(OpeError::InvalidCiphertextError);
+        }
+        let (nonce, blocks) = bytes.split_at(NONCE_LEN);
+        let blocks = blocks.chunks_exact(BLOCK_VALUES);
+        if !blocks.remainder().is_empty() || bytes.iter().skip(NONCE_LEN).any(|c| *c > 2)
+        {
+            return Err(OpeError::InvalidCiphertextError);
+        }
+        Ok(Self {
+            nonce: nonce.try_into().expect("nonce has its length"),
+            blocks: blocks
+                .map(|block| block.try_into().expect("block has its length"))
+                .collect(),
+        })
+    }
+}
+
+/// Compares the plaintext of `left` to the plaintext of `right`, without key.
+///
+/// Both must be ciphertexts of the same key, otherwise the result is meaningless.
+/// Fails with [`OpeError::InvalidCiphertextError`] if the plaintexts don't have the
+/// same length.
+pub fn compare(
+    left: &LeftCiphertext,
+    right: &RightCiphertext,
+) -> Result<Ordering, OpeError> {
+    if left.blocks.len() != right.blocks.len() {
+        return Err(OpeError::InvalidCiphertextError);
+    }
+    for ((key, position), comparisons) in left.blocks.iter().zip(&right.blocks) {
+        let masked = comparisons[usize::from(*position)];
+        match (masked + 3 - mask(key, &right.nonce)) % 3 {
+            0 => continue,
+            1 => return Ok(Ordering::Less),
+            _ => return Ok(Ordering::Greater),
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+pub struct Ore {
+    /// Key of the PRF of the blocks.
+    prf_key: [u8; KEY_LEN],
+    /// Key of the permutations of the blocks.
+    permutation_key: [u8; KEY_LEN],
+}
+
+impl Ore {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            prf_key: hmac(key, &[b"liserk ore prf"]),
+            permutation_key: hmac(key, &[b"liserk ore permutation"]),
+        }
+    }
+
+    /// Permutation of the block following `prefix`.
+    fn permutation(&self, prefix: &[u8]) -> [u8; BLOCK_VALUES] {
+        permutation(&hmac(&self.permutation_key, &[prefix]))
+    }
+
+    /// PRF of the block at `position` of the permutation following `prefix`.
+    fn prf(&self, prefix: &[u8], position: u8) -> [u8; KEY_LEN] {
+        hmac(&self.prf_key, &[prefix, &[position]])
+    }
+
+    pub fn encrypt_left(&self, plaintext: &[u8]) -> LeftCiphertext {
+        let blocks = (0..plaintext.len())
+            .map(|index| {
+                let (prefix, block) = (&plaintext[..index], plaintext[index]);
+                let position = self.permutation(prefix)[usize::from(block)];
+                (self.prf(prefix, position), position)
+            })
+            .collect();
+        LeftCiphertext { blocks }
+    }
+
+    /// Encrypts `plaintext` with a random nonce.
+    pub fn encrypt_right(&self, plaintext: &[u8]) -> RightCiphertext {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        let blocks = (0..plaintext.len())
+            .map(|index| {
+                let (prefix, block) = (&plaintext[..index], plaintext[index]);
+                let permutation = self.permutation(prefix);
+                let mut comparisons = [0u8; BLOCK_VALUES];
+                for value in 0..=u8::MAX {
+                    let position = permutation[usize::from(value)];
+                    let key = self.prf(prefix, position);
+                    comparisons[usize::from(position)] =
+                        (comparison(value, block) + mask(&key, &nonce)) % 3;
+                }
+                comparisons
+            })
+            .collect();
+        RightCiphertext { nonce, blocks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutation() {
+        let mut permutation = permutation(b"seed");
+        assert_ne!(permutation[..16], (0..16).collect::<Vec<u8>>());
+        permutation.sort();
+        assert!(permutation
+            .iter()
+            .enumerate()
+            .all(|(index, value)| index == *value as usize));
+    }
+
+    #[test]
+    fn test_compare() {
+        let ore = Ore::new(b"test_key");
+        let values = [0u16, 1, 255, 256, 257, 0x1234, 0xff00, u16::MAX];
+        for x in values {
+            let left = ore.encrypt_left(&x.to_be_bytes());
+            let left = LeftCiphertext::from_bytes(&left.to_bytes()).unwrap();
+            for y in values {
+                let right = ore.encrypt_right(&y.to_be_bytes());
+                let right = RightCiphertext::from_bytes(&right.to_bytes()).unwrap();
+                assert_eq!(compare(&left, &right), Ok(x.cmp(&y)), "{} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_right_ciphertexts_are_randomized() {
+        let ore = Ore::new(b"test_key");
+        assert_ne!(ore.encrypt_right(b"same"), ore.encrypt_right(b"same"));
+        assert_eq!(ore.encrypt_left(b"same"), ore.encrypt_left(b"same"));
+        assert_ne!(
+            ore.encrypt_left(b"same"),
+            Ore::new(b"other key").encrypt_left(b"same")
+        );
+    }
+
+    #[test]
+    fn test_invalid_ciphertexts() {
+        let ore = Ore::new(b"test_key");
+        let left = ore.encrypt_left(&[1, 2]);
+        let right = ore.encrypt_right(&[1, 2, 3]);
+        assert_eq!(compare(&left, &right), Err(OpeError::InvalidCiphertextError));
+
+        let bytes = right.to_bytes();
+        assert!(RightCiphertext::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(
+            RightCiphertext::from_bytes(&[&bytes[..], &[3; 256][..]].concat()).is_err()
+        );
+        assert!(LeftCiphertext::from_bytes(&left.to_bytes()[1..]).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Enforcement of the ACL stored next to every record.
+//!
+//! The ACL of the record `collection:id` is kept under `collection:id:acl`. A record
+//! without ACL is treated as missing, nobody can read or modify it.
+
+use std::collections::HashSet;
+
+use liserk_shared::acl::{AclEntry, Permission};
+
+use crate::auth::User;
+use crate::storage::{Key, StorageTransaction};
+use crate::Error;
+
+pub fn acl_key(data_key: &str) -> String {
+    format!("{}:acl", data_key)
+}
+
+fn is_granted(acl: &[AclEntry], user: &User, permission: Permission) -> bool {
+    acl.iter()
+        .any(|entry| entry.grants(&user.username, &user.groups, permission))
+}
+
+/// Gives the administration of a new record to its creator, unless the ACL sent with
+/// the insertion already does.
+pub fn with_owner(mut acl: Vec<AclEntry>, user: &User) -> Vec<AclEntry> {
+    if !is_granted(&acl, user, Permission::Admin) {
+        acl.push(AclEntry::user(user.username.clone(), Permission::Admin));
+    }
+    acl
+}
+
+/// Checks that `user` has `permission` on the record stored at `data_key`.
+///
+/// Returns `false` if the record does not exist and [`Error::AccessDenied`] if the
+/// user is not allowed.
+pub async fn require(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+    data_key: &str,
+    permission: Permission,
+) -> Result<bool, Error> {
+    let Some(acl) = transaction.get(acl_key(data_key).into_bytes()).await? else {
+        return Ok(false);
+    };
+    let acl: Vec<AclEntry> = serde_cbor::from_slice(&acl)?;
+    if !is_granted(&acl, user, permission) {
+        return Err(Error::AccessDenied(data_key.to_string()));
+    }
+    Ok(true)
+}
+
+/// Keeps the records of `data_keys` `user` is allowed to read, in the same order.
+pub async fn filter_readable(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+    data_keys: Vec<String>,
+) -> Result<Vec<String>, Error> {
+    let acl_keys: Vec<Key> =
+        data_keys.iter().map(|key| acl_key(key).into_bytes()).collect();
+    let mut readable = HashSet::new();
+    for (key, acl) in transaction.batch_get(acl_keys).await? {
+        let acl: Vec<AclEntry> = serde_cbor::from_slice(&acl)?;
+        if is_granted(&acl, user, Permission::Read) {
+            readable.insert(key);
+        }
+    }
+    Ok(data_keys
+        .into_iter()
+        .filter(|key| readable.contains(acl_key(key).as_bytes()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+    use crate::test_utils::create_user;
+
+    #[tokio::test]
+    async fn test_require_and_filter() {
+        let storage = EmbeddedBackend::in_memory();
+        let alice = create_user(&storage, "alice", false, &[]).await;
+        let bob = create_user(&storage, "bob", false, &["staff"]).await;
+        let shared = with_owner(vec![AclEntry::group("staff", Permission::Read)], &alice);
+        let private = with_owner(vec![], &alice);
+
+        let mut transaction = storage.begin().await.unwrap();
+        for (key, acl) in [("users:1", shared), ("users:2", private)] {
+            let acl = serde_cbor::to_vec(&acl).unwrap();
+            transaction.put(acl_key(key).into_bytes(), acl).await.unwrap();
+        }
+
+        let transaction = transaction.as_mut();
+        assert!(require(transaction, &alice, "users:2", Permission::Admin)
+            .await
+            .unwrap());
+        assert!(require(transaction, &bob, "users:1", Permission::Read).await.unwrap());
+        assert!(matches!(
+            require(transaction, &bob, "users:1", Permission::Write).await,
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            require(transaction, &bob, "users:2", Permission::Read).await,
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(!require(transaction, &bob, "users:3", Permission::Read).await.unwrap());
+
+        let keys = ["users:3", "users:2", "users:1"].map(String::from).to_vec();
+        let readable = filter_readable(transaction, &bob, keys.clone()).await.unwrap();
+        assert_eq!(readable, vec![String::from("users:1")]);
+        let readable = filter_readable(transaction, &alice, keys).await.unwrap();
+        assert_eq!(readable, ["users:2", "users:1"].map(String::from).to_vec());
+    }
+}
@@ -0,0 +1,353 @@
+//! Users of the server and authentication of the connections.
+//!
+//! Users are kept in the storage backend under [`USERS_PREFIX`], with their password
+//! hashed by Argon2id. A connection starts unauthenticated and only accepts
+//! `ClientAuthentification` and `EndOfCommunication` until the credentials of a user
+//! are verified.
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use liserk_shared::message::{NewUser, PasswordChange};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::settings::{AuthConfig, EXAMPLE_ADMIN_PASSWORD};
+use crate::storage::{Key, StorageBackend};
+use crate::Error;
+
+/// Prefix of the keys holding the users, it can't clash with the keys of a record
+/// because it starts with [`RESERVED_PREFIX`](crate::catalog::RESERVED_PREFIX), which
+/// no collection can start with.
+pub const USERS_PREFIX: &str = "__liserk:users:";
+
+/// A user as stored in the storage backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// Argon2id hash in the PHC string format.
+    password_hash: String,
+    /// Administrators can manage the other users.
+    pub admin: bool,
+    /// Groups the user belongs to, which ACL entries can refer to.
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// Authentication state of one connection.
+#[derive(Debug)]
+pub struct ClientSession {
+    user: Option<User>,
+    session_id: Option<Uuid>,
+    failed_attempts: u32,
+    max_attempts: u32,
+}
+
+impl ClientSession {
+    /// Creates the state of a new connection, which is closed after `max_attempts`
+    /// failed authentications.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            user: None,
+            session_id: None,
+            failed_attempts: 0,
+            max_attempts,
+        }
+    }
+
+    /// User the requests of the connection are made as, `None` until the client
+    /// authenticates.
+    pub fn user(&self) -> Option<&User> {
+        self.user.as_ref()
+    }
+
+    pub fn session_id(&self) -> Option<Uuid> {
+        self.session_id
+    }
+
+    /// Whether the client failed to authenticate too many times.
+    pub fn attempts_exhausted(&self) -> bool {
+        self.failed_attempts >= self.max_attempts
+    }
+
+    /// Marks the connection as authenticated by `user` and returns the new session id.
+    pub fn authenticate(&mut self, user: User) -> Uuid {
+        let session_id = Uuid::new_v4();
+        self.user = Some(user);
+        self.session_id = Some(session_id);
+        self.failed_attempts = 0;
+        session_id
+    }
+
+    /// Drops any previous authentication after invalid credentials.
+    pub fn fail(&mut self) {
+        self.user = None;
+        self.session_id = None;
+        self.failed_attempts += 1;
+    }
+}
+
+fn user_key(username: &str) -> Key {
+    format!("{}{}", USERS_PREFIX, username).into_bytes()
+}
+
+/// Hashes a password with Argon2id and a random salt, off the async runtime.
+async fn hash_password(password: String) -> Result<String, Error> {
+    let hash = tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .expect("password hashing does not panic")?;
+    Ok(hash)
+}
+
+/// Checks a password against an Argon2 hash, off the async runtime.
+async fn verify_password(password: String, hash: String) -> Result<bool, Error> {
+    let valid = tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&hash)?;
+        match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(err) => Err(err),
+        }
+    })
+    .await
+    .expect("password verification does not panic")?;
+    Ok(valid)
+}
+
+pub async fn get_user(
+    storage: &dyn StorageBackend,
+    username: &str,
+) -> Result<Option<User>, Error> {
+    let mut transaction = storage.begin().await?;
+    let user = transaction.get(user_key(username)).await?;
+    transaction.commit().await?;
+    match user {
+        Some(user) => Ok(Some(serde_cbor::from_slice(&user)?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the user if the credentials are valid.
+pub async fn authenticate(
+    storage: &dyn StorageBackend,
+    username: &str,
+    password: &str,
+) -> Result<Option<User>, Error> {
+    let Some(user) = get_user(storage, username).await? else {
+        return Ok(None);
+    };
+    let valid = verify_password(password.to_string(), user.password_hash.clone()).await?;
+    Ok(valid.then_some(user))
+}
+
+pub async fn create_user(
+    storage: &dyn StorageBackend,
+    new_user: NewUser,
+) -> Result<User, Error> {
+    let user = User {
+        password_hash: hash_password(new_user.password).await?,
+        username: new_user.username,
+        admin: new_user.admin,
+        groups: new_user.groups,
+    };
+    let key = user_key(&user.username);
+    let mut transaction = storage.begin().await?;
+    if transaction.get_for_update(key.clone()).await?.is_some() {
+        transaction.rollback().await?;
+        return Err(Error::UserAlreadyExists(user.username));
+    }
+    transaction.insert(key, serde_cbor::to_vec(&user)?).await?;
+    transaction.commit().await?;
+    info!("user {} created", user.username);
+    Ok(user)
+}
+
+pub async fn delete_user(
+    storage: &dyn StorageBackend,
+    username: &str,
+) -> Result<(), Error> {
+    let key = user_key(username);
+    let mut transaction = storage.begin().await?;
+    if transaction.get_for_update(key.clone()).await?.is_none() {
+        transaction.rollback().await?;
+        return Err(Error::UserNotFound(username.to_string()));
+    }
+    transaction.delete(key).await?;
+    transaction.commit().await?;
+    info!("user {} deleted", username);
+    Ok(())
+}
+
+/// Changes the password of `change.username` on behalf of `caller`.
+///
+/// Users can change their own password by giving the current one, administrators can
+/// reset the password of anyone else.
+pub async fn change_password(
+    storage: &dyn StorageBackend,
+    caller: &User,
+    change: PasswordChange,
+) -> Result<(), Error> {
+    let own_password = caller.username == change.username;
+    if !own_password && !caller.admin {
+        return Err(Error::AdminRequired);
+    }
+    let key = user_key(&change.username);
+    let mut transaction = storage.begin().await?;
+    let Some(user) = transaction.get_for_update(key.clone()).await? else {
+        transaction.rollback().await?;
+        return Err(Error::UserNotFound(change.username));
+    };
+    let mut user: User = serde_cbor::from_slice(&user)?;
+    if own_password
+        && !verify_password(change.current_password, user.password_hash.clone()).await?
+    {
+        transaction.rollback().await?;
+        return Err(Error::InvalidCredentials);
+    }
+    user.password_hash = hash_password(change.new_password).await?;
+    transaction.put(key, serde_cbor::to_vec(&user)?).await?;
+    transaction.commit().await?;
+    info!("password of {} changed", user.username);
+    Ok(())
+}
+
+/// Creates the administrator of the configuration if it does not exist yet.
+pub async fn ensure_admin(
+    storage: &dyn StorageBackend,
+    config: &AuthConfig,
+) -> Result<(), Error> {
+    if config.admin_username.is_empty() {
+        warn!("no administrator configured, users can't be managed");
+        return Ok(());
+    }
+    if get_user(storage, &config.admin_username).await?.is_some() {
+        return Ok(());
+    }
+    if config.admin_password.is_empty() || config.admin_password == EXAMPLE_ADMIN_PASSWORD
+    {
+        warn!(
+            "administrator {} not created, its password is empty or the example one",
+            config.admin_username
+        );
+        return Ok(());
+    }
+    let admin = NewUser {
+        username: config.admin_username.clone(),
+        password: config.admin_password.clone(),
+        admin: true,
+        groups: vec![],
+    };
+    match create_user(storage, admin).await {
+        // Another server sharing the storage created it first.
+        Ok(_) | Err(Error::UserAlreadyExists(_)) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::EmbeddedBackend;
+
+    fn new_user(username: &str, password: &str, admin: bool) -> NewUser {
+        NewUser {
+            username: username.into(),
+            password: password.into(),
+            admin,
+            groups: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let storage = EmbeddedBackend::in_memory();
+        let user = create_user(&storage, new_user("alice", "secret", false))
+            .await
+            .unwrap();
+        assert!(!user.password_hash.contains("secret"));
+        assert!(user.password_hash.starts_with("$argon2id$"));
+
+        let authenticated = authenticate(&storage, "alice", "secret").await.unwrap();
+        assert_eq!(authenticated, Some(user));
+        assert_eq!(authenticate(&storage, "alice", "wrong").await.unwrap(), None);
+        assert_eq!(authenticate(&storage, "bob", "secret").await.unwrap(), None);
+
+        assert!(matches!(
+            create_user(&storage, new_user("alice", "other", true)).await,
+            Err(Error::UserAlreadyExists(_))
+        ));
+        delete_user(&storage, "alice").await.unwrap();
+        assert_eq!(authenticate(&storage, "alice", "secret").await.unwrap(), None);
+        assert!(matches!(
+            delete_user(&storage, "alice").await,
+            Err(Error::UserNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let storage = EmbeddedBackend::in_memory();
+        let admin = create_user(&storage, new_user("root", "root", true)).await.unwrap();
+        let alice = create_user(&storage, new_user("alice", "old", false)).await.unwrap();
+        let change = |username: &str, current: &str, new: &str| PasswordChange {
+            username: username.into(),
+            current_password: current.into(),
+            new_password: new.into(),
+        };
+
+        assert!(matches!(
+            change_password(&storage, &alice, change("alice", "wrong", "new")).await,
+            Err(Error::InvalidCredentials)
+        ));
+        assert!(matches!(
+            change_password(&storage, &alice, change("root", "root", "new")).await,
+            Err(Error::AdminRequired)
+        ));
+        change_password(&storage, &alice, change("alice", "old", "new"))
+            .await
+            .unwrap();
+        assert!(authenticate(&storage, "alice", "new").await.unwrap().is_some());
+
+        change_password(&storage, &admin, change("alice", "", "reset"))
+            .await
+            .unwrap();
+        assert!(authenticate(&storage, "alice", "new").await.unwrap().is_none());
+        assert!(authenticate(&storage, "alice", "reset").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_ensure_admin_keeps_existing_password() {
+        let storage = EmbeddedBackend::in_memory();
+        let config = AuthConfig {
+            admin_username: String::from("root"),
+            admin_password: String::from("first"),
+            ..AuthConfig::default()
+        };
+        ensure_admin(&storage, &config).await.unwrap();
+        let config = AuthConfig { admin_password: String::from("second"), ..config };
+        ensure_admin(&storage, &config).await.unwrap();
+
+        let admin = authenticate(&storage, "root", "first").await.unwrap().unwrap();
+        assert!(admin.admin);
+        assert!(authenticate(&storage, "root", "second").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ensure_admin_refuses_default_passwords() {
+        let storage = EmbeddedBackend::in_memory();
+        for password in ["", EXAMPLE_ADMIN_PASSWORD] {
+            let config = AuthConfig {
+                admin_username: String::from("root"),
+                admin_password: String::from(password),
+                ..AuthConfig::default()
+            };
+            ensure_admin(&storage, &config).await.unwrap();
+            assert!(get_user(&storage, "root").await.unwrap().is_none());
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! Catalog of the collections.
+//!
+//! The [`CollectionInfo`] of a collection is kept under
+//! `__liserk:catalog:<collection>`. It is created by the first insertion into the
+//! collection and only written again when the collection gets a new usecase or a new
+//! history, so the writers of a collection don't all lock it.
+//!
+//! The number and the size of the records are split into [`COUNT_SHARDS`] counters,
+//! `__liserk:counts:<shard>:<collection>`, and a record is always counted in the
+//! shard of its data key. Only the writers of records of the same shard conflict on
+//! a counter, and those of the same record already conflict on the record itself.
+
+use chrono::Utc;
+use liserk_shared::catalog::{CollectionInfo, EncryptionKind, UsecaseInfo};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::User;
+use crate::storage::{Key, StorageTransaction};
+use crate::Error;
+
+/// Prefix of the keys of the server itself, such as the users and the catalog.
+pub const RESERVED_PREFIX: &str = "__liserk";
+
+pub const CATALOG_PREFIX: &str = "__liserk:catalog:";
+
+pub const COUNTS_PREFIX: &str = "__liserk:counts:";
+
+/// Number of counters of the records of each collection.
+pub const COUNT_SHARDS: u32 = 16;
+
+/// Refuses the collections starting with [`RESERVED_PREFIX`], whose record keys could
+/// overwrite or be read as the keys of the server, and the collections containing
+/// `:`, whose keys could be read as the keys of another collection.
+pub fn check_collection(collection: &str) -> Result<(), Error> {
+    if collection.starts_with(RESERVED_PREFIX) {
+        return Err(Error::ReservedCollection(collection.to_string()));
+    }
+    if collection.contains(':') {
+        return Err(Error::InvalidName(collection.to_string()));
+    }
+    Ok(())
+}
+
+/// Refuses the usecases containing `:`, whose index keys could be read as the keys of
+/// another usecase.
+pub fn check_usecases(usecases: &[String]) -> Result<(), Error> {
+    match usecases.iter().find(|usecase| usecase.contains(':')) {
+        Some(usecase) => Err(Error::InvalidName(usecase.clone())),
+        None => Ok(()),
+    }
+}
+
+fn catalog_key(collection: &str) -> Key {
+    format!("{}{}", CATALOG_PREFIX, collection).into_bytes()
+}
+
+/// The shard comes first, as a collection name may end like another one followed by
+/// a shard.
+fn counts_key(shard: u32, collection: &str) -> Key {
+    format!("{}{:02}:{}", COUNTS_PREFIX, shard, collection).into_bytes()
+}
+
+/// Shard of the record stored at `data_key`, with FNV-1a so it doesn't change between
+/// builds of the server.
+fn shard(data_key: &str) -> u32 {
+    let hash = data_key.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    hash % COUNT_SHARDS
+}
+
+/// Changes of the number and the size of the records counted in a shard, which may be
+/// negative when the records were inserted before the catalog existed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Counts {
+    records: i64,
+    bytes: i64,
+}
+
+/// Whether `user` can see and configure the collection described by `info`.
+fn is_visible(info: &CollectionInfo, user: &User) -> bool {
+    info.owner == user.username || user.admin
+}
+
+/// Catalog of `collection` as stored, without its counts.
+async fn load(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+) -> Result<Option<CollectionInfo>, Error> {
+    match transaction.get(catalog_key(collection)).await? {
+        Some(info) => Ok(Some(serde_cbor::from_slice(&info)?)),
+        None => Ok(None),
+    }
+}
+
+/// Sets the counts of `info` to the sum of its shards.
+async fn add_counts(
+    transaction: &mut dyn StorageTransaction,
+    info: &mut CollectionInfo,
+) -> Result<(), Error> {
+    let keys = (0..COUNT_SHARDS).map(|shard| counts_key(shard, &info.name)).collect();
+    let mut total = Counts::default();
+    for (_, counts) in transaction.batch_get(keys).await? {
+        let counts: Counts = serde_cbor::from_slice(&counts)?;
+        total.records += counts.records;
+        total.bytes += counts.bytes;
+    }
+    info.record_count = total.records.max(0) as u64;
+    info.byte_size = total.bytes.max(0) as u64;
+    Ok(())
+}
+
+pub async fn get(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+) -> Result<Option<CollectionInfo>, Error> {
+    let Some(mut info) = load(transaction, collection).await? else {
+        return Ok(None);
+    };
+    add_counts(transaction, &mut info).await?;
+    Ok(Some(info))
+}
+
+/// Catalog of `collection` for `user`, only its owner and the administrators can see
+/// it.
+pub async fn describe(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+    collection: &str,
+) -> Result<Option<CollectionInfo>, Error> {
+    check_collection(collection)?;
+    match get(transaction, collection).await? {
+        Some(info) if !is_visible(&info, user) => {
+            Err(Error::AccessDenied(collection.to_string()))
+        }
+        info => Ok(info),
+    }
+}
+
+/// Refuses `user` the collections of other owners, unless an administrator.
+pub async fn check_access(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+    collection: &str,
+) -> Result<(), Error> {
+    check_collection(collection)?;
+    match load(transaction, collection).await? {
+        Some(info) if !is_visible(&info, user) => {
+            Err(Error::AccessDenied(collection.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Number of previous versions kept for each record of `collection`.
+pub async fn history(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+) -> Result<u32, Error> {
+    Ok(load(transaction, collection).await?.map_or(0, |info| info.history))
+}
+
+/// Names of the collections `user` can see, in order.
+pub async fn list(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+) -> Result<Vec<String>, Error> {
+    let start = CATALOG_PREFIX.as_bytes().to_vec();
+    let mut end = start.clone();
+    // Right after every key of the prefix, which ends with `:`.
+    *end.last_mut().expect("prefix is not empty") += 1;
+    let mut collections = Vec::new();
+    for (_, info) in transaction.scan(start, end, u32::MAX).await? {
+        let info: CollectionInfo = serde_cbor::from_slice(&info)?;
+        if is_visible(&info, user) {
+            collections.push(info.name);
+        }
+    }
+    Ok(collections)
+}
+
+/// Names of the collections starting with `collection:`, created before the
+/// collections could not contain `:`.
+pub async fn nested(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+) -> Result<Vec<String>, Error> {
+    let start = format!("{}{}:", CATALOG_PREFIX, collection).into_bytes();
+    let mut end = start.clone();
+    // Right after every key of the prefix, which ends with `:`.
+    *end.last_mut().expect("prefix is not empty") += 1;
+    let mut collections = Vec::new();
+    for (_, info) in transaction.scan(start, end, u32::MAX).await? {
+        let info: CollectionInfo = serde_cbor::from_slice(&info)?;
+        collections.push(info.name);
+    }
+    Ok(collections)
+}
+
+async fn save(
+    transaction: &mut dyn StorageTransaction,
+    info: &CollectionInfo,
+) -> Result<(), Error> {
+    let serialized = serde_cbor::to_vec(info)?;
+    transaction.put(catalog_key(&info.name), serialized).await?;
+    Ok(())
+}
+
+/// Applies `change` to the catalog of `collection`, if it exists.
+async fn update(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    change: impl FnOnce(&mut CollectionInfo),
+) -> Result<(), Error> {
+    let key = catalog_key(collection);
+    let Some(info) = transaction.get_for_update(key.clone()).await? else {
+        return Ok(());
+    };
+    let mut info: CollectionInfo = serde_cbor::from_slice(&info)?;
+    change(&mut info);
+    save(transaction, &info).await
+}
+
+/// Adds `records` records of `bytes` bytes to the shard of `data_key`.
+async fn count(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    data_key: &str,
+    records: i64,
+    bytes: i64,
+) -> Result<(), Error> {
+    let key = counts_key(shard(data_key), collection);
+    let mut counts: Counts = match transaction.get_for_update(key.clone()).await? {
+        Some(counts) => serde_cbor::from_slice(&counts)?,
+        None => Counts::default(),
+    };
+    counts.records += records;
+    counts.bytes += bytes;
+    transaction.put(key, serde_cbor::to_vec(&counts)?).await?;
+    Ok(())
+}
+
+/// Whether one of `usecases` is not yet in the catalog of `collection`, read without
+/// locking it.
+async fn has_new_usecase(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    usecases: &[String],
+) -> Result<bool, Error> {
+    Ok(match load(transaction, collection).await? {
+        Some(info) => usecases.iter().any(|usecase| info.usecase(usecase).is_none()),
+        None => true,
+    })
+}
+
+/// Records a new record of `byte_size` bytes stored at `data_key` by `user`, creating
+/// the collection if needed.
+pub async fn record_insertion(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+    collection: &str,
+    data_key: &str,
+    usecases: &[String],
+    kind: EncryptionKind,
+    byte_size: u64,
+) -> Result<(), Error> {
+    if has_new_usecase(transaction, collection, usecases).await? {
+        let mut info: CollectionInfo =
+            match transaction.get_for_update(catalog_key(collection)).await? {
+                Some(info) => serde_cbor::from_slice(&info)?,
+                None => new_collection(user, collection),
+            };
+        add_usecases(&mut info, usecases, kind);
+        save(transaction, &info).await?;
+    }
+    count(transaction, collection, data_key, 1, byte_size as i64).await
+}
+
+fn new_collection(user: &User, collection: &str) -> CollectionInfo {
+    CollectionInfo {
+        name: collection.to_string(),
+        created_at: Utc::now(),
+        owner: user.username.clone(),
+        usecases: Vec::new(),
+        record_count: 0,
+        byte_size: 0,
+        history: 0,
+    }
+}
+
+/// Sets the number of previous versions kept for each record of `collection`. Only
+/// its owner and the administrators can change it, and only the administrators can
+/// create the collection to set its history before its first insertion.
+pub async fn set_history(
+    transaction: &mut dyn StorageTransaction,
+    user: &User,
+    collection: &str,
+    versions: u32,
+) -> Result<CollectionInfo, Error> {
+    check_collection(collection)?;
+    let mut info = match transaction.get_for_update(catalog_key(collection)).await? {
+        Some(info) => serde_cbor::from_slice(&info)?,
+        None if user.admin => new_collection(user, collection),
+        None => return Err(Error::CollectionNotFound(collection.to_string())),
+    };
+    if !is_visible(&info, user) {
+        return Err(Error::AccessDenied(collection.to_string()));
+    }
+    info.history = versions;
+    save(transaction, &info).await?;
+    add_counts(transaction, &mut info).await?;
+    Ok(info)
+}
+
+fn add_usecases(info: &mut CollectionInfo, usecases: &[String], kind: EncryptionKind) {
+    for usecase in usecases {
+        if info.usecase(usecase).is_none() {
+            info.usecases.push(UsecaseInfo { name: usecase.clone(), kind });
+        }
+    }
+}
+
+/// Records usecases added to an existing record.
+pub async fn record_usecases(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    usecases: &[String],
+    kind: EncryptionKind,
+) -> Result<(), Error> {
+    if !has_new_usecase(transaction, collection, usecases).await? {
+        return Ok(());
+    }
+    update(transaction, collection, |info| add_usecases(info, usecases, kind)).await
+}
+
+/// Records that the data of the record stored at `data_key` changed from `old_size`
+/// to `new_size` bytes.
+pub async fn record_update(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    data_key: &str,
+    old_size: u64,
+    new_size: u64,
+) -> Result<(), Error> {
+    let bytes = new_size as i64 - old_size as i64;
+    count(transaction, collection, data_key, 0, bytes).await
+}
+
+/// Records the deletion of the record of `byte_size` bytes stored at `data_key`.
+pub async fn record_deletion(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    data_key: &str,
+    byte_size: u64,
+) -> Result<(), Error> {
+    count(transaction, collection, data_key, -1, -(byte_size as i64)).await
+}
+
+pub async fn remove_usecase(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    usecase: &str,
+) -> Result<(), Error> {
+    update(transaction, collection, |info| {
+        info.usecases.retain(|other| other.name != usecase);
+    })
+    .await
+}
+
+pub async fn remove(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+) -> Result<(), Error> {
+    transaction.delete(catalog_key(collection)).await?;
+    for shard in 0..COUNT_SHARDS {
+        transaction.delete(counts_key(shard, collection)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+    use crate::test_utils::create_user;
+
+    #[tokio::test]
+    async fn test_catalog_follows_records() {
+        let storage = EmbeddedBackend::in_memory();
+        let user = create_user(&storage, "alice", false, &[]).await;
+        let mut transaction = storage.begin().await.unwrap();
+        let transaction = transaction.as_mut();
+        let usecases = [String::from("red")];
+        for collection in ["fruits", "vegetables"] {
+            let data_key = format!("{}:1", collection);
+            record_insertion(
+                transaction,
+                &user,
+                collection,
+                &data_key,
+                &usecases,
+                EncryptionKind::Aes,
+                4,
+            )
+            .await
+            .unwrap();
+        }
+        let usecases = [String::from("red"), String::from("price")];
+        record_insertion(
+            transaction,
+            &user,
+            "fruits",
+            "fruits:2",
+            &usecases,
+            EncryptionKind::Ope,
+            2,
+        )
+        .await
+        .unwrap();
+        record_update(transaction, "fruits", "fruits:2", 2, 5).await.unwrap();
+        let usecases = [String::from("ripe")];
+        record_usecases(transaction, "fruits", &usecases, EncryptionKind::Aes)
+            .await
+            .unwrap();
+        record_deletion(transaction, "fruits", "fruits:1", 4).await.unwrap();
+        remove_usecase(transaction, "fruits", "red").await.unwrap();
+
+        let info = get(transaction, "fruits").await.unwrap().unwrap();
+        assert_eq!(info.owner, "alice");
+        assert_eq!((info.record_count, info.byte_size), (1, 5));
+        assert_eq!(
+            info.usecases,
+            [
+                UsecaseInfo {
+                    name: String::from("price"),
+                    kind: EncryptionKind::Ope
+                },
+                UsecaseInfo {
+                    name: String::from("ripe"),
+                    kind: EncryptionKind::Aes
+                },
+            ]
+        );
+        assert_eq!(list(transaction, &user).await.unwrap(), ["fruits", "vegetables"]);
+        let info = set_history(transaction, &user, "fruits", 2).await.unwrap();
+        assert_eq!((info.history, info.record_count), (2, 1));
+        remove(transaction, "fruits").await.unwrap();
+        assert_eq!(get(transaction, "fruits").await.unwrap(), None);
+        assert_eq!(list(transaction, &user).await.unwrap(), ["vegetables"]);
+        let counts = transaction.scan(
+            COUNTS_PREFIX.as_bytes().to_vec(),
+            b"__liserk:counts;".to_vec(),
+            u32::MAX,
+        );
+        let counts = counts.await.unwrap();
+        assert!(counts.iter().all(|(key, _)| key.ends_with(b":vegetables")));
+    }
+
+    #[tokio::test]
+    async fn test_writers_of_a_collection_dont_conflict() {
+        let storage = EmbeddedBackend::in_memory();
+        let user = create_user(&storage, "alice", false, &[]).await;
+        let usecases = [String::from("red")];
+        let mut transaction = storage.begin().await.unwrap();
+        record_insertion(
+            transaction.as_mut(),
+            &user,
+            "fruits",
+            "fruits:0",
+            &usecases,
+            EncryptionKind::Aes,
+            1,
+        )
+        .await
+        .unwrap();
+        transaction.commit().await.unwrap();
+
+        let data_keys = ["fruits:1", "fruits:2"];
+        assert_ne!(shard(data_keys[0]), shard(data_keys[1]));
+        let mut transactions = Vec::new();
+        for data_key in data_keys {
+            let mut transaction = storage.begin().await.unwrap();
+            record_insertion(
+                transaction.as_mut(),
+                &user,
+                "fruits",
+                data_key,
+                &usecases,
+                EncryptionKind::Aes,
+                1,
+            )
+            .await
+            .unwrap();
+            transactions.push(transaction);
+        }
+        for mut transaction in transactions {
+            transaction.commit().await.unwrap();
+        }
+
+        let mut transaction = storage.begin().await.unwrap();
+        let info = get(transaction.as_mut(), "fruits").await.unwrap().unwrap();
+        assert_eq!((info.record_count, info.byte_size), (3, 3));
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collections_of_other_users() {
+        let storage = EmbeddedBackend::in_memory();
+        let alice = create_user(&storage, "alice", false, &[]).await;
+        let bob = create_user(&storage, "bob", false, &[]).await;
+        let root = create_user(&storage, "root", true, &[]).await;
+        let mut transaction = storage.begin().await.unwrap();
+        let transaction = transaction.as_mut();
+        record_insertion(
+            transaction,
+            &bob,
+            "tools",
+            "tools:1",
+            &[],
+            EncryptionKind::Aes,
+            1,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            describe(transaction, &alice, "tools").await,
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            set_history(transaction, &alice, "tools", 1).await,
+            Err(Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            set_history(transaction, &alice, "fruits", 1).await,
+            Err(Error::CollectionNotFound(_))
+        ));
+        assert_eq!(describe(transaction, &alice, "fruits").await.unwrap(), None);
+        assert!(list(transaction, &alice).await.unwrap().is_empty());
+        let info = describe(transaction, &bob, "tools").await.unwrap().unwrap();
+        assert_eq!(info.record_count, 1);
+
+        let info = set_history(transaction, &root, "fruits", 1).await.unwrap();
+        assert_eq!((info.owner.as_str(), info.history), ("root", 1));
+        assert_eq!(list(transaction, &root).await.unwrap(), ["fruits", "tools"]);
+        assert_eq!(list(transaction, &bob).await.unwrap(), ["tools"]);
+    }
+}
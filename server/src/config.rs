@@ -1 +0,0 @@
-pub const TIKV_URL: &str = "127.0.0.1:2379";
@@ -0,0 +1,184 @@
+use std::fs;
+
+use aes_gcm_siv::{
+    aead::{generic_array::GenericArray, Aead},
+    Aes256GcmSiv, KeyInit,
+};
+use chrono::{Duration, Utc};
+use futures::{SinkExt, StreamExt};
+use liserk_shared::certificate::Certificate;
+use liserk_shared::codec::LiserkCodec;
+use liserk_shared::message::{
+    ClientSetupSecureConnection, Message, ServerSetupSecureConnection,
+};
+use liserk_shared::message_type::PROTOCOL_VERSION;
+use liserk_shared::session::{CipherSuite, HandshakeError, SessionCipher, SessionKeys};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::settings::CertificatesConfig;
+use crate::Error;
+
+/// Size of the nonce stored in front of the encrypted Kyber secret key.
+const KEY_FILE_NONCE_SIZE: usize = 12;
+
+/// Certificate of the server and the Kyber secret key matching it.
+#[derive(Clone)]
+pub struct ServerIdentity {
+    certificate: Certificate,
+    kyber_secret_key: Vec<u8>,
+}
+
+impl ServerIdentity {
+    pub fn new(certificate: Certificate, kyber_secret_key: Vec<u8>) -> Self {
+        Self { certificate, kyber_secret_key }
+    }
+
+    /// Reads the files written by the certificate authority.
+    ///
+    /// The Kyber secret key file holds a nonce followed by the key encrypted with
+    /// `config.aes_key`.
+    pub fn load(config: &CertificatesConfig) -> Result<Self, Error> {
+        let certificate = fs::read_to_string(&config.certificate_path)?;
+        let certificate: Certificate = toml::from_str(&certificate)?;
+
+        let encrypted = fs::read(&config.kyber_secret_key_path)?;
+        if encrypted.len() <= KEY_FILE_NONCE_SIZE || config.aes_key.len() != 32 {
+            return Err(Error::InvalidIdentity);
+        }
+        let (nonce, encrypted) = encrypted.split_at(KEY_FILE_NONCE_SIZE);
+        let cipher = Aes256GcmSiv::new(GenericArray::from_slice(&config.aes_key));
+        let kyber_secret_key = cipher
+            .decrypt(GenericArray::from_slice(nonce), encrypted)
+            .map_err(|_| Error::InvalidIdentity)?;
+        info!("loaded certificate {}", certificate.serial_number);
+        Ok(Self::new(certificate, kyber_secret_key))
+    }
+
+    /// Creates a throwaway identity for tests, `sign` returns the signature of the
+    /// [`Certificate::signed_content`] by the test authority.
+    pub fn generate(sign: impl FnOnce(&[u8]) -> Vec<u8>) -> Self {
+        let keys = pqc_kyber::keypair(&mut rand::thread_rng());
+        let now = Utc::now();
+        let mut certificate = Certificate {
+            public_key: keys.public.to_vec(),
+            identity_info: String::from("Server"),
+            issuer_info: String::from("Test authority"),
+            signature: vec![],
+            valid_from: now,
+            valid_to: now + Duration::days(1),
+            serial_number: Uuid::new_v4(),
+            cipher_suits: vec![CipherSuite::Kyber768Aes256GcmSiv.to_string()],
+        };
+        certificate.signature = sign(&certificate.signed_content());
+        Self::new(certificate, keys.secret.to_vec())
+    }
+
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+}
+
+/// Runs the server side of the handshake described in [`liserk_shared::session`] and
+/// turns on the encryption of both halves of the connection.
+pub async fn handshake(
+    read: &mut FramedRead<OwnedReadHalf, LiserkCodec>,
+    write: &mut FramedWrite<OwnedWriteHalf, LiserkCodec>,
+    identity: &ServerIdentity,
+) -> Result<(), Error> {
+    let setup = match read.next().await.ok_or(HandshakeError::ConnectionClosed)?? {
+        Message::ClientSetup(setup) => setup,
+        message => {
+            return Err(HandshakeError::UnexpectedMessage(message.message_type()).into())
+        }
+    };
+    debug!("client setup: {:?}", setup);
+    let ClientSetupSecureConnection {
+        protocol_version,
+        client_public_key,
+        cipher_suits,
+        ..
+    } = setup;
+    if protocol_version != PROTOCOL_VERSION as u32 {
+        return Err(HandshakeError::UnsupportedVersion(protocol_version).into());
+    }
+    let cipher_suit = CipherSuite::negotiate(&cipher_suits)
+        .ok_or(HandshakeError::NoCommonCipherSuite(cipher_suits))?;
+
+    let (ciphertext, client_secret) =
+        pqc_kyber::encapsulate(&client_public_key, &mut rand::thread_rng())
+            .map_err(|_| HandshakeError::KeyEncapsulation)?;
+    let session_id = Uuid::new_v4();
+    let server_setup = ServerSetupSecureConnection {
+        protocol_version: PROTOCOL_VERSION as u32,
+        certificate: identity.certificate.clone(),
+        ciphertext: ciphertext.to_vec(),
+        session_id,
+        cipher_suit: cipher_suit.to_string(),
+        compression: String::from("0"),
+    };
+    write.send(Message::ServerSetup(Box::new(server_setup))).await?;
+
+    let exchange = match read.next().await.ok_or(HandshakeError::ConnectionClosed)?? {
+        Message::ClientKeyExchange(exchange) => exchange,
+        message => {
+            return Err(HandshakeError::UnexpectedMessage(message.message_type()).into())
+        }
+    };
+    let server_secret =
+        pqc_kyber::decapsulate(&exchange.ciphertext, &identity.kyber_secret_key)
+            .map_err(|_| HandshakeError::KeyEncapsulation)?;
+
+    let keys = SessionKeys::derive(&[&client_secret, &server_secret], session_id);
+    read.decoder_mut()
+        .decrypt_with(SessionCipher::new(&keys.client_to_server));
+    write
+        .encoder_mut()
+        .encrypt_with(SessionCipher::new(&keys.server_to_client));
+    info!("session {} established with {}", session_id, cipher_suit);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_load_files_of_the_certificate_authority() {
+        let generated = ServerIdentity::generate(|_| vec![]);
+        let aes_key = vec![9; 32];
+        let nonce = [4; KEY_FILE_NONCE_SIZE];
+        let cipher = Aes256GcmSiv::new(GenericArray::from_slice(&aes_key));
+        let encrypted = cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                generated.kyber_secret_key.as_ref(),
+            )
+            .unwrap();
+
+        let directory = env::temp_dir().join(format!("liserk-{}", Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        let config = CertificatesConfig {
+            certificate_path: directory.join("certificate.crt"),
+            kyber_secret_key_path: directory.join("encrypted.kyber"),
+            aes_key,
+        };
+        let certificate = toml::to_string(&generated.certificate).unwrap();
+        fs::write(&config.certificate_path, certificate).unwrap();
+        fs::write(&config.kyber_secret_key_path, [&nonce[..], &encrypted].concat())
+            .unwrap();
+
+        let loaded = ServerIdentity::load(&config).unwrap();
+        let wrong_key = CertificatesConfig { aes_key: vec![1; 32], ..config.clone() };
+        let refused = ServerIdentity::load(&wrong_key);
+        fs::remove_dir_all(directory).unwrap();
+
+        assert_eq!(loaded.certificate, generated.certificate);
+        assert_eq!(loaded.kyber_secret_key, generated.kyber_secret_key);
+        assert!(matches!(refused, Err(Error::InvalidIdentity)));
+    }
+}
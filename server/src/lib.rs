@@ -1,32 +1,87 @@
+use futures::{SinkExt, StreamExt};
+use liserk_shared::codec::{CodecError, LiserkCodec};
+use liserk_shared::error::ErrorCode;
 use liserk_shared::message::Message;
 use liserk_shared::message_type::MessageType;
-use serde::{Deserialize, Serialize};
+use liserk_shared::session::HandshakeError;
 use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{io, net::SocketAddr};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::OwnedReadHalf;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, info, trace};
-use uuid::Uuid;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tracing::{debug, error, info};
 
+use crate::auth::ClientSession;
 use crate::command::Command;
-use crate::message_parsing::parse_message;
+use crate::handshake::{handshake, ServerIdentity};
+use crate::message_parsing::{parse_message, send_error};
+use crate::settings::ServerConfig;
+use crate::storage::{StorageBackend, StorageError};
 
+/// Default address the server listens on.
 pub const BINDED_URL_PORT: &str = "127.0.0.1:5545";
 
+mod acl;
+pub mod auth;
+mod catalog;
 mod command;
-mod config;
+pub mod handshake;
 mod message_parsing;
 mod mutation;
+mod ore_index;
+mod planner;
 mod query_engine;
+mod range_index;
+mod record_index;
+pub mod settings;
+pub mod storage;
+#[cfg(test)]
+mod test_utils;
+mod version;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     TokioIo(#[from] tokio::io::Error),
     ChannelSend(#[from] async_channel::SendError<Message>),
     Parsing(#[from] serde_cbor::Error),
-    Storage(#[from] tikv_client::Error),
+    Codec(#[from] CodecError),
+    Storage(#[from] storage::StorageError),
     Float(#[from] rug::float::ParseFloatError),
+    /// The client sent a message only the server is supposed to send.
+    UnexpectedMessage(MessageType),
+    /// The request is known but not implemented by this server.
+    Unsupported(MessageType),
+    /// The update of a record indexed by OPE or ORE, whose range index entries can't
+    /// follow a new value encrypted with AES.
+    UnsupportedUpdate(String),
+    /// The query can't be evaluated as it is built.
+    InvalidQuery(String),
+    /// An OPE ciphertext is not a non-negative integer which fits in the range index,
+    /// or an ORE ciphertext is malformed.
+    InvalidCiphertext,
+    Handshake(#[from] HandshakeError),
+    Certificate(#[from] toml::de::Error),
+    /// The Kyber secret key file can't be decrypted with the configured key.
+    InvalidIdentity,
+    /// The request needs an authenticated connection.
+    NotAuthenticated(MessageType),
+    /// The request is reserved to administrators: managing users or dropping data.
+    AdminRequired,
+    /// The current password given to change a password is wrong.
+    InvalidCredentials,
+    UserNotFound(String),
+    UserAlreadyExists(String),
+    CollectionNotFound(String),
+    /// The ACL of the record does not give the needed permission to the user.
+    AccessDenied(String),
+    /// The collection starts with the prefix of the keys of the server.
+    ReservedCollection(String),
+    /// A collection or a usecase contains `:`, which separates the parts of the keys.
+    InvalidName(String),
+    PasswordHash(#[from] argon2::password_hash::Error),
 }
 
 impl Display for Error {
@@ -34,33 +89,178 @@ impl Display for Error {
         match self {
             Error::TokioIo(_) => write!(f, "Tokio IO Error"),
             Error::Parsing(_) => write!(f, "Parsing Error serde"),
+            Error::Codec(err) => write!(f, "Invalid frame {}", err),
             Error::Storage(err) => write!(f, "Error with storage layer {}", err),
             Error::Float(err) => write!(f, "Error parsing float {}", err),
             Error::ChannelSend(sender_error) => {
                 write!(f, "ChannelSenderError {}", sender_error)
             }
+            Error::UnexpectedMessage(message_type) => {
+                write!(f, "Unexpected message {} sent to the server", message_type)
+            }
+            Error::Unsupported(message_type) => {
+                write!(f, "Message {} is not supported", message_type)
+            }
+            Error::UnsupportedUpdate(key) => {
+                write!(f, "Update of {} is not supported, it is range-indexed", key)
+            }
+            Error::InvalidQuery(reason) => write!(f, "Invalid query: {}", reason),
+            Error::InvalidCiphertext => write!(f, "Invalid OPE or ORE ciphertext"),
+            Error::Handshake(err) => write!(f, "Handshake failed {}", err),
+            Error::Certificate(err) => write!(f, "Invalid certificate file {}", err),
+            Error::InvalidIdentity => write!(f, "Invalid kyber secret key file"),
+            Error::NotAuthenticated(message_type) => {
+                write!(f, "Message {} requires authentication", message_type)
+            }
+            Error::AdminRequired => {
+                write!(f, "Only administrators can manage users and drop data")
+            }
+            Error::InvalidCredentials => write!(f, "Invalid credentials"),
+            Error::UserNotFound(username) => write!(f, "User {} not found", username),
+            Error::UserAlreadyExists(username) => {
+                write!(f, "User {} already exists", username)
+            }
+            Error::CollectionNotFound(collection) => {
+                write!(f, "Collection {} not found", collection)
+            }
+            Error::AccessDenied(key) => write!(f, "Access to {} denied", key),
+            Error::ReservedCollection(collection) => {
+                write!(f, "Collection {} is reserved to the server", collection)
+            }
+            Error::InvalidName(name) => write!(f, "Name {} can't contain ':'", name),
+            Error::PasswordHash(err) => write!(f, "Password hashing failed {}", err),
+        }
+    }
+}
+
+impl Error {
+    /// Code reported to the client when a request fails with this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Codec(CodecError::Io(_)) => ErrorCode::Internal,
+            Error::Parsing(_)
+            | Error::Codec(_)
+            | Error::Float(_)
+            | Error::UnexpectedMessage(_)
+            | Error::InvalidQuery(_)
+            | Error::InvalidCiphertext
+            | Error::InvalidName(_) => ErrorCode::MalformedRequest,
+            Error::Unsupported(_)
+            | Error::UnsupportedUpdate(_)
+            | Error::Handshake(
+                HandshakeError::UnsupportedVersion(_)
+                | HandshakeError::NoCommonCipherSuite(_),
+            ) => ErrorCode::Unsupported,
+            Error::Handshake(_) => ErrorCode::MalformedRequest,
+            Error::NotAuthenticated(_)
+            | Error::AdminRequired
+            | Error::InvalidCredentials
+            | Error::AccessDenied(_)
+            | Error::ReservedCollection(_) => ErrorCode::PermissionDenied,
+            Error::UserNotFound(_) | Error::CollectionNotFound(_) => ErrorCode::NotFound,
+            Error::Storage(
+                StorageError::Conflict(_) | StorageError::AlreadyExists(_),
+            )
+            | Error::UserAlreadyExists(_) => ErrorCode::Conflict,
+            Error::Storage(StorageError::Tikv(_) | StorageError::Io(_)) => {
+                ErrorCode::StorageUnavailable
+            }
+            Error::Storage(_)
+            | Error::TokioIo(_)
+            | Error::ChannelSend(_)
+            | Error::Certificate(_)
+            | Error::InvalidIdentity
+            | Error::PasswordHash(_) => ErrorCode::Internal,
         }
     }
 }
 
-async fn on_new_client(socket: TcpStream, _addr: &SocketAddr) -> Result<(), Error> {
+async fn on_new_client(
+    socket: TcpStream,
+    addr: &SocketAddr,
+    storage: Arc<dyn StorageBackend>,
+    identity: Arc<ServerIdentity>,
+    codec: LiserkCodec,
+    idle_timeout: Option<Duration>,
+    max_attempts: u32,
+) -> Result<(), Error> {
     let (tx, rx) = async_channel::unbounded::<Message>();
-    let (mut read, mut write) = socket.into_split();
+    let (read, write) = socket.into_split();
+    let mut read = FramedRead::new(read, codec.clone());
+    let mut write = FramedWrite::new(write, codec);
+
+    let handshake = handshake(&mut read, &mut write, &identity);
+    let established = match idle_timeout {
+        Some(idle_timeout) => timeout(idle_timeout, handshake)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut).into())),
+        None => handshake.await,
+    };
+    if let Err(err) = established {
+        let response = Message::Error {
+            code: err.code(),
+            message: err.to_string(),
+            request_id: 0,
+        };
+        if let Err(err) = write.send(response).await {
+            error!("failed to report handshake failure: {}", err);
+        }
+        return Err(err);
+    }
 
     tokio::spawn(async move {
-        loop {
-            let message = rx.recv().await.expect("failed to recieve message");
+        while let Ok(message) = rx.recv().await {
             if message == Message::CloseCommunication {
-                write.shutdown().await.expect("failed to shutdown communication");
+                if let Err(err) = write.close().await {
+                    error!("failed to shutdown communication: {}", err);
+                }
+                break;
+            }
+            if let Err(err) = write.send(message).await {
+                error!("failed to send message: {}", err);
                 break;
             }
-            let message = message.setup_for_network().unwrap();
-            write.write(&message).await.unwrap();
         }
     });
+    let mut session = ClientSession::new(max_attempts);
+    // Index of the current message, reported back in `Message::Error`.
+    let mut request_id = 0;
     loop {
-        let message = parse_message_from_tcp_stream(&mut read).await?;
-        let command = parse_message(message, tx.clone()).await;
+        let frame = match idle_timeout {
+            Some(idle_timeout) => match timeout(idle_timeout, read.next()).await {
+                Ok(frame) => frame,
+                Err(_) => {
+                    info!("closing idle connection {}", addr);
+                    tx.send(Message::CloseCommunication).await?;
+                    break;
+                }
+            },
+            None => read.next().await,
+        };
+        let message = match frame {
+            Some(Ok(message)) => message,
+            Some(Err(CodecError::Io(err))) => return Err(err.into()),
+            Some(Err(err)) => {
+                // The stream can't be trusted anymore, report and hang up.
+                send_error(&tx, request_id, err.into()).await;
+                tx.send(Message::CloseCommunication).await?;
+                break;
+            }
+            None => {
+                info!("connection closed by {}", addr);
+                break;
+            }
+        };
+        debug!("parsed message: {:#?}", message);
+        let command = parse_message(
+            message,
+            request_id,
+            tx.clone(),
+            storage.as_ref(),
+            &mut session,
+        )
+        .await;
+        request_id += 1;
         info!("message parsing end communication: {:?}", command);
         if command == Command::Exit {
             break;
@@ -69,46 +269,57 @@ async fn on_new_client(socket: TcpStream, _addr: &SocketAddr) -> Result<(), Erro
     Ok(())
 }
 
-async fn parse_message_from_tcp_stream(
-    stream: &mut OwnedReadHalf,
-) -> Result<Message, Error> {
-    let mut buffer = [0; 1];
-    let _ = stream.read(&mut buffer).await;
-    let message_type = MessageType::try_from(buffer[0]);
-    info!("messageType: {:?}", message_type);
-
-    let mut message_size = [0; 4];
-    let _size_error = stream.read(&mut message_size).await;
-    let decimal_size = u32::from_be_bytes(message_size);
-    trace!("message size: {}", decimal_size);
-
-    let mut slice = vec![0; decimal_size as usize];
-    let _size_read = stream.read_exact(&mut slice).await;
-    trace!("slice: {:?}", slice);
-    let message: Message = serde_cbor::from_slice(&slice)?;
-    debug!("parsed message: {:#?}", message);
-    Ok(message)
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
-struct Authentification {
-    protocol_version: u32,
-    server_public_key: Vec<u8>,
-    session_id: Uuid,
-    cipher_suits: Vec<String>,
-    compression: String,
+/// Starts the server with the storage backend and the certificate selected in `config`.
+pub async fn run_app(config: ServerConfig) -> io::Result<()> {
+    let identity =
+        ServerIdentity::load(&config.certificates).map_err(io::Error::other)?;
+    let storage = storage::open(&config.storage).await.map_err(io::Error::other)?;
+    run_app_with_storage(config, storage, identity).await
 }
 
-pub async fn run_app() -> io::Result<()> {
-    let listener = TcpListener::bind(BINDED_URL_PORT).await?;
-    info!("Server started, listening on {}", BINDED_URL_PORT);
+/// Starts the server with the given storage backend and identity, ignoring
+/// `config.storage` and `config.certificates`.
+pub async fn run_app_with_storage(
+    config: ServerConfig,
+    storage: Arc<dyn StorageBackend>,
+    identity: ServerIdentity,
+) -> io::Result<()> {
+    auth::ensure_admin(storage.as_ref(), &config.auth)
+        .await
+        .map_err(io::Error::other)?;
+    let identity = Arc::new(identity);
+    let listener = TcpListener::bind(&config.listen_address).await?;
+    info!("Server started, listening on {}", config.listen_address);
 
+    let connections = Arc::new(Semaphore::new(config.limits.max_connections));
+    let codec = LiserkCodec::new(config.limits.max_frame_size);
+    let idle_timeout =
+        (config.timeouts.idle > 0).then(|| Duration::from_secs(config.timeouts.idle));
+    let max_attempts = config.auth.max_attempts;
     loop {
+        let permit = connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
         let (socket, addr) = listener.accept().await?;
+        let storage = storage.clone();
+        let identity = identity.clone();
+        let codec = codec.clone();
         tokio::spawn(async move {
-            match on_new_client(socket, &addr).await {
-                Ok(_) => println!("c'est ok"),
-                Err(err) => eprintln!("err: {}", err),
+            let _permit = permit;
+            let client = on_new_client(
+                socket,
+                &addr,
+                storage,
+                identity,
+                codec,
+                idle_timeout,
+                max_attempts,
+            );
+            match client.await {
+                Ok(_) => info!("connection from {} closed", addr),
+                Err(err) => error!("connection from {} failed: {}", addr, err),
             };
         });
     }
@@ -1,16 +1,33 @@
+use clap::Parser;
 use liserk_server::run_app;
+use liserk_server::settings::{Cli, LogFormat, LoggingConfig, ServerConfig};
 use std::io;
 use tracing::{error, Level};
 use tracing_subscriber::FmtSubscriber;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let subscriber = FmtSubscriber::builder().with_max_level(Level::TRACE).finish();
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("setting default subscriber failed");
-    match run_app().await {
+    let cli = Cli::parse();
+    let config = ServerConfig::load(&cli)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    setup_logging(&config.logging);
+    match run_app(config).await {
         Ok(_) => {} // Do nothing
         Err(err) => error!("{:?}", err),
     }
     Ok(())
 }
+
+fn setup_logging(logging: &LoggingConfig) {
+    let builder = FmtSubscriber::builder().with_max_level(Level::from(logging.level));
+    let result = match logging.format {
+        LogFormat::Full => tracing::subscriber::set_global_default(builder.finish()),
+        LogFormat::Compact => {
+            tracing::subscriber::set_global_default(builder.compact().finish())
+        }
+        LogFormat::Pretty => {
+            tracing::subscriber::set_global_default(builder.pretty().finish())
+        }
+    };
+    result.expect("setting default subscriber failed");
+}
@@ -1,117 +1,883 @@
 use async_channel::Sender;
 use liserk_shared::message::{
-    ClientAuthentication, ClientSetupSecureConnection, CountSubject, Delete, Insertion,
-    InsertionOpe, Message, Update,
+    AuthenticationResponse, ClientAuthentication, CountSubject, Delete, DropSubject,
+    Insertion, InsertionOpe, InsertionOre, Message, NewUser, PasswordChange, Update,
 };
 use liserk_shared::query::Query;
 use tracing::debug;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+use crate::auth::{self, ClientSession, User};
+use crate::catalog;
 use crate::command::Command;
 use crate::mutation;
 use crate::query_engine;
+use crate::storage::StorageBackend;
+use crate::version;
+use crate::Error;
 
-pub async fn parse_message(message: Message, tx: Sender<Message>) -> Command {
+pub async fn parse_message(
+    message: Message,
+    request_id: u64,
+    tx: Sender<Message>,
+    storage: &dyn StorageBackend,
+    session: &mut ClientSession,
+) -> Command {
+    let message_type = message.message_type();
+    let result = match message {
+        Message::ClientAuthentification(param) => {
+            authenticate(storage, param, session, &tx).await
+        }
+        Message::EndOfCommunication => Ok(end_communication(&tx).await),
+        Message::ClientSetup(_)
+        | Message::ServerSetup(_)
+        | Message::ClientKeyExchange(_)
+        | Message::AuthenticationResponse(_)
+        | Message::UserResponse { .. }
+        | Message::DeleteResult(_)
+        | Message::InsertResponse { .. }
+        | Message::QueryResponse { .. }
+        | Message::SingleValueResponse { .. }
+        | Message::ExplainResponse(_)
+        | Message::CloseCommunication
+        | Message::UpdateResponse { .. }
+        | Message::DropProgress { .. }
+        | Message::DropResult(_)
+        | Message::CountResponse(_)
+        | Message::CollectionList(_)
+        | Message::CollectionDescription(_)
+        | Message::Error { .. } => Err(Error::UnexpectedMessage(message_type)),
+        message => match session.user() {
+            Some(user) => parse_request(message, user, &tx, storage).await,
+            None => Err(Error::NotAuthenticated(message_type)),
+        },
+    };
+    match result {
+        Ok(command) => command,
+        Err(err) => {
+            send_error(&tx, request_id, err).await;
+            Command::Continue
+        }
+    }
+}
+
+/// Handles a request of an authenticated client.
+async fn parse_request(
+    message: Message,
+    user: &User,
+    tx: &Sender<Message>,
+    storage: &dyn StorageBackend,
+) -> Result<Command, Error> {
+    let message_type = message.message_type();
     match message {
-        Message::ClientSetup(param) => parse_client_setup(param),
-        Message::ClientAuthentification(param) => parse_authentification(param),
-        Message::Insert(param) => insert(param, tx).await,
-        Message::InsertOpe(param) => insert_ope(param, tx).await,
-        Message::Query(param) => handle_query(param, tx).await,
-        Message::Count(param) => count(param, tx).await,
-        Message::Update(param) => update(param, tx).await,
-        Message::Delete(param) => delete(param, tx).await,
-        Message::DeleteForUsecase { .. } => todo!(),
-        Message::Drop(_) => todo!(),
-        Message::EndOfCommunication => end_communication(tx).await,
-        Message::DeleteResult(_) => unreachable!(),
-        Message::InsertResponse { .. } => unreachable!(),
-        Message::QueryResponse { .. } => unreachable!(),
-        Message::SingleValueResponse { .. } => unreachable!(),
-        Message::CloseCommunication => unreachable!(),
-        Message::UpdateResponse { .. } => unreachable!(),
-        Message::DropResult(_) => unreachable!(),
-        Message::CountResponse(_) => todo!(),
-    }
-}
-
-async fn count(param: CountSubject, tx: Sender<Message>) -> Command {
-    let command = query_engine::count(param, tx).await;
-    if command.is_err() {
-        error!("error in count: {:?}", command.unwrap_err());
-        return Command::Continue;
-    }
-    command.expect("error checked before")
-}
-
-async fn update(query: Update, tx: Sender<Message>) -> Command {
-    let status = match mutation::update(query).await {
-        Ok(status) => status,
-        Err(_) => liserk_shared::message::UpdateStatus::Failure,
+        Message::Insert(param) => insert(storage, user, param, tx).await,
+        Message::InsertOpe(param) => insert_ope(storage, user, param, tx).await,
+        Message::InsertOre(param) => insert_ore(storage, user, param, tx).await,
+        Message::Query(param) => handle_query(storage, user, param, tx).await,
+        Message::Count(param) => count(storage, user, param, tx).await,
+        Message::Update(param) => update(storage, user, param, tx).await,
+        Message::Delete(param) => delete(storage, user, param, tx).await,
+        Message::DeleteForUsecase { collection, usecase, id } => {
+            delete_for_usecase(storage, user, collection, usecase, id, tx).await
+        }
+        Message::Drop(param) => drop(storage, user, param, tx).await,
+        Message::ListCollections => list_collections(storage, user, tx).await,
+        Message::DescribeCollection { collection } => {
+            describe_collection(storage, user, collection, tx).await
+        }
+        Message::SetHistory { collection, versions } => {
+            set_history(storage, user, collection, versions, tx).await
+        }
+        Message::CreateUser(param) => create_user(storage, user, param, tx).await,
+        Message::DeleteUser { username } => {
+            delete_user(storage, user, username, tx).await
+        }
+        Message::ChangePassword(param) => change_password(storage, user, param, tx).await,
+        _ => Err(Error::UnexpectedMessage(message_type)),
+    }
+}
+
+/// Reports a failed request to the client.
+pub async fn send_error(tx: &Sender<Message>, request_id: u64, err: Error) {
+    error!("request {} failed: {}", request_id, err);
+    let response = Message::Error {
+        code: err.code(),
+        message: err.to_string(),
+        request_id,
     };
-    if let Err(err) = tx.send(Message::UpdateResponse { status }).await {
-        error!("err while sending update response: {:?}", err);
+    if let Err(err) = tx.send(response).await {
+        error!("err while sending error response: {:?}", err);
     }
-    Command::Continue
 }
 
-async fn delete(delete: Delete, tx: Sender<Message>) -> Command {
-    let result = mutation::delete(delete).await.unwrap_or(false);
-    if let Err(err) = tx.send(Message::DeleteResult(result)).await {
-        error!("delete message: {:?}", err);
+async fn count(
+    storage: &dyn StorageBackend,
+    user: &User,
+    param: CountSubject,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    query_engine::count(storage, user, param, tx).await
+}
+
+async fn update(
+    storage: &dyn StorageBackend,
+    user: &User,
+    query: Update,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let status = mutation::update(storage, user, query).await?;
+    tx.send(Message::UpdateResponse { status }).await?;
+    Ok(Command::Continue)
+}
+
+async fn delete(
+    storage: &dyn StorageBackend,
+    user: &User,
+    delete: Delete,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let status = mutation::delete(storage, user, delete).await?;
+    tx.send(Message::DeleteResult(status)).await?;
+    Ok(Command::Continue)
+}
+
+async fn delete_for_usecase(
+    storage: &dyn StorageBackend,
+    user: &User,
+    collection: String,
+    usecase: String,
+    id: String,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let status =
+        mutation::delete_for_usecase(storage, user, collection, usecase, id).await?;
+    tx.send(Message::DeleteResult(status)).await?;
+    Ok(Command::Continue)
+}
+
+/// Drops a collection or a usecase, reserved to administrators as it ignores the ACL
+/// of the records.
+async fn drop(
+    storage: &dyn StorageBackend,
+    caller: &User,
+    subject: DropSubject,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    if !caller.admin {
+        return Err(Error::AdminRequired);
     }
-    Command::Continue
+    let dropped = match subject {
+        DropSubject::Collection(collection) => {
+            mutation::drop_collection(storage, &collection, tx).await?
+        }
+        DropSubject::Usecase { collection, usecase, records } => {
+            mutation::drop_usecase(storage, &collection, &usecase, records, tx).await?
+        }
+    };
+    tx.send(Message::DropResult(dropped > 0)).await?;
+    Ok(Command::Continue)
+}
+
+/// Lists the collections owned by the caller, or all of them for an administrator.
+async fn list_collections(
+    storage: &dyn StorageBackend,
+    user: &User,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let mut transaction = storage.begin().await?;
+    let collections = catalog::list(transaction.as_mut(), user).await?;
+    transaction.commit().await?;
+    tx.send(Message::CollectionList(collections)).await?;
+    Ok(Command::Continue)
 }
 
-fn parse_authentification(authentification: ClientAuthentication) -> Command {
-    info!("authentification: {:?}", authentification);
-    Command::Continue
+async fn describe_collection(
+    storage: &dyn StorageBackend,
+    user: &User,
+    collection: String,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let mut transaction = storage.begin().await?;
+    let info = catalog::describe(transaction.as_mut(), user, &collection).await?;
+    transaction.commit().await?;
+    tx.send(Message::CollectionDescription(info)).await?;
+    Ok(Command::Continue)
 }
 
-fn parse_client_setup(secure_connection_message: ClientSetupSecureConnection) -> Command {
-    info!("secure message: {:?}", secure_connection_message);
-    Command::Continue
+async fn set_history(
+    storage: &dyn StorageBackend,
+    user: &User,
+    collection: String,
+    versions: u32,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let mut transaction = storage.begin().await?;
+    let info =
+        catalog::set_history(transaction.as_mut(), user, &collection, versions).await?;
+    transaction.commit().await?;
+    tx.send(Message::CollectionDescription(Some(info))).await?;
+    Ok(Command::Continue)
 }
 
-async fn end_communication(tx: Sender<Message>) -> Command {
+async fn authenticate(
+    storage: &dyn StorageBackend,
+    credentials: ClientAuthentication,
+    session: &mut ClientSession,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let username = credentials.username;
+    let response =
+        match auth::authenticate(storage, &username, &credentials.password).await? {
+            Some(user) => {
+                let session_id = session.authenticate(user);
+                info!("{} authenticated, session {}", username, session_id);
+                AuthenticationResponse::Success { session_id }
+            }
+            None => {
+                session.fail();
+                warn!("authentication of {} failed", username);
+                AuthenticationResponse::Failure
+            }
+        };
+    tx.send(Message::AuthenticationResponse(response)).await?;
+    if session.attempts_exhausted() {
+        warn!("too many failed authentications, closing the connection");
+        tx.send(Message::CloseCommunication).await?;
+        return Ok(Command::Exit);
+    }
+    Ok(Command::Continue)
+}
+
+async fn create_user(
+    storage: &dyn StorageBackend,
+    caller: &User,
+    new_user: NewUser,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    if !caller.admin {
+        return Err(Error::AdminRequired);
+    }
+    let user = auth::create_user(storage, new_user).await?;
+    tx.send(Message::UserResponse { username: user.username }).await?;
+    Ok(Command::Continue)
+}
+
+async fn delete_user(
+    storage: &dyn StorageBackend,
+    caller: &User,
+    username: String,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    if !caller.admin {
+        return Err(Error::AdminRequired);
+    }
+    auth::delete_user(storage, &username).await?;
+    tx.send(Message::UserResponse { username }).await?;
+    Ok(Command::Continue)
+}
+
+async fn change_password(
+    storage: &dyn StorageBackend,
+    caller: &User,
+    change: PasswordChange,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let username = change.username.clone();
+    auth::change_password(storage, caller, change).await?;
+    tx.send(Message::UserResponse { username }).await?;
+    Ok(Command::Continue)
+}
+
+async fn end_communication(tx: &Sender<Message>) -> Command {
     if let Err(err) = tx.send(Message::CloseCommunication).await {
         error!("err while shutdown communication: {:?}", err);
     }
     Command::Exit
 }
 
-async fn insert(insertion: Insertion, tx: Sender<Message>) -> Command {
-    match mutation::insert(insertion).await {
-        Ok(inserted_id) => {
-            debug!("inserted uuid: {}", inserted_id);
-            if let Err(err) = tx.send(Message::InsertResponse { inserted_id }).await {
-                error!("err: {:?}", err);
+async fn insert(
+    storage: &dyn StorageBackend,
+    user: &User,
+    insertion: Insertion,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let inserted_id = mutation::insert(storage, user, insertion).await?;
+    debug!("inserted uuid: {}", inserted_id);
+    let version = version::FIRST_VERSION;
+    tx.send(Message::InsertResponse { inserted_id, version }).await?;
+    Ok(Command::Continue)
+}
+
+async fn insert_ope(
+    storage: &dyn StorageBackend,
+    user: &User,
+    insertion: InsertionOpe,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let inserted_id = mutation::insert_ope(storage, user, insertion).await?;
+    debug!("inserted uuid: {}", inserted_id);
+    let version = version::FIRST_VERSION;
+    tx.send(Message::InsertResponse { inserted_id, version }).await?;
+    Ok(Command::Continue)
+}
+
+async fn insert_ore(
+    storage: &dyn StorageBackend,
+    user: &User,
+    insertion: InsertionOre,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let inserted_id = mutation::insert_ore(storage, user, insertion).await?;
+    debug!("inserted uuid: {}", inserted_id);
+    let version = version::FIRST_VERSION;
+    tx.send(Message::InsertResponse { inserted_id, version }).await?;
+    Ok(Command::Continue)
+}
+
+async fn handle_query(
+    storage: &dyn StorageBackend,
+    user: &User,
+    query: Query,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    query_engine::handle_query(storage, user, query, tx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use liserk_shared::acl::{AclEntry, Permission};
+    use liserk_shared::catalog::EncryptionKind;
+    use liserk_shared::error::ErrorCode;
+    use liserk_shared::message::{DeleteStatus, UpdateOptions, UpdateStatus};
+    use liserk_shared::query::{CompoundQuery, QueryType, SingleQuery};
+
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+    use crate::test_utils::{authenticated_session, create_user, PASSWORD};
+
+    fn error_code(message: Message, expected_request_id: u64) -> ErrorCode {
+        match message {
+            Message::Error { code, request_id, .. } => {
+                assert_eq!(request_id, expected_request_id);
+                code
             }
+            message => panic!("unexpected response {:?}", message),
+        }
+    }
+
+    fn insertion(usecases: &[&str]) -> Insertion {
+        Insertion {
+            collection: String::from("fruits"),
+            acl: vec![],
+            data: vec![1],
+            usecases: usecases.iter().map(|usecase| usecase.to_string()).collect(),
+            nonce: vec![0; 12],
+            searchable: false,
         }
-        Err(err) => debug!("{:?}", err),
     }
-    Command::Continue
-}
 
-async fn insert_ope(insertion: InsertionOpe, tx: Sender<Message>) -> Command {
-    match mutation::insert_ope(insertion).await {
-        Ok(inserted_id) => {
-            debug!("inserted uuid: {}", inserted_id);
-            if let Err(err) = tx.send(Message::InsertResponse { inserted_id }).await {
-                error!("err: {:?}", err);
+    #[tokio::test]
+    async fn test_failed_request_is_reported() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, false).await;
+        let (tx, rx) = async_channel::unbounded();
+
+        let message =
+            Message::InsertResponse { inserted_id: String::from("id"), version: 1 };
+        let command = parse_message(message, 3, tx.clone(), &storage, &mut session).await;
+        assert_eq!(command, Command::Continue);
+        assert_eq!(error_code(rx.recv().await.unwrap(), 3), ErrorCode::MalformedRequest);
+
+        let message = Message::Drop(DropSubject::Collection(String::from("users")));
+        parse_message(message, 4, tx, &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 4), ErrorCode::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_requests_need_authentication() {
+        let storage = EmbeddedBackend::in_memory();
+        authenticated_session(&storage, false).await;
+        let mut session = ClientSession::new(2);
+        let (tx, rx) = async_channel::unbounded();
+
+        let delete = Message::Delete(Delete {
+            collection: "users".into(),
+            id: "id".into(),
+            expected_version: None,
+        });
+        parse_message(delete.clone(), 0, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 0), ErrorCode::PermissionDenied);
+
+        let credentials = |password: &str| {
+            Message::ClientAuthentification(ClientAuthentication {
+                username: String::from("alice"),
+                password: password.to_string(),
+            })
+        };
+        let command =
+            parse_message(credentials("wrong"), 1, tx.clone(), &storage, &mut session)
+                .await;
+        assert_eq!(command, Command::Continue);
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            Message::AuthenticationResponse(AuthenticationResponse::Failure)
+        );
+        assert!(session.user().is_none());
+
+        parse_message(credentials(PASSWORD), 2, tx.clone(), &storage, &mut session).await;
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Message::AuthenticationResponse(AuthenticationResponse::Success { .. })
+        ));
+        parse_message(delete, 3, tx.clone(), &storage, &mut session).await;
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            Message::DeleteResult(DeleteStatus::KeyNotFound)
+        );
+
+        let create = Message::CreateUser(NewUser {
+            username: String::from("bob"),
+            password: String::from("bob"),
+            admin: false,
+            groups: vec![],
+        });
+        parse_message(create, 4, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 4), ErrorCode::PermissionDenied);
+
+        parse_message(credentials("wrong"), 5, tx.clone(), &storage, &mut session).await;
+        rx.recv().await.unwrap();
+        let command =
+            parse_message(credentials("wrong"), 6, tx, &storage, &mut session).await;
+        assert_eq!(command, Command::Exit);
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            Message::AuthenticationResponse(AuthenticationResponse::Failure)
+        );
+        assert_eq!(rx.recv().await.unwrap(), Message::CloseCommunication);
+    }
+
+    #[tokio::test]
+    async fn test_reserved_collections_are_refused() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, false).await;
+        let (tx, rx) = async_channel::unbounded();
+        let collection = String::from("__liserk:users");
+        let insert = Message::Insert(Insertion { collection, ..insertion(&[]) });
+        parse_message(insert, 0, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 0), ErrorCode::PermissionDenied);
+        let users = CompoundQuery {
+            query_type: QueryType::Or,
+            queries: vec![Query::GetById {
+                id: String::from("alice"),
+                collection: String::from("__liserk:users"),
+            }],
+        };
+        let query = Message::Query(Query::Explain(Box::new(Query::Compound(users))));
+        parse_message(query, 1, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 1), ErrorCode::PermissionDenied);
+        let set_history = Message::SetHistory {
+            collection: String::from("__liserk:catalog"),
+            versions: 1,
+        };
+        parse_message(set_history, 2, tx, &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 2), ErrorCode::PermissionDenied);
+
+        let mut transaction = storage.begin().await.unwrap();
+        let start = auth::USERS_PREFIX.as_bytes().to_vec();
+        let keys = transaction.scan(start, b"__liserk:users;".to_vec(), 10);
+        assert_eq!(keys.await.unwrap().len(), 1);
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_names_with_separator_are_refused() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, false).await;
+        let (tx, rx) = async_channel::unbounded();
+        let collection = String::from("fruits:price:ore");
+        let insert = Message::Insert(Insertion { collection, ..insertion(&[]) });
+        parse_message(insert, 0, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 0), ErrorCode::MalformedRequest);
+        let get_by_id = Query::GetById {
+            id: String::from("id"),
+            collection: String::from("fruits:price"),
+        };
+        parse_message(Message::Query(get_by_id), 1, tx.clone(), &storage, &mut session)
+            .await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 1), ErrorCode::MalformedRequest);
+        let insert = Message::InsertOre(InsertionOre {
+            collection: String::from("fruits"),
+            acl: vec![],
+            data: vec![1],
+            usecases: vec![String::from("price:ore:other")],
+            nonce: vec![0; 12],
+            ciphertext: vec![0; 16],
+        });
+        parse_message(insert, 2, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 2), ErrorCode::MalformedRequest);
+        let insert = Message::InsertOpe(InsertionOpe {
+            collection: String::from("fruits:price:ope"),
+            acl: vec![],
+            data: b"12".to_vec(),
+            usecases: vec![String::from("price")],
+        });
+        parse_message(insert, 3, tx, &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 3), ErrorCode::MalformedRequest);
+
+        let mut transaction = storage.begin().await.unwrap();
+        let keys = transaction.scan(b"fruits:".to_vec(), b"fruits;".to_vec(), 10);
+        assert!(keys.await.unwrap().is_empty());
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_count_needs_collection_access() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut alice = authenticated_session(&storage, false).await;
+        let mut bob = ClientSession::new(3);
+        bob.authenticate(create_user(&storage, "bob", false, &[]).await);
+        let (tx, rx) = async_channel::unbounded();
+        let insert = Message::Insert(insertion(&["red"]));
+        parse_message(insert, 0, tx.clone(), &storage, &mut alice).await;
+        assert!(matches!(rx.recv().await.unwrap(), Message::InsertResponse { .. }));
+
+        let collection = String::from("fruits");
+        let usecase = String::from("red");
+        for subject in [
+            CountSubject::Collection(collection.clone()),
+            CountSubject::Usecase { collection, usecase },
+        ] {
+            let count = Message::Count(subject);
+            parse_message(count.clone(), 1, tx.clone(), &storage, &mut bob).await;
+            assert_eq!(
+                error_code(rx.recv().await.unwrap(), 1),
+                ErrorCode::PermissionDenied
+            );
+            parse_message(count, 2, tx.clone(), &storage, &mut alice).await;
+            assert_eq!(rx.recv().await.unwrap(), Message::CountResponse(1));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_needs_collection_access() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut alice = authenticated_session(&storage, false).await;
+        let mut bob = ClientSession::new(3);
+        bob.authenticate(create_user(&storage, "bob", false, &[]).await);
+        let (tx, rx) = async_channel::unbounded();
+        let insert = Message::Insert(insertion(&["red"]));
+        parse_message(insert, 0, tx.clone(), &storage, &mut alice).await;
+        assert!(matches!(rx.recv().await.unwrap(), Message::InsertResponse { .. }));
+
+        let red = SingleQuery::new(String::from("fruits"), String::from("red"));
+        let query = CompoundQuery {
+            query_type: QueryType::And,
+            queries: vec![Query::Single(red)],
+        };
+        let explain = Message::Query(Query::Explain(Box::new(Query::Compound(query))));
+        parse_message(explain.clone(), 1, tx.clone(), &storage, &mut bob).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 1), ErrorCode::PermissionDenied);
+        parse_message(explain, 2, tx, &storage, &mut alice).await;
+        match rx.recv().await.unwrap() {
+            Message::ExplainResponse(plan) => assert_eq!(plan.estimated_rows(), 1),
+            message => panic!("unexpected response {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_manages_users() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, true).await;
+        let (tx, rx) = async_channel::unbounded();
+
+        let create = Message::CreateUser(NewUser {
+            username: String::from("bob"),
+            password: String::from("bob"),
+            admin: false,
+            groups: vec![],
+        });
+        parse_message(create.clone(), 0, tx.clone(), &storage, &mut session).await;
+        let created = Message::UserResponse { username: String::from("bob") };
+        assert_eq!(rx.recv().await.unwrap(), created);
+        parse_message(create, 1, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 1), ErrorCode::Conflict);
+
+        let delete = Message::DeleteUser { username: String::from("bob") };
+        parse_message(delete.clone(), 2, tx.clone(), &storage, &mut session).await;
+        assert_eq!(rx.recv().await.unwrap(), created);
+        parse_message(delete, 3, tx, &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 3), ErrorCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_update_changes_record() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, false).await;
+        let (tx, rx) = async_channel::unbounded();
+        let insert = Message::Insert(insertion(&["red"]));
+        parse_message(insert, 0, tx.clone(), &storage, &mut session).await;
+        let id = match rx.recv().await.unwrap() {
+            Message::InsertResponse { inserted_id, .. } => inserted_id,
+            message => panic!("unexpected response {:?}", message),
+        };
+
+        let cooks = AclEntry::group("cooks", Permission::Read);
+        let mut update = Update {
+            collection: String::from("fruits"),
+            id: id.clone(),
+            new_value: vec![2],
+            nonce: vec![1; 12],
+            options: UpdateOptions {
+                add_usecases: vec![String::from("sweet")],
+                remove_usecases: vec![String::from("red")],
+                searchable: false,
+                acl: Some(vec![cooks.clone()]),
+                expected_version: None,
+            },
+        };
+        for (request_id, expected_version, status) in [
+            (1, 2, UpdateStatus::Conflict { version: 1 }),
+            (2, 1, UpdateStatus::Success),
+            (3, 1, UpdateStatus::Conflict { version: 2 }),
+        ] {
+            update.options.expected_version = Some(expected_version);
+            let message = Message::Update(update.clone());
+            parse_message(message, request_id, tx.clone(), &storage, &mut session).await;
+            assert_eq!(rx.recv().await.unwrap(), Message::UpdateResponse { status });
+        }
+
+        let data_key = format!("fruits:{}", id);
+        let mut transaction = storage.begin().await.unwrap();
+        let get = |suffix: &str| format!("{}{}", data_key, suffix).into_bytes();
+        assert_eq!(transaction.get(get("")).await.unwrap(), Some(vec![2]));
+        assert_eq!(transaction.get(get(":nonce")).await.unwrap(), Some(vec![1; 12]));
+        let acl = transaction.get(get(":acl")).await.unwrap().unwrap();
+        let acl: Vec<AclEntry> = serde_cbor::from_slice(&acl).unwrap();
+        assert_eq!(acl, [cooks, AclEntry::user("alice", Permission::Admin)]);
+        assert!(transaction
+            .get(b"fruits:red:usecase".to_vec())
+            .await
+            .unwrap()
+            .is_none());
+        let sweet = transaction.get(b"fruits:sweet:usecase".to_vec()).await.unwrap();
+        let sweet: Vec<Vec<u8>> = serde_cbor::from_slice(&sweet.unwrap()).unwrap();
+        assert_eq!(sweet, [data_key.into_bytes()]);
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_of_range_indexed_record_is_refused() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, false).await;
+        let (tx, rx) = async_channel::unbounded();
+        let insert = Message::InsertOpe(InsertionOpe {
+            collection: String::from("fruits"),
+            acl: vec![],
+            data: b"12".to_vec(),
+            usecases: vec![String::from("price")],
+        });
+        parse_message(insert, 0, tx.clone(), &storage, &mut session).await;
+        let id = match rx.recv().await.unwrap() {
+            Message::InsertResponse { inserted_id, .. } => inserted_id,
+            message => panic!("unexpected response {:?}", message),
+        };
+
+        let update = Message::Update(Update {
+            collection: String::from("fruits"),
+            id: id.clone(),
+            new_value: vec![2],
+            nonce: vec![1; 12],
+            options: UpdateOptions::default(),
+        });
+        parse_message(update, 1, tx, &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 1), ErrorCode::Unsupported);
+        let mut transaction = storage.begin().await.unwrap();
+        let data = transaction.get(format!("fruits:{}", id).into()).await.unwrap();
+        assert_eq!(data, Some(b"12".to_vec()));
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_history_of_versions() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, false).await;
+        let (tx, rx) = async_channel::unbounded();
+        let set_history =
+            Message::SetHistory { collection: "fruits".into(), versions: 1 };
+        parse_message(set_history.clone(), 0, tx.clone(), &storage, &mut session).await;
+        assert_eq!(error_code(rx.recv().await.unwrap(), 0), ErrorCode::NotFound);
+        let insert = Message::Insert(insertion(&[]));
+        parse_message(insert, 1, tx.clone(), &storage, &mut session).await;
+        let id = match rx.recv().await.unwrap() {
+            Message::InsertResponse { inserted_id, version: 1 } => inserted_id,
+            message => panic!("unexpected response {:?}", message),
+        };
+        parse_message(set_history, 1, tx.clone(), &storage, &mut session).await;
+        match rx.recv().await.unwrap() {
+            Message::CollectionDescription(Some(info)) => {
+                assert_eq!((info.owner.as_str(), info.history), ("alice", 1));
+                assert_eq!(info.record_count, 1);
             }
+            message => panic!("unexpected response {:?}", message),
+        }
+        for value in [2, 3] {
+            let update = Message::Update(Update {
+                collection: String::from("fruits"),
+                id: id.clone(),
+                new_value: vec![value],
+                nonce: vec![value; 12],
+                options: UpdateOptions::default(),
+            });
+            parse_message(update, 2, tx.clone(), &storage, &mut session).await;
+            let status = UpdateStatus::Success;
+            assert_eq!(rx.recv().await.unwrap(), Message::UpdateResponse { status });
+        }
+
+        let get_by_id = Query::GetById { id: id.clone(), collection: "fruits".into() };
+        parse_message(Message::Query(get_by_id), 3, tx.clone(), &storage, &mut session)
+            .await;
+        let current = Message::SingleValueResponse {
+            data: Some(vec![3]),
+            nonce: Some(vec![3; 12]),
+            version: Some(3),
+        };
+        assert_eq!(rx.recv().await.unwrap(), current);
+        let previous = Message::SingleValueResponse {
+            data: Some(vec![2]),
+            nonce: Some(vec![2; 12]),
+            version: Some(2),
+        };
+        let missing =
+            Message::SingleValueResponse { data: None, nonce: None, version: None };
+        for (version, response) in [(3, current), (2, previous), (1, missing)] {
+            let get_version = Query::GetVersion {
+                id: id.clone(),
+                collection: "fruits".into(),
+                version,
+            };
+            let query = Message::Query(get_version);
+            parse_message(query, 4, tx.clone(), &storage, &mut session).await;
+            assert_eq!(rx.recv().await.unwrap(), response);
+        }
+
+        for (expected_version, status) in
+            [(2, DeleteStatus::Conflict { version: 3 }), (3, DeleteStatus::Success)]
+        {
+            let delete = Message::Delete(Delete {
+                collection: "fruits".into(),
+                id: id.clone(),
+                expected_version: Some(expected_version),
+            });
+            parse_message(delete, 5, tx.clone(), &storage, &mut session).await;
+            assert_eq!(rx.recv().await.unwrap(), Message::DeleteResult(status));
         }
-        Err(err) => debug!("{:?}", err),
+        let mut transaction = storage.begin().await.unwrap();
+        let keys = transaction.scan(b"fruits:".to_vec(), b"fruits;".to_vec(), 10);
+        assert!(keys.await.unwrap().is_empty());
+        transaction.commit().await.unwrap();
     }
-    Command::Continue
-}
 
-async fn handle_query(query: Query, tx: Sender<Message>) -> Command {
-    match query_engine::handle_query(query, tx).await {
-        Ok(command) => command,
-        Err(err) => {
-            error!("{:?}", err);
-            Command::Exit
+    #[tokio::test]
+    async fn test_admin_drops_usecase_and_collection() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, true).await;
+        let (tx, rx) = async_channel::unbounded();
+        let mut ids = Vec::new();
+        for usecases in [vec!["red"], vec!["red", "sweet"]] {
+            let insert = Message::Insert(insertion(&usecases));
+            parse_message(insert, 0, tx.clone(), &storage, &mut session).await;
+            match rx.recv().await.unwrap() {
+                Message::InsertResponse { inserted_id, .. } => ids.push(inserted_id),
+                message => panic!("unexpected response {:?}", message),
+            }
         }
+
+        let drop = Message::Drop(DropSubject::Usecase {
+            collection: String::from("fruits"),
+            usecase: String::from("red"),
+            records: true,
+        });
+        parse_message(drop.clone(), 1, tx.clone(), &storage, &mut session).await;
+        assert_eq!(rx.recv().await.unwrap(), Message::DropProgress { dropped: 2 });
+        assert_eq!(rx.recv().await.unwrap(), Message::DropResult(true));
+        parse_message(drop, 2, tx.clone(), &storage, &mut session).await;
+        assert_eq!(rx.recv().await.unwrap(), Message::DropResult(false));
+        let mut transaction = storage.begin().await.unwrap();
+        for (id, exists) in [(&ids[0], false), (&ids[1], true)] {
+            let data = transaction.get(format!("fruits:{}", id).into()).await.unwrap();
+            assert_eq!(data.is_some(), exists);
+        }
+        let sweet = transaction.get(b"fruits:sweet:usecase".to_vec()).await.unwrap();
+        assert!(sweet.is_some());
+        assert!(transaction
+            .get(b"fruits:red:usecase".to_vec())
+            .await
+            .unwrap()
+            .is_none());
+        transaction.commit().await.unwrap();
+        let describe = Message::DescribeCollection { collection: String::from("fruits") };
+        parse_message(describe, 3, tx.clone(), &storage, &mut session).await;
+        match rx.recv().await.unwrap() {
+            Message::CollectionDescription(Some(info)) => {
+                assert_eq!((info.record_count, info.byte_size), (1, 1));
+                assert_eq!(info.usecases.len(), 1);
+                assert!(info.usecase("sweet").is_some());
+            }
+            message => panic!("unexpected response {:?}", message),
+        }
+
+        let drop = Message::Drop(DropSubject::Collection(String::from("fruits")));
+        parse_message(drop.clone(), 3, tx.clone(), &storage, &mut session).await;
+        assert!(matches!(rx.recv().await.unwrap(), Message::DropProgress { .. }));
+        assert_eq!(rx.recv().await.unwrap(), Message::DropResult(true));
+        parse_message(drop, 4, tx.clone(), &storage, &mut session).await;
+        assert_eq!(rx.recv().await.unwrap(), Message::DropResult(false));
+        parse_message(Message::ListCollections, 5, tx, &storage, &mut session).await;
+        assert_eq!(rx.recv().await.unwrap(), Message::CollectionList(vec![]));
+        let mut transaction = storage.begin().await.unwrap();
+        let keys = transaction.scan(b"fruits:".to_vec(), b"fruits;".to_vec(), 10);
+        assert!(keys.await.unwrap().is_empty());
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drop_keeps_nested_collections() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut session = authenticated_session(&storage, true).await;
+        let (tx, rx) = async_channel::unbounded();
+        // Collections created before they could not contain `:`.
+        let mut transaction = storage.begin().await.unwrap();
+        for (collection, data_key) in
+            [("orders", "orders:1"), ("orders:archive", "orders:archive:2")]
+        {
+            let user = session.user().unwrap();
+            catalog::record_insertion(
+                transaction.as_mut(),
+                user,
+                collection,
+                data_key,
+                &[],
+                EncryptionKind::Aes,
+                1,
+            )
+            .await
+            .unwrap();
+            transaction.put(data_key.into(), vec![1]).await.unwrap();
+        }
+        transaction.commit().await.unwrap();
+
+        let drop = Message::Drop(DropSubject::Collection(String::from("orders")));
+        parse_message(drop, 0, tx.clone(), &storage, &mut session).await;
+        assert_eq!(rx.recv().await.unwrap(), Message::DropProgress { dropped: 1 });
+        assert_eq!(rx.recv().await.unwrap(), Message::DropResult(true));
+        parse_message(Message::ListCollections, 1, tx, &storage, &mut session).await;
+        let collections = vec![String::from("orders:archive")];
+        assert_eq!(rx.recv().await.unwrap(), Message::CollectionList(collections));
+        let mut transaction = storage.begin().await.unwrap();
+        let keys = transaction.scan(b"orders:".to_vec(), b"orders;".to_vec(), 10);
+        let keys: Vec<Vec<u8>> =
+            keys.await.unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, [b"orders:archive:2".to_vec()]);
+        transaction.commit().await.unwrap();
     }
 }
@@ -1,112 +1,496 @@
-use liserk_shared::message::{Delete, Insertion, InsertionOpe, Update, UpdateStatus};
-use tikv_client::TransactionClient;
+use async_channel::Sender;
+use liserk_shared::acl::Permission;
+use liserk_shared::catalog::EncryptionKind;
+use liserk_shared::message::{
+    Delete, DeleteStatus, Insertion, InsertionOpe, InsertionOre, Message, Update,
+    UpdateStatus,
+};
 use tracing::info;
 use uuid::Uuid;
 
-use crate::{config::TIKV_URL, Error};
+use crate::record_index::{self, RecordIndex};
+use crate::storage::{StorageBackend, StorageTransaction};
+use crate::version::{self, HistoryEntry};
+use crate::{acl, auth::User, catalog, ore_index, range_index, Error};
 
-pub async fn insert(insertion: Insertion) -> Result<String, Error> {
-    let client = TransactionClient::new(vec![TIKV_URL]).await?;
+/// Number of keys, or of records for a usecase, deleted by each transaction of a
+/// drop.
+const DROP_BATCH_SIZE: u32 = 1000;
 
+pub async fn insert(
+    storage: &dyn StorageBackend,
+    user: &User,
+    insertion: Insertion,
+) -> Result<String, Error> {
+    catalog::check_collection(&insertion.collection)?;
+    catalog::check_usecases(&insertion.usecases)?;
     let unique_id = Uuid::new_v4().to_string();
 
     let data_key = format!("{}:{}", insertion.collection, unique_id);
     info!("data_key: {}", data_key);
+    let kind = match insertion.searchable {
+        true => EncryptionKind::SearchableToken,
+        false => EncryptionKind::Aes,
+    };
+    let byte_size = insertion.data.len() as u64;
 
-    let mut transaction = client.begin_optimistic().await?;
-    transaction.insert(data_key.clone(), insertion.data).await?;
+    let mut transaction = storage.begin().await?;
+    transaction.insert(data_key.clone().into(), insertion.data).await?;
 
     let nonce_key = format!("{}:{}:nonce", insertion.collection, unique_id);
-    transaction.insert(nonce_key.clone(), insertion.nonce).await?;
+    transaction.insert(nonce_key.clone().into(), insertion.nonce).await?;
     info!("nonce_key: {}", nonce_key);
 
-    let acl_key = format!("{}:{}:acl", insertion.collection, unique_id);
-    let acl_json = serde_cbor::to_vec(&insertion.acl)?;
-    transaction.insert(acl_key, acl_json).await?;
-
-    for usecase in insertion.usecases {
-        let usecase_key = format!("{}:{}:usecase", insertion.collection, usecase);
-        info!("usecase_key: {}", usecase_key);
-        let values = match transaction.get(usecase_key.clone()).await? {
-            Some(value) => {
-                let mut values: Vec<Vec<u8>> = serde_cbor::from_slice(&value)?;
-                values.push(data_key.clone().into_bytes());
-                values
-            }
-            None => {
-                vec![data_key.clone().into_bytes()]
-            }
-        };
-        let bytes = serde_cbor::to_vec(&values)?;
-        transaction.put(usecase_key, bytes).await?;
-    }
-    let commit = transaction.commit().await?;
-    info!("insert commit: {:?}", commit);
+    let acl = acl::with_owner(insertion.acl, user);
+    let acl_json = serde_cbor::to_vec(&acl)?;
+    transaction.insert(acl::acl_key(&data_key).into(), acl_json).await?;
+    version::save(transaction.as_mut(), &data_key, version::FIRST_VERSION).await?;
+
+    let usecases = &insertion.usecases;
+    catalog::record_insertion(
+        transaction.as_mut(),
+        user,
+        &insertion.collection,
+        &data_key,
+        usecases,
+        kind,
+        byte_size,
+    )
+    .await?;
+    let index = RecordIndex { usecases: insertion.usecases, range_keys: vec![] };
+    index_usecases(transaction.as_mut(), &insertion.collection, &data_key, index).await?;
+    transaction.commit().await?;
+    info!("insert committed");
     Ok(unique_id)
 }
 
-pub async fn insert_ope(insertion: InsertionOpe) -> Result<String, Error> {
-    let client = TransactionClient::new(vec![TIKV_URL]).await?;
+pub async fn insert_ope(
+    storage: &dyn StorageBackend,
+    user: &User,
+    insertion: InsertionOpe,
+) -> Result<String, Error> {
+    catalog::check_collection(&insertion.collection)?;
+    catalog::check_usecases(&insertion.usecases)?;
+    let unique_id = Uuid::new_v4().to_string();
+
+    let data_key = format!("{}:{}", insertion.collection, unique_id);
+    info!("data_key: {}", data_key);
+    let ciphertext = range_index::encode_ciphertext(&insertion.data)?;
+    let byte_size = insertion.data.len() as u64;
+
+    let mut transaction = storage.begin().await?;
+    transaction.insert(data_key.clone().into(), insertion.data).await?;
+
+    let acl = acl::with_owner(insertion.acl, user);
+    let acl_json = serde_cbor::to_vec(&acl)?;
+    transaction.insert(acl::acl_key(&data_key).into(), acl_json).await?;
+    version::save(transaction.as_mut(), &data_key, version::FIRST_VERSION).await?;
+
+    let mut range_keys = Vec::new();
+    for usecase in insertion.usecases.iter().cloned() {
+        let index_key = range_index::index_key(
+            &insertion.collection,
+            &usecase,
+            &ciphertext,
+            &unique_id,
+        );
+        transaction
+            .insert(index_key.clone(), data_key.clone().into_bytes())
+            .await?;
+
+        range_keys.push((usecase, index_key));
+    }
+    catalog::record_insertion(
+        transaction.as_mut(),
+        user,
+        &insertion.collection,
+        &data_key,
+        &insertion.usecases,
+        EncryptionKind::Ope,
+        byte_size,
+    )
+    .await?;
+    let index = RecordIndex { usecases: insertion.usecases, range_keys };
+    index_usecases(transaction.as_mut(), &insertion.collection, &data_key, index).await?;
+    transaction.commit().await?;
+    info!("insert committed");
+    Ok(unique_id)
+}
 
+pub async fn insert_ore(
+    storage: &dyn StorageBackend,
+    user: &User,
+    insertion: InsertionOre,
+) -> Result<String, Error> {
+    catalog::check_collection(&insertion.collection)?;
+    catalog::check_usecases(&insertion.usecases)?;
     let unique_id = Uuid::new_v4().to_string();
 
     let data_key = format!("{}:{}", insertion.collection, unique_id);
     info!("data_key: {}", data_key);
+    ore_index::check_ciphertext(&insertion.ciphertext)?;
+    let byte_size = insertion.data.len() as u64;
 
-    let mut transaction = client.begin_optimistic().await?;
-    transaction.insert(data_key.clone(), insertion.data).await?;
-
-    let acl_key = format!("{}:{}:acl", insertion.collection, unique_id);
-    let acl_json = serde_cbor::to_vec(&insertion.acl)?;
-    transaction.insert(acl_key, acl_json).await?;
-
-    for usecase in insertion.usecases {
-        let usecase_key = format!("{}:{}:usecase", insertion.collection, usecase);
-        info!("usecase_key: {}", usecase_key);
-        let values = match transaction.get(usecase_key.clone()).await? {
-            Some(value) => {
-                let mut values: Vec<Vec<u8>> = serde_cbor::from_slice(&value)?;
-                values.push(data_key.clone().into_bytes());
-                values
-            }
-            None => {
-                vec![data_key.clone().into_bytes()]
-            }
-        };
-        let bytes = serde_cbor::to_vec(&values)?;
-        transaction.put(usecase_key, bytes).await?;
+    let mut transaction = storage.begin().await?;
+    transaction.insert(data_key.clone().into(), insertion.data).await?;
+
+    let nonce_key = format!("{}:{}:nonce", insertion.collection, unique_id);
+    transaction.insert(nonce_key.into(), insertion.nonce).await?;
+
+    let acl = acl::with_owner(insertion.acl, user);
+    let acl_json = serde_cbor::to_vec(&acl)?;
+    transaction.insert(acl::acl_key(&data_key).into(), acl_json).await?;
+    version::save(transaction.as_mut(), &data_key, version::FIRST_VERSION).await?;
+
+    let mut range_keys = Vec::new();
+    for usecase in insertion.usecases.iter().cloned() {
+        let index_key = ore_index::index_key(&insertion.collection, &usecase, &unique_id);
+        transaction
+            .insert(index_key.clone(), insertion.ciphertext.clone())
+            .await?;
+
+        range_keys.push((usecase, index_key));
     }
-    let commit = transaction.commit().await?;
-    info!("insert commit: {:?}", commit);
+    catalog::record_insertion(
+        transaction.as_mut(),
+        user,
+        &insertion.collection,
+        &data_key,
+        &insertion.usecases,
+        EncryptionKind::Ore,
+        byte_size,
+    )
+    .await?;
+    let index = RecordIndex { usecases: insertion.usecases, range_keys };
+    index_usecases(transaction.as_mut(), &insertion.collection, &data_key, index).await?;
+    transaction.commit().await?;
+    info!("insert committed");
     Ok(unique_id)
 }
 
-pub async fn update(query: Update) -> Result<UpdateStatus, Error> {
-    let client = TransactionClient::new(vec![TIKV_URL]).await?;
+/// Lists a new record in the index of each of its usecases, and records these
+/// entries with its range index keys.
+async fn index_usecases(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    data_key: &str,
+    index: RecordIndex,
+) -> Result<(), Error> {
+    for usecase in &index.usecases {
+        info!("usecase_key: {}", record_index::usecase_key(collection, usecase));
+        record_index::add_usecase(transaction, collection, usecase, data_key).await?;
+    }
+    record_index::save(transaction, data_key, &index).await
+}
 
+/// Replaces the value and the nonce of a record, changes its usecases and its ACL, and
+/// increments its version. The replaced value is kept if the collection has a
+/// history.
+///
+/// The records inserted with OPE or ORE are refused with
+/// [`Error::UnsupportedUpdate`], as their range index entries can't follow the new
+/// value.
+pub async fn update(
+    storage: &dyn StorageBackend,
+    user: &User,
+    query: Update,
+) -> Result<UpdateStatus, Error> {
+    catalog::check_collection(&query.collection)?;
+    catalog::check_usecases(&query.options.add_usecases)?;
     let data_key = format!("{}:{}", query.collection, query.id);
     info!("data_key: {}", data_key);
+    let options = query.options;
 
-    let mut transaction = client.begin_optimistic().await?;
-    let Some(_) = transaction.get_for_update(data_key.clone()).await? else {
-        let _ = transaction.commit().await?;
+    let mut transaction = storage.begin().await?;
+    if !acl::require(transaction.as_mut(), user, &data_key, Permission::Write).await? {
+        transaction.commit().await?;
+        return Ok(UpdateStatus::KeyNotFound);
+    }
+    if options.acl.is_some() {
+        acl::require(transaction.as_mut(), user, &data_key, Permission::Admin).await?;
+    }
+    let Some(old_value) = transaction.get_for_update(data_key.clone().into()).await?
+    else {
+        transaction.commit().await?;
         return Ok(UpdateStatus::KeyNotFound);
     };
-    transaction.put(data_key, query.new_value).await?;
-    let commit = transaction.commit().await?;
-    info!("update commit: {:?}", commit);
+    let version = version::load(transaction.as_mut(), &data_key).await?;
+    if options.expected_version.is_some_and(|expected| expected != version) {
+        transaction.commit().await?;
+        return Ok(UpdateStatus::Conflict { version });
+    }
+    let mut index = record_index::load(transaction.as_mut(), &data_key).await?;
+    if !index.range_keys.is_empty() {
+        transaction.commit().await?;
+        return Err(Error::UnsupportedUpdate(data_key));
+    }
+
+    let (old_size, new_size) = (old_value.len() as u64, query.new_value.len() as u64);
+    catalog::record_update(
+        transaction.as_mut(),
+        &query.collection,
+        &data_key,
+        old_size,
+        new_size,
+    )
+    .await?;
+    let nonce_key = format!("{}:nonce", data_key);
+    let history = catalog::history(transaction.as_mut(), &query.collection).await?;
+    if history > 0 {
+        let nonce = transaction.get(nonce_key.clone().into()).await?;
+        let entry = HistoryEntry { value: old_value, nonce };
+        version::archive(transaction.as_mut(), &data_key, version, &entry, history)
+            .await?;
+    }
+    transaction.put(data_key.clone().into(), query.new_value).await?;
+    transaction.put(nonce_key.into(), query.nonce).await?;
+    version::save(transaction.as_mut(), &data_key, version + 1).await?;
+
+    if let Some(acl) = options.acl {
+        let acl_json = serde_cbor::to_vec(&acl::with_owner(acl, user))?;
+        transaction.put(acl::acl_key(&data_key).into(), acl_json).await?;
+    }
+
+    for usecase in &options.remove_usecases {
+        record_index::remove_usecase(
+            transaction.as_mut(),
+            &query.collection,
+            usecase,
+            &data_key,
+        )
+        .await?;
+    }
+    index
+        .usecases
+        .retain(|usecase| !options.remove_usecases.contains(usecase));
+    let mut added = Vec::new();
+    for usecase in options.add_usecases {
+        if index.usecases.contains(&usecase) || added.contains(&usecase) {
+            continue;
+        }
+        record_index::add_usecase(
+            transaction.as_mut(),
+            &query.collection,
+            &usecase,
+            &data_key,
+        )
+        .await?;
+        added.push(usecase);
+    }
+    let kind = match options.searchable {
+        true => EncryptionKind::SearchableToken,
+        false => EncryptionKind::Aes,
+    };
+    catalog::record_usecases(transaction.as_mut(), &query.collection, &added, kind)
+        .await?;
+    index.usecases.extend(added);
+    record_index::save(transaction.as_mut(), &data_key, &index).await?;
+
+    transaction.commit().await?;
+    info!("update committed");
     Ok(UpdateStatus::Success)
 }
 
-pub async fn delete(query: Delete) -> Result<bool, Error> {
-    let client = TransactionClient::new(vec![TIKV_URL]).await?;
-    let key = format!("{}:{}", query.collection, query.id);
-    let mut transaction = client.begin_optimistic().await?;
-    let is_deleted = match transaction.delete(key).await {
-        Ok(_) => true,
-        Err(_) => false,
+/// Deletes a record with its nonce, its ACL, its index entries and its history.
+pub async fn delete(
+    storage: &dyn StorageBackend,
+    user: &User,
+    query: Delete,
+) -> Result<DeleteStatus, Error> {
+    catalog::check_collection(&query.collection)?;
+    let data_key = format!("{}:{}", query.collection, query.id);
+    let mut transaction = storage.begin().await?;
+    if !acl::require(transaction.as_mut(), user, &data_key, Permission::Write).await? {
+        transaction.commit().await?;
+        return Ok(DeleteStatus::KeyNotFound);
+    }
+    let Some(_) = transaction.get_for_update(data_key.clone().into()).await? else {
+        transaction.commit().await?;
+        return Ok(DeleteStatus::KeyNotFound);
     };
-    let commit = transaction.commit().await?;
-    info!("delet commit: {:?}", commit);
-    Ok(is_deleted)
+    let version = version::load(transaction.as_mut(), &data_key).await?;
+    if query.expected_version.is_some_and(|expected| expected != version) {
+        transaction.commit().await?;
+        return Ok(DeleteStatus::Conflict { version });
+    }
+    delete_record(transaction.as_mut(), &query.collection, &data_key).await?;
+    transaction.commit().await?;
+    info!("delete committed");
+    Ok(DeleteStatus::Success)
+}
+
+async fn delete_record(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    data_key: &str,
+) -> Result<(), Error> {
+    let data = transaction.get(data_key.into()).await?;
+    let byte_size = data.map_or(0, |data| data.len() as u64);
+    catalog::record_deletion(transaction, collection, data_key, byte_size).await?;
+    record_index::remove(transaction, collection, data_key).await?;
+    transaction.delete(format!("{}:nonce", data_key).into()).await?;
+    version::remove(transaction, data_key).await?;
+    transaction.delete(acl::acl_key(data_key).into()).await?;
+    transaction.delete(data_key.into()).await?;
+    Ok(())
+}
+
+/// Removes a record from the indexes of `usecase`, the record itself is kept.
+pub async fn delete_for_usecase(
+    storage: &dyn StorageBackend,
+    user: &User,
+    collection: String,
+    usecase: String,
+    id: String,
+) -> Result<DeleteStatus, Error> {
+    catalog::check_collection(&collection)?;
+    let data_key = format!("{}:{}", collection, id);
+    let mut transaction = storage.begin().await?;
+    if !acl::require(transaction.as_mut(), user, &data_key, Permission::Write).await? {
+        transaction.commit().await?;
+        return Ok(DeleteStatus::KeyNotFound);
+    }
+    let removed = record_index::remove_from_usecase(
+        transaction.as_mut(),
+        &collection,
+        &usecase,
+        &data_key,
+    )
+    .await?;
+    transaction.commit().await?;
+    if !removed {
+        return Ok(DeleteStatus::KeyNotFound);
+    }
+    info!("delete for usecase committed");
+    Ok(DeleteStatus::Success)
+}
+
+/// Sends the progress of a drop after a batch.
+async fn report_progress(tx: &Sender<Message>, dropped: u64) -> Result<(), Error> {
+    info!("{} dropped so far", dropped);
+    tx.send(Message::DropProgress { dropped }).await?;
+    Ok(())
+}
+
+/// Deletes every key starting with `prefix`, except the keys starting with one of
+/// `kept`, [`DROP_BATCH_SIZE`] keys per transaction, and returns how many were
+/// deleted.
+async fn delete_prefix(
+    storage: &dyn StorageBackend,
+    prefix: &str,
+    kept: &[String],
+    tx: &Sender<Message>,
+) -> Result<u64, Error> {
+    let mut start = prefix.as_bytes().to_vec();
+    let mut end = start.clone();
+    // Right after every key of the prefix, which ends with `:`.
+    *end.last_mut().expect("prefix is not empty") += 1;
+    let mut deleted = 0;
+    loop {
+        let mut transaction = storage.begin().await?;
+        let pairs = transaction.scan(start.clone(), end.clone(), DROP_BATCH_SIZE).await?;
+        let mut batch = 0;
+        for (key, _) in &pairs {
+            if !kept.iter().any(|kept| key.starts_with(kept.as_bytes())) {
+                transaction.delete(key.clone()).await?;
+                batch += 1;
+            }
+        }
+        transaction.commit().await?;
+        let Some((last, _)) = pairs.last() else {
+            return Ok(deleted);
+        };
+        // Right after the last key, as the kept keys are still there.
+        start = [last.as_slice(), &[0]].concat();
+        if batch > 0 {
+            deleted += batch;
+            report_progress(tx, deleted).await?;
+        }
+    }
+}
+
+/// Deletes every key of `collection`, by batches so a large collection is not
+/// deleted by a single transaction.
+///
+/// Returns the number of deleted keys. The keys of the server, whose collection
+/// starts with `__liserk`, can't be dropped. Collections can't contain `:` anymore,
+/// but the keys of those created before, such as `orders:archive` for `orders`, are
+/// kept.
+pub async fn drop_collection(
+    storage: &dyn StorageBackend,
+    collection: &str,
+    tx: &Sender<Message>,
+) -> Result<u64, Error> {
+    catalog::check_collection(collection)?;
+    let mut transaction = storage.begin().await?;
+    let kept: Vec<String> = catalog::nested(transaction.as_mut(), collection)
+        .await?
+        .into_iter()
+        .map(|nested| format!("{}:", nested))
+        .collect();
+    transaction.commit().await?;
+    let prefix = format!("{}:", collection);
+    let deleted = delete_prefix(storage, &prefix, &kept, tx).await?;
+    let mut transaction = storage.begin().await?;
+    catalog::remove(transaction.as_mut(), collection).await?;
+    transaction.commit().await?;
+    info!("collection {} dropped, {} keys deleted", collection, deleted);
+    Ok(deleted)
+}
+
+/// Removes every record from `usecase` and deletes its index. With `records`, the
+/// records which have no other usecase are deleted.
+///
+/// Returns the number of records removed from the usecase.
+pub async fn drop_usecase(
+    storage: &dyn StorageBackend,
+    collection: &str,
+    usecase: &str,
+    records: bool,
+    tx: &Sender<Message>,
+) -> Result<u64, Error> {
+    catalog::check_collection(collection)?;
+    let usecase_key = record_index::usecase_key(collection, usecase);
+    let mut dropped = 0;
+    loop {
+        let mut transaction = storage.begin().await?;
+        let Some(value) = transaction.get_for_update(usecase_key.clone().into()).await?
+        else {
+            transaction.commit().await?;
+            break;
+        };
+        let data_keys: Vec<Vec<u8>> = serde_cbor::from_slice(&value)?;
+        let batch = data_keys.iter().take(DROP_BATCH_SIZE as usize);
+        for data_key in batch.map(|key| String::from_utf8_lossy(key).to_string()) {
+            let transaction = transaction.as_mut();
+            let index = record_index::load(transaction, &data_key).await?;
+            if records && index.usecases == [usecase] {
+                delete_record(transaction, collection, &data_key).await?;
+            } else if !record_index::remove_from_usecase(
+                transaction,
+                collection,
+                usecase,
+                &data_key,
+            )
+            .await?
+            {
+                // Records deleted before the index entries were recorded.
+                record_index::remove_usecase(transaction, collection, usecase, &data_key)
+                    .await?;
+            }
+            dropped += 1;
+        }
+        transaction.commit().await?;
+        report_progress(tx, dropped).await?;
+    }
+    let mut transaction = storage.begin().await?;
+    catalog::remove_usecase(transaction.as_mut(), collection, usecase).await?;
+    transaction.commit().await?;
+    // Leftovers of the records deleted before the index entries were recorded.
+    for scheme in ["ope", "ore"] {
+        let prefix = format!("{}:{}:{}:", collection, usecase, scheme);
+        delete_prefix(storage, &prefix, &[], tx).await?;
+    }
+    info!("usecase {}:{} dropped, {} records removed", collection, usecase, dropped);
+    Ok(dropped)
 }
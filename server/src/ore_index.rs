@@ -0,0 +1,201 @@
+//! Index of the ORE ciphertexts of each usecase.
+//!
+//! Every record inserted with `InsertOre` gets, for each of its usecases, the key
+//! `collection:usecase:ore:<id>` holding its right ciphertext. Right ciphertexts
+//! can't be sorted, only compared to the left ciphertext of a bound, so a range query
+//! compares the bounds to every ciphertext of the usecase.
+
+use std::cmp::Ordering;
+
+use liserk_ope::ore::{compare, LeftCiphertext, RightCiphertext};
+use liserk_shared::query::{RangeBound, SingleQuery};
+use tracing::warn;
+
+use crate::storage::{Key, StorageTransaction};
+use crate::Error;
+
+fn index_prefix(collection: &str, usecase: &str) -> Vec<u8> {
+    format!("{}:{}:ore:", collection, usecase).into_bytes()
+}
+
+pub fn index_key(collection: &str, usecase: &str, id: &str) -> Key {
+    [&index_prefix(collection, usecase), id.as_bytes()].concat()
+}
+
+/// Checks that a right ciphertext sent by the client can be compared.
+pub fn check_ciphertext(ciphertext: &[u8]) -> Result<(), Error> {
+    RightCiphertext::from_bytes(ciphertext).map_err(|_| Error::InvalidCiphertext)?;
+    Ok(())
+}
+
+/// Left ciphertext of `bound` and the orderings of the bound relative to the records
+/// it matches.
+fn parse_bound(
+    bound: &Option<RangeBound>,
+    exclusive: Ordering,
+) -> Result<Option<(LeftCiphertext, Vec<Ordering>)>, Error> {
+    let Some(bound) = bound else {
+        return Ok(None);
+    };
+    let left = LeftCiphertext::from_bytes(&bound.ciphertext)
+        .map_err(|_| Error::InvalidCiphertext)?;
+    let matching =
+        if bound.inclusive { vec![exclusive, Ordering::Equal] } else { vec![exclusive] };
+    Ok(Some((left, matching)))
+}
+
+/// Returns the data keys of the records of the usecase of `single_query` whose
+/// ciphertext is within its bounds. Invalid entries are skipped, so they don't fail
+/// every query on the usecase.
+pub async fn scan(
+    transaction: &mut dyn StorageTransaction,
+    single_query: &SingleQuery,
+) -> Result<Vec<String>, Error> {
+    let bounds = [
+        parse_bound(&single_query.lower_limit, Ordering::Less)?,
+        parse_bound(&single_query.upper_limit, Ordering::Greater)?,
+    ];
+    if let [Some((lower, _)), Some((upper, _))] = &bounds {
+        if lower.blocks.len() != upper.blocks.len() {
+            return Err(Error::InvalidCiphertext);
+        }
+    }
+    let prefix = index_prefix(&single_query.collection, &single_query.usecase);
+    let mut end = prefix.clone();
+    // Right after every key of the prefix, which ends with `:`.
+    *end.last_mut().expect("prefix is not empty") += 1;
+
+    let mut data_keys = Vec::new();
+    for (key, ciphertext) in transaction.scan(prefix.clone(), end, u32::MAX).await? {
+        let id = String::from_utf8_lossy(&key[prefix.len()..]);
+        match matches(&bounds, &id, &ciphertext) {
+            Some(true) => data_keys.push(format!("{}:{}", single_query.collection, id)),
+            Some(false) => {}
+            None => warn!("skipping invalid ORE index entry {:?}", key),
+        }
+    }
+    Ok(data_keys)
+}
+
+/// Whether the entry of the record `id` is within `bounds`, `None` if it is not an
+/// entry of a record or its ciphertext can't be compared to the bounds.
+fn matches(
+    bounds: &[Option<(LeftCiphertext, Vec<Ordering>)>],
+    id: &str,
+    ciphertext: &[u8],
+) -> Option<bool> {
+    if id.is_empty() || id.contains(':') {
+        return None;
+    }
+    let right = RightCiphertext::from_bytes(ciphertext).ok()?;
+    let mut matches = true;
+    for (left, matching) in bounds.iter().flatten() {
+        if left.blocks.len() != right.blocks.len() {
+            // A plaintext of another length, such as another type, never matches.
+            return Some(false);
+        }
+        let ordering = compare(left, &right).ok()?;
+        matches &= matching.contains(&ordering);
+    }
+    Some(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use liserk_ope::ore::Ore;
+    use liserk_shared::query::RangeScheme;
+
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+
+    fn bound(ore: &Ore, value: u8, inclusive: bool) -> Option<RangeBound> {
+        let ciphertext = ore.encrypt_left(&[value]).to_bytes();
+        let bound = if inclusive {
+            RangeBound::inclusive(ciphertext)
+        } else {
+            RangeBound::exclusive(ciphertext)
+        };
+        Some(bound.with_scheme(RangeScheme::Ore))
+    }
+
+    /// Ids of the records with a ciphertext within the bounds.
+    async fn range(
+        transaction: &mut dyn StorageTransaction,
+        lower_limit: Option<RangeBound>,
+        upper_limit: Option<RangeBound>,
+    ) -> String {
+        let query = SingleQuery {
+            collection: String::from("prices"),
+            usecase: String::from("price"),
+            lower_limit,
+            upper_limit,
+        };
+        let mut ids: Vec<String> = scan(transaction, &query)
+            .await
+            .unwrap()
+            .iter()
+            .map(|key| key["prices:".len()..].to_string())
+            .collect();
+        ids.sort();
+        ids.concat()
+    }
+
+    #[tokio::test]
+    async fn test_scan_bounds() {
+        let ore = Ore::new(b"test_key");
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        for (id, value) in [("a", 3), ("b", 10), ("c", 10), ("d", 200)] {
+            let ciphertext = ore.encrypt_right(&[value]).to_bytes();
+            check_ciphertext(&ciphertext).unwrap();
+            let key = index_key("prices", "price", id);
+            transaction.put(key, ciphertext).await.unwrap();
+        }
+        let other = index_key("prices", "other", "e");
+        let ciphertext = ore.encrypt_right(&[5]).to_bytes();
+        transaction.put(other, ciphertext).await.unwrap();
+
+        let transaction = transaction.as_mut();
+        assert_eq!(range(transaction, None, None).await, "abcd");
+        assert_eq!(range(transaction, bound(&ore, 10, true), None).await, "bcd");
+        assert_eq!(range(transaction, bound(&ore, 10, false), None).await, "d");
+        assert_eq!(
+            range(transaction, bound(&ore, 9, false), bound(&ore, 10, true)).await,
+            "bc"
+        );
+        assert_eq!(
+            range(transaction, bound(&ore, 0, true), bound(&ore, 10, false)).await,
+            "a"
+        );
+        assert_eq!(range(transaction, None, bound(&ore, 3, false)).await, "");
+        assert_eq!(range(transaction, None, bound(&ore, 255, true)).await, "abcd");
+
+        let longer = Some(RangeBound::inclusive(ore.encrypt_left(&[1, 2]).to_bytes()));
+        assert_eq!(range(transaction, longer.clone(), None).await, "");
+        let query = SingleQuery {
+            lower_limit: longer,
+            upper_limit: bound(&ore, 10, true),
+            ..SingleQuery::new(String::from("prices"), String::from("price"))
+        };
+        assert!(matches!(scan(transaction, &query).await, Err(Error::InvalidCiphertext)));
+        assert!(matches!(check_ciphertext(b"short"), Err(Error::InvalidCiphertext)));
+    }
+
+    #[tokio::test]
+    async fn test_scan_skips_invalid_entries() {
+        let ore = Ore::new(b"test_key");
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        let ciphertext = ore.encrypt_right(&[3]).to_bytes();
+        for id in ["a", "b:nonce"] {
+            let key = index_key("prices", "price", id);
+            transaction.put(key, ciphertext.clone()).await.unwrap();
+        }
+        let corrupt = index_key("prices", "price", "c");
+        transaction.put(corrupt, b"corrupt".to_vec()).await.unwrap();
+
+        let transaction = transaction.as_mut();
+        assert_eq!(range(transaction, None, None).await, "a");
+        assert_eq!(range(transaction, bound(&ore, 3, true), None).await, "a");
+    }
+}
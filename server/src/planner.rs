@@ -0,0 +1,146 @@
+//! Planning of the queries before they are evaluated by the query engine.
+//!
+//! The number of records matched by a usecase is the size of its index, which is
+//! cheap to read. The sub-queries of an `And` are sorted by this estimate so the most
+//! selective one is evaluated first.
+
+use futures::future::{BoxFuture, FutureExt};
+use liserk_shared::query::{CompoundQuery, Query, QueryPlan, QueryType, SingleQuery};
+
+use crate::query_engine::{compute_length_of_cell, dedup};
+use crate::storage::StorageTransaction;
+use crate::Error;
+
+/// Plans `query`, reading the size of the usecase indexes it uses.
+pub fn plan<'a>(
+    client: &'a mut dyn StorageTransaction,
+    query: &'a Query,
+) -> BoxFuture<'a, Result<QueryPlan, Error>> {
+    async move {
+        match query {
+            Query::Single(single_query) => plan_single_query(client, single_query).await,
+            Query::Compound(compound_query) => {
+                plan_compound_query(client, compound_query).await
+            }
+            Query::GetById { id, collection }
+            | Query::GetVersion { id, collection, .. } => Ok(QueryPlan::Ids {
+                collection: collection.clone(),
+                ids: vec![id.clone()],
+            }),
+            Query::GetByIds { ids, collection } => Ok(QueryPlan::Ids {
+                collection: collection.clone(),
+                ids: dedup(ids.clone()),
+            }),
+            Query::Equals { collection, token } => {
+                let single_query = SingleQuery::new(collection.clone(), token.clone());
+                plan_single_query(client, &single_query).await
+            }
+            Query::Explain(_) => Err(Error::InvalidQuery(String::from(
+                "Explain can only be the outermost query",
+            ))),
+        }
+    }
+    .boxed()
+}
+
+async fn plan_single_query(
+    client: &mut dyn StorageTransaction,
+    single_query: &SingleQuery,
+) -> Result<QueryPlan, Error> {
+    let key = format!("{}:{}:usecase", single_query.collection, single_query.usecase);
+    let index = client.get(key.into()).await?;
+    let mut estimated_rows = compute_length_of_cell(index)?;
+    // Nothing is known about the distribution of the values, assume each bound keeps
+    // half of the records.
+    for bound in [&single_query.lower_limit, &single_query.upper_limit] {
+        if bound.is_some() {
+            estimated_rows = estimated_rows.div_ceil(2);
+        }
+    }
+    Ok(QueryPlan::Usecase { query: single_query.clone(), estimated_rows })
+}
+
+pub async fn plan_compound_query(
+    client: &mut dyn StorageTransaction,
+    compound_query: &CompoundQuery,
+) -> Result<QueryPlan, Error> {
+    let mut steps = Vec::with_capacity(compound_query.queries.len());
+    for query in &compound_query.queries {
+        steps.push(plan(client, query).await?);
+    }
+    let estimated_rows = match compound_query.query_type {
+        QueryType::And => {
+            steps.sort_by_key(QueryPlan::estimated_rows);
+            steps.iter().map(QueryPlan::estimated_rows).min().unwrap_or(0)
+        }
+        QueryType::Or => steps
+            .iter()
+            .map(QueryPlan::estimated_rows)
+            .fold(0, u32::saturating_add),
+    };
+    Ok(QueryPlan::Compound {
+        query_type: compound_query.query_type.clone(),
+        steps,
+        estimated_rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use liserk_shared::query::{RangeBound, SingleQueryBuilder};
+
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+
+    fn single(usecase: &str) -> SingleQuery {
+        SingleQuery::new(String::from("fruits"), usecase.into())
+    }
+
+    #[tokio::test]
+    async fn test_most_selective_step_first() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        for (usecase, size) in [("red", 8), ("small", 2), ("price", 10)] {
+            let index: Vec<Vec<u8>> = (0..size).map(|i| vec![i]).collect();
+            let key = format!("fruits:{}:usecase", usecase);
+            transaction
+                .put(key.into_bytes(), serde_cbor::to_vec(&index).unwrap())
+                .await
+                .unwrap();
+        }
+        let cheap = SingleQueryBuilder::default()
+            .with_collection(String::from("fruits"))
+            .with_usecase(String::from("price"))
+            .with_upper_bound(RangeBound::exclusive(b"10".to_vec()))
+            .build();
+        let or = Query::Compound(CompoundQuery::new(
+            QueryType::Or,
+            vec![Query::Single(single("red")), Query::Single(single("small"))],
+        ));
+        let query = CompoundQuery::new(
+            QueryType::And,
+            vec![
+                Query::Single(cheap.clone()),
+                or,
+                Query::Single(single("small")),
+                Query::Single(single("unknown")),
+            ],
+        );
+
+        let compound = plan_compound_query(transaction.as_mut(), &query).await.unwrap();
+        let QueryPlan::Compound { steps, estimated_rows, .. } = &compound else {
+            panic!("unexpected plan {:?}", compound);
+        };
+        assert_eq!(*estimated_rows, 0);
+        let estimates: Vec<u32> = steps.iter().map(QueryPlan::estimated_rows).collect();
+        assert_eq!(estimates, [0, 2, 5, 10]);
+        assert_eq!(steps[2], QueryPlan::Usecase { query: cheap, estimated_rows: 5 });
+        assert!(compound.to_string().contains("usecase fruits:price < 10 (~5 rows)"));
+
+        let explain = Query::Explain(Box::new(Query::Single(single("red"))));
+        assert!(matches!(
+            plan(transaction.as_mut(), &explain).await,
+            Err(Error::InvalidQuery(_))
+        ));
+    }
+}
@@ -1,58 +1,117 @@
+use std::collections::{HashMap, HashSet};
+
 use async_channel::Sender;
+use futures::future::{BoxFuture, FutureExt};
 use liserk_shared::{
+    acl::Permission,
     message::{CountSubject, Message, QueryOutput},
     query::*,
 };
-use rug::Float;
-use tikv_client::{KvPair, Transaction, TransactionClient};
-use tracing::{debug, error, info};
-
-use crate::{command::Command, config::TIKV_URL, Error};
+use tracing::{debug, info};
+
+use crate::{
+    acl,
+    auth::User,
+    catalog,
+    command::Command,
+    ore_index, planner, range_index,
+    storage::{Key, KvPair, StorageBackend, StorageTransaction, Value},
+    version, Error,
+};
 
 /// Encrypted data used in Repsonse
 pub type EncryptedData = Vec<KvPair>;
 
-/// Nonces Nonce is used for complexifie AES encryption
-pub type Nonces = Vec<KvPair>;
+/// Nonce of each record, `None` for the OPE records which are not encrypted with AES
+pub type Nonces = Vec<Option<Value>>;
 
 /// QueryResponse Represent a query
 pub type QueryResponse = (EncryptedData, Option<Nonces>);
 
-pub async fn handle_query(query: Query, tx: Sender<Message>) -> Result<Command, Error> {
-    let client = TransactionClient::new(vec![TIKV_URL]).await;
-    let client = client.expect("failed to connet to tikv");
-    let mut transaction = client.begin_optimistic().await?;
+pub async fn handle_query(
+    storage: &dyn StorageBackend,
+    user: &User,
+    query: Query,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    check_collections(&query)?;
+    let mut transaction = storage.begin().await?;
     let message_converter = MessageConverter::default();
 
     let message = match query {
         Query::Single(single_query) => {
-            let data = handle_single_query(&mut transaction, single_query).await?;
+            let data =
+                handle_single_query(transaction.as_mut(), user, single_query).await?;
             message_converter.convert_to_message(data)
         }
         Query::Compound(compound_query) => {
-            let data = handle_compound_query(&mut transaction, compound_query).await?;
+            let data =
+                handle_compound_query(transaction.as_mut(), user, compound_query).await?;
             message_converter.convert_to_message(data)
         }
         Query::GetById { id, collection } => {
-            let (data, nonce) = get_by_id(&mut transaction, id, collection).await?;
-            Message::SingleValueResponse { data, nonce }
+            let (data, nonce, version) =
+                get_by_id(transaction.as_mut(), user, id, collection).await?;
+            Message::SingleValueResponse { data, nonce, version }
+        }
+        Query::GetVersion { id, collection, version } => {
+            let (data, nonce) =
+                get_version(transaction.as_mut(), user, id, collection, version).await?;
+            let version = data.as_ref().map(|_| version);
+            Message::SingleValueResponse { data, nonce, version }
         }
         Query::GetByIds { ids, collection } => {
-            let (data, nonce) = get_by_ids(&mut transaction, ids, collection).await?;
+            let (data, nonce) =
+                get_by_ids(transaction.as_mut(), user, ids, collection).await?;
             let formated = (data, Some(nonce));
             message_converter.convert_to_message(formated)
         }
+        Query::Equals { collection, token } => {
+            let single_query = SingleQuery::new(collection, token);
+            let data =
+                handle_single_query(transaction.as_mut(), user, single_query).await?;
+            message_converter.convert_to_message(data)
+        }
+        Query::Explain(query) => {
+            // The plan reveals the size of the indexes, like a count.
+            for collection in collections(&query) {
+                catalog::check_access(transaction.as_mut(), user, collection).await?;
+            }
+            Message::ExplainResponse(planner::plan(transaction.as_mut(), &query).await?)
+        }
     };
     transaction.commit().await?;
 
-    info!("data found {:?}", message);
-    if let Err(err) = tx.send(message).await {
-        error!("error while sending QueryResponse: {:?}", err);
-    }
-    // Todo send in a channel a message Ok(Command::Continue)
+    let records = match &message {
+        Message::QueryResponse((data, _)) => data.len(),
+        Message::SingleValueResponse { data, .. } => usize::from(data.is_some()),
+        _ => 0,
+    };
+    debug!("{} records found", records);
+    tx.send(message).await?;
     Ok(Command::Continue)
 }
 
+/// Refuses the queries on a reserved collection, see [`catalog::check_collection`].
+fn check_collections(query: &Query) -> Result<(), Error> {
+    collections(query).into_iter().try_for_each(catalog::check_collection)
+}
+
+/// Collections read by `query`.
+fn collections(query: &Query) -> Vec<&str> {
+    match query {
+        Query::Single(single_query) => vec![single_query.collection.as_str()],
+        Query::Compound(compound_query) => {
+            compound_query.queries.iter().flat_map(collections).collect()
+        }
+        Query::GetById { collection, .. }
+        | Query::GetByIds { collection, .. }
+        | Query::GetVersion { collection, .. }
+        | Query::Equals { collection, .. } => vec![collection.as_str()],
+        Query::Explain(query) => collections(query),
+    }
+}
+
 trait TokioSender {
     fn serialize_kv_pairs(pairs: &Vec<KvPair>) -> Vec<Vec<u8>> {
         let mut serialized_pairs = Vec::new();
@@ -67,10 +126,7 @@ trait TokioSender {
 
         let serialized_encrypted_data = Self::serialize_kv_pairs(&encrypted_data);
 
-        let serialized_nonces =
-            nonces_option.as_ref().map(|nonces| Self::serialize_kv_pairs(&nonces));
-
-        (serialized_encrypted_data, serialized_nonces)
+        (serialized_encrypted_data, nonces_option)
     }
 
     fn convert_to_message(&self, response: QueryResponse) -> Message {
@@ -85,64 +141,111 @@ struct MessageConverter {}
 impl TokioSender for MessageConverter {}
 
 async fn get_by_id(
-    client: &mut Transaction,
+    client: &mut dyn StorageTransaction,
+    user: &User,
     id: String,
     collection: String,
-) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), Error> {
+) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>, Option<u64>), Error> {
     let key = format!("{}:{}", collection, id);
+    if !acl::require(client, user, &key, Permission::Read).await? {
+        return Ok((None, None, None));
+    }
     let key_nonce = format!("{}:{}:nonce", collection, id);
-    let data = client.get(key).await?;
-    let nonce = client.get(key_nonce).await?;
-    Ok((data, nonce))
+    let Some(data) = client.get(key.clone().into()).await? else {
+        return Ok((None, None, None));
+    };
+    let nonce = client.get(key_nonce.into()).await?;
+    let version = version::load(client, &key).await?;
+    Ok((Some(data), nonce, Some(version)))
+}
+
+/// Reads a record at its current version, or a previous version kept in its history.
+async fn get_version(
+    client: &mut dyn StorageTransaction,
+    user: &User,
+    id: String,
+    collection: String,
+    version: u64,
+) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), Error> {
+    let key = format!("{}:{}", collection, id);
+    if !acl::require(client, user, &key, Permission::Read).await? {
+        return Ok((None, None));
+    }
+    if version::load(client, &key).await? == version {
+        let (data, nonce, _) = get_by_id(client, user, id, collection).await?;
+        return Ok((data, nonce));
+    }
+    match version::get(client, &key, version).await? {
+        Some(entry) => Ok((Some(entry.value), entry.nonce)),
+        None => Ok((None, None)),
+    }
 }
 
 async fn get_by_ids(
-    client: &mut Transaction,
+    client: &mut dyn StorageTransaction,
+    user: &User,
     ids: Vec<String>,
     collection: String,
-) -> Result<(Vec<KvPair>, Vec<KvPair>), Error> {
-    let keys: Vec<String> =
-        ids.iter().map(|id| format!("{}:{}", collection, id)).collect();
-    let results = fetch_data_from_keys(client, keys.clone()).await?;
-    let nonces = fetch_nonce_from_keys(client, keys).await?;
-
-    Ok((results, nonces))
+) -> Result<(Vec<KvPair>, Nonces), Error> {
+    let keys = dedup(ids.iter().map(|id| format!("{}:{}", collection, id)).collect());
+    let keys = acl::filter_readable(client, user, keys).await?;
+    fetch_records(client, keys).await
 }
 
 async fn handle_single_query(
-    client: &mut Transaction,
+    client: &mut dyn StorageTransaction,
+    user: &User,
     single_query: SingleQuery,
 ) -> Result<QueryResponse, Error> {
+    let data_keys = single_query_keys(client, &single_query).await?;
+    let data_keys = acl::filter_readable(client, user, data_keys).await?;
+    if range_scheme(&single_query)? == Some(RangeScheme::Ope) {
+        let results = fetch_data_from_keys(client, data_keys).await?;
+        return Ok((results, None));
+    }
+    let (results, nonces) = fetch_records(client, data_keys).await?;
+    Ok((results, Some(nonces)))
+}
+
+/// Returns the keys of the records inserted with the usecase of `single_query`.
+async fn usecase_keys(
+    client: &mut dyn StorageTransaction,
+    single_query: &SingleQuery,
+) -> Result<Vec<String>, Error> {
     let key = format!("{}:{}:usecase", single_query.collection, single_query.usecase);
     info!("key: {}", key);
 
-    match client.get(key.clone()).await? {
-        Some(value) => {
-            println!("Got value for key {}: {:?}", key, value);
-            let data_keys = extract_data_keys_from_value(value)?;
-            let mut results = fetch_data_from_keys(client, data_keys.clone()).await?;
-            if is_ope_query(&single_query) {
-                results =
-                    filter_results_by_upper_limit(results, single_query.upper_limit);
-                results =
-                    filter_results_by_lower_limit(results, single_query.lower_limit);
-            } else {
-                let nonce = fetch_nonce_from_keys(client, data_keys).await?;
-
-                return Ok((results, Some(nonce)));
-            }
-
-            Ok((results, None))
-        }
+    match client.get(key.clone().into()).await? {
+        Some(value) => Ok(dedup(extract_data_keys_from_value(value)?)),
         None => {
             debug!("No value found for key {}", key);
-            Ok((Vec::new(), None))
+            Ok(Vec::new())
         }
     }
 }
 
-fn is_ope_query(query: &SingleQuery) -> bool {
-    query.upper_limit.is_some() || query.lower_limit.is_some()
+/// Returns the keys of the records matched by `single_query`, read from the range
+/// index of the scheme of its bounds if it has some.
+async fn single_query_keys(
+    client: &mut dyn StorageTransaction,
+    single_query: &SingleQuery,
+) -> Result<Vec<String>, Error> {
+    match range_scheme(single_query)? {
+        Some(RangeScheme::Ope) => range_index::scan(client, single_query).await,
+        Some(RangeScheme::Ore) => ore_index::scan(client, single_query).await,
+        None => usecase_keys(client, single_query).await,
+    }
+}
+
+/// Scheme of the bounds of `query`, `None` if it has no bound.
+fn range_scheme(query: &SingleQuery) -> Result<Option<RangeScheme>, Error> {
+    match (&query.lower_limit, &query.upper_limit) {
+        (Some(lower), Some(upper)) if lower.scheme != upper.scheme => {
+            Err(Error::InvalidQuery(String::from("the bounds have different schemes")))
+        }
+        (Some(bound), _) | (None, Some(bound)) => Ok(Some(bound.scheme)),
+        (None, None) => Ok(None),
+    }
 }
 
 fn extract_data_keys_from_value(value: Vec<u8>) -> Result<Vec<String>, Error> {
@@ -154,152 +257,149 @@ fn extract_data_keys_from_value(value: Vec<u8>) -> Result<Vec<String>, Error> {
 }
 
 async fn fetch_data_from_keys(
-    client: &mut Transaction,
+    client: &mut dyn StorageTransaction,
     data_keys: Vec<String>,
 ) -> Result<Vec<KvPair>, Error> {
-    Ok(client.batch_get(data_keys).await?.collect())
+    let data_keys = data_keys.into_iter().map(String::into_bytes).collect();
+    Ok(client.batch_get(data_keys).await?)
 }
 
 async fn fetch_nonce_from_keys(
-    client: &mut Transaction,
+    client: &mut dyn StorageTransaction,
     data_keys: Vec<String>,
 ) -> Result<Vec<KvPair>, Error> {
-    let nonce_key: Vec<String> =
-        data_keys.iter().map(|key| key.to_owned() + ":nonce").collect();
-    Ok(client.batch_get(nonce_key).await?.collect())
+    let nonce_key: Vec<Vec<u8>> = data_keys
+        .iter()
+        .map(|key| (key.to_owned() + ":nonce").into_bytes())
+        .collect();
+    Ok(client.batch_get(nonce_key).await?)
 }
 
-fn filter_results_by_upper_limit(
-    mut results: Vec<KvPair>,
-    upper_limit: Option<f64>,
-) -> Vec<KvPair> {
-    if let Some(upper_limit) = upper_limit {
-        let upper_limit = Float::with_val(53, upper_limit); // 53 bits precision (similar to f64)
-        results = results
+/// Fetches the data and the nonces of `data_keys`, each nonce is at the same position
+/// as its data. The records without data are skipped.
+async fn fetch_records(
+    client: &mut dyn StorageTransaction,
+    data_keys: Vec<String>,
+) -> Result<(Vec<KvPair>, Nonces), Error> {
+    let mut data: HashMap<Key, Value> = fetch_data_from_keys(client, data_keys.clone())
+        .await?
+        .into_iter()
+        .collect();
+    let mut nonces: HashMap<Key, Value> =
+        fetch_nonce_from_keys(client, data_keys.clone())
+            .await?
             .into_iter()
-            .filter(|kv_pair| {
-                let deserialized_value: f64 =
-                    serde_cbor::from_slice(&kv_pair.1).unwrap_or(0.0);
-                let value_as_float = Float::with_val(53, deserialized_value);
-                value_as_float <= upper_limit
-            })
             .collect();
+
+    let mut records = (Vec::new(), Vec::new());
+    for data_key in data_keys {
+        let data_key = data_key.into_bytes();
+        let nonce_key = [&data_key[..], b":nonce"].concat();
+        if let Some(value) = data.remove(&data_key) {
+            records.0.push((data_key, value));
+            records.1.push(nonces.remove(&nonce_key));
+        }
     }
-    results
+    Ok(records)
 }
 
-fn filter_results_by_lower_limit(
-    mut results: Vec<KvPair>,
-    lower_limit: Option<f64>,
-) -> Vec<KvPair> {
-    if let Some(lower_limit) = lower_limit {
-        let lower_limit = Float::with_val(53, lower_limit); // 53 bits precision (similar to f64)
-        results = results
-            .into_iter()
-            .filter(|kv_pair| {
-                let deserialized_value: f64 =
-                    serde_cbor::from_slice(&kv_pair.1).unwrap_or(0.0);
-                let value_as_float = Float::with_val(53, deserialized_value);
-                value_as_float >= lower_limit
-            })
-            .collect();
-    }
-    results
+async fn handle_compound_query(
+    client: &mut dyn StorageTransaction,
+    user: &User,
+    compound_query: CompoundQuery,
+) -> Result<QueryResponse, Error> {
+    let data_keys = evaluate_compound_query(client, &compound_query).await?;
+    debug!("keys {:?}", data_keys);
+    let data_keys = acl::filter_readable(client, user, data_keys).await?;
+    let (data, nonces) = fetch_records(client, data_keys).await?;
+    Ok((data, Some(nonces)))
 }
 
-fn retrieve_keys_from_query(compound_query: &CompoundQuery) -> Vec<String> {
-    compound_query
-        .queries
-        .iter()
-        .filter_map(|query| match query {
-            Query::Single(single_query) => {
-                Some(format!("{}:{}", single_query.collection, single_query.usecase))
-            }
-            _ => None,
-        })
-        .collect()
+async fn evaluate_compound_query(
+    client: &mut dyn StorageTransaction,
+    compound_query: &CompoundQuery,
+) -> Result<Vec<String>, Error> {
+    let plan = planner::plan_compound_query(client, compound_query).await?;
+    debug!("plan {:?}", plan);
+    execute(client, &plan).await
 }
 
-async fn handle_compound_query(
-    client: &mut Transaction,
-    compound_query: CompoundQuery,
-) -> Result<(Vec<KvPair>, Option<Vec<KvPair>>), Error> {
-    let keys = retrieve_keys_from_query(&compound_query);
-    debug!("keys {:?}", keys);
-
-    match compound_query.query_type {
-        QueryType::And => {
-            let values = get_kvpair_from_keys(keys, client).await?;
-
-            for (key, value) in values {
-                println!("Got value for key {}: {:?}", key, value);
-                let data_keys: Vec<String> =
-                    serde_cbor::from_slice::<Vec<Vec<u8>>>(&value)?
-                        .iter()
-                        .map(|data_key| String::from_utf8_lossy(data_key).to_string())
-                        .collect();
-                let data = fetch_data_from_keys(client, data_keys.clone()).await?;
-                let nonce = fetch_nonce_from_keys(client, data_keys).await?;
-                return Ok((data, Some(nonce)));
+/// Executes `plan` and returns the keys of the records it matches, without
+/// duplicates.
+fn execute<'a>(
+    client: &'a mut dyn StorageTransaction,
+    plan: &'a QueryPlan,
+) -> BoxFuture<'a, Result<Vec<String>, Error>> {
+    async move {
+        match plan {
+            QueryPlan::Usecase { query, .. } => single_query_keys(client, query).await,
+            QueryPlan::Ids { collection, ids } => {
+                Ok(ids.iter().map(|id| format!("{}:{}", collection, id)).collect())
             }
-            debug!("No values found for keys");
-            Ok((Vec::new(), None))
-        }
-        QueryType::Or => {
-            let values = get_kvpair_from_keys(keys, client).await?;
-
-            for (key, value) in values {
-                debug!("Got value for key {}: {:?}", key, value);
-                let data_keys: Vec<String> =
-                    serde_cbor::from_slice::<Vec<Vec<u8>>>(&value)?
-                        .iter()
-                        .map(|data_key| String::from_utf8_lossy(data_key).to_string())
-                        .collect();
-                let data = fetch_data_from_keys(client, data_keys.clone()).await?;
-                let nonce = fetch_nonce_from_keys(client, data_keys).await?;
-                return Ok((data, Some(nonce)));
+            QueryPlan::Compound { query_type: QueryType::Or, steps, .. } => {
+                let mut data_keys = Vec::new();
+                for step in steps {
+                    data_keys.extend(execute(client, step).await?);
+                }
+                Ok(dedup(data_keys))
+            }
+            QueryPlan::Compound { query_type: QueryType::And, steps, .. } => {
+                let mut steps = steps.iter();
+                let Some(first) = steps.next() else {
+                    return Ok(Vec::new());
+                };
+                let mut data_keys = execute(client, first).await?;
+                for step in steps {
+                    if data_keys.is_empty() {
+                        debug!("no record left, skipping the remaining steps");
+                        break;
+                    }
+                    data_keys = intersect(data_keys, execute(client, step).await?);
+                }
+                Ok(data_keys)
             }
-            debug!("No values found for keys");
-            Ok((Vec::new(), None))
         }
     }
+    .boxed()
 }
 
-async fn get_kvpair_from_keys(
-    keys: Vec<String>,
-    transaction: &mut Transaction,
-) -> Result<Vec<(String, Vec<u8>)>, Error> {
-    let kv_pairs = transaction.batch_get(keys).await?;
-    let kv_pairs = kv_pairs
-        .map(|pair| {
-            let key = pair.0;
-            let key_string: String = String::from_utf8_lossy((&key).into()).to_string();
-            let value = pair.1;
-            (key_string, value)
-        })
-        .collect();
-    Ok(kv_pairs)
+/// Keeps the keys of `keys` which are in `other`, in the order of `keys`.
+fn intersect(keys: Vec<String>, other: Vec<String>) -> Vec<String> {
+    let other: HashSet<String> = other.into_iter().collect();
+    keys.into_iter().filter(|key| other.contains(key)).collect()
 }
 
-pub async fn count(count: CountSubject, tx: Sender<Message>) -> Result<Command, Error> {
-    let key = match count {
+pub fn dedup(keys: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    keys.into_iter().filter(|key| seen.insert(key.clone())).collect()
+}
+
+/// Counts the records of a collection or a usecase, only its owner and the
+/// administrators can count them.
+pub async fn count(
+    storage: &dyn StorageBackend,
+    user: &User,
+    count: CountSubject,
+    tx: &Sender<Message>,
+) -> Result<Command, Error> {
+    let mut transaction = storage.begin().await?;
+    let length = match count {
         CountSubject::Collection(collection) => {
-            format!("{}:keys", collection)
+            let info = catalog::describe(transaction.as_mut(), user, &collection).await?;
+            info.map_or(0, |info| info.record_count as u32)
         }
         CountSubject::Usecase { collection, usecase } => {
-            format!("{}:{}:usecase", collection, usecase)
+            catalog::check_access(transaction.as_mut(), user, &collection).await?;
+            let key = format!("{}:{}:usecase", collection, usecase);
+            compute_length_of_cell(transaction.get(key.into()).await?)?
         }
     };
-    let client = TransactionClient::new(vec![TIKV_URL]).await?;
-    let mut transaction = client.begin_optimistic().await?;
-    let values = transaction.get(key).await?;
     transaction.commit().await?;
-    let length = compute_length_of_cell(values)?;
     tx.send(Message::CountResponse(length)).await?;
     Ok(Command::Continue)
 }
 
-fn compute_length_of_cell(values: Option<Vec<u8>>) -> Result<u32, Error> {
+pub fn compute_length_of_cell(values: Option<Vec<u8>>) -> Result<u32, Error> {
     if values.is_none() {
         return Ok(0);
     }
@@ -309,3 +409,146 @@ fn compute_length_of_cell(values: Option<Vec<u8>>) -> Result<u32, Error> {
     .iter()
     .count() as u32)
 }
+
+#[cfg(test)]
+mod tests {
+    use liserk_shared::message::{Insertion, InsertionOpe};
+
+    use super::*;
+    use crate::mutation;
+    use crate::storage::EmbeddedBackend;
+    use crate::test_utils::create_user;
+
+    async fn insert(
+        storage: &EmbeddedBackend,
+        user: &User,
+        data: u8,
+        usecases: &[&str],
+    ) -> String {
+        let insertion = Insertion {
+            collection: String::from("fruits"),
+            acl: vec![],
+            data: vec![data],
+            usecases: usecases.iter().map(|usecase| usecase.to_string()).collect(),
+            nonce: vec![data; 12],
+            searchable: false,
+        };
+        let id = mutation::insert(storage, user, insertion).await.unwrap();
+        format!("fruits:{}", id)
+    }
+
+    fn single(usecase: &str) -> Query {
+        Query::Single(SingleQuery::new(String::from("fruits"), usecase.into()))
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_nested_compound_query() {
+        let storage = EmbeddedBackend::in_memory();
+        let user = create_user(&storage, "alice", false, &[]).await;
+        let apple = insert(&storage, &user, 1, &["red", "sweet"]).await;
+        let cherry = insert(&storage, &user, 2, &["red", "sweet", "small"]).await;
+        let lemon = insert(&storage, &user, 3, &["yellow", "sour"]).await;
+        let price = InsertionOpe {
+            collection: String::from("fruits"),
+            acl: vec![],
+            data: b"12".to_vec(),
+            usecases: vec![String::from("price")],
+        };
+        let price = format!(
+            "fruits:{}",
+            mutation::insert_ope(&storage, &user, price).await.unwrap()
+        );
+
+        let mut transaction = storage.begin().await.unwrap();
+        let client = transaction.as_mut();
+
+        // (red AND sweet) OR sour, cherry is matched twice but returned once.
+        let query = CompoundQuery::new(
+            QueryType::Or,
+            vec![
+                Query::Compound(CompoundQuery::new(
+                    QueryType::And,
+                    vec![single("red"), single("sweet")],
+                )),
+                single("sour"),
+                single("small"),
+            ],
+        );
+        let keys = evaluate_compound_query(client, &query).await.unwrap();
+        assert_eq!(keys, vec![apple.clone(), cherry.clone(), lemon.clone()]);
+
+        let query = CompoundQuery::new(
+            QueryType::And,
+            vec![single("sweet"), Query::Compound(query), single("small")],
+        );
+        let keys = evaluate_compound_query(client, &query).await.unwrap();
+        assert_eq!(keys, vec![cherry.clone()]);
+
+        // The usecases of a record may be SSE tokens.
+        let small = Query::Equals {
+            collection: String::from("fruits"),
+            token: String::from("small"),
+        };
+        let query = CompoundQuery::new(QueryType::And, vec![single("red"), small]);
+        let keys = evaluate_compound_query(client, &query).await.unwrap();
+        assert_eq!(keys, vec![cherry.clone()]);
+
+        let cheap = SingleQueryBuilder::default()
+            .with_collection(String::from("fruits"))
+            .with_usecase(String::from("price"))
+            .with_upper_bound(RangeBound::inclusive(b"10".to_vec()))
+            .build();
+        let query = CompoundQuery::new(
+            QueryType::Or,
+            vec![Query::Single(cheap.clone()), single("sour")],
+        );
+        let keys = evaluate_compound_query(client, &query).await.unwrap();
+        assert_eq!(keys, vec![lemon.clone()]);
+        let expensive = SingleQuery {
+            upper_limit: None,
+            lower_limit: Some(RangeBound::inclusive(b"10".to_vec())),
+            ..cheap
+        };
+        let query = CompoundQuery::new(
+            QueryType::Or,
+            vec![Query::Single(expensive), single("sour")],
+        );
+        let keys = evaluate_compound_query(client, &query).await.unwrap();
+        assert_eq!(keys, vec![price, lemon.clone()]);
+
+        let (data, nonces) = handle_compound_query(
+            client,
+            &user,
+            CompoundQuery::new(QueryType::Or, vec![single("sour"), single("sweet")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(data.iter().map(|(_, value)| value[0]).collect::<Vec<_>>(), [3, 1, 2]);
+        let nonces = nonces.unwrap();
+        assert_eq!(
+            nonces
+                .iter()
+                .map(|nonce| nonce.as_ref().unwrap()[0])
+                .collect::<Vec<_>>(),
+            [3, 1, 2]
+        );
+
+        // The OPE records have no nonce but are still returned.
+        let query =
+            CompoundQuery::new(QueryType::Or, vec![single("sour"), single("price")]);
+        let (data, nonces) = handle_compound_query(client, &user, query).await.unwrap();
+        let data: Vec<Value> = data.into_iter().map(|(_, value)| value).collect();
+        assert_eq!(data, [vec![3], b"12".to_vec()]);
+        assert_eq!(nonces.unwrap(), [Some(vec![3; 12]), None]);
+        let (data, nonces) = handle_single_query(
+            client,
+            &user,
+            SingleQuery::new(String::from("fruits"), String::from("price")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(nonces.unwrap(), [None]);
+        transaction.commit().await.unwrap();
+    }
+}
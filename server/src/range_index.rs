@@ -0,0 +1,208 @@
+//! Ordered index of the OPE ciphertexts of each usecase.
+//!
+//! Every record inserted with `InsertOpe` gets, for each of its usecases, the key
+//! `collection:usecase:ope:<ciphertext><id>` pointing to its data key. The ciphertext
+//! is encoded as a big-endian unsigned integer of [`CIPHERTEXT_WIDTH`] bytes, so the
+//! keys sort like the ciphertexts, which sort like the plaintexts. A range query is
+//! then a scan between the encoded bounds.
+
+use liserk_shared::query::{SingleQuery, OPE_CIPHERTEXT_WIDTH};
+use rug::integer::Order;
+use rug::Integer;
+use tracing::warn;
+
+use crate::storage::{Key, StorageTransaction};
+use crate::Error;
+
+/// Size of an encoded ciphertext, larger ciphertexts are refused.
+pub const CIPHERTEXT_WIDTH: usize = OPE_CIPHERTEXT_WIDTH;
+
+fn index_prefix(collection: &str, usecase: &str) -> Vec<u8> {
+    format!("{}:{}:ope:", collection, usecase).into_bytes()
+}
+
+pub fn index_key(collection: &str, usecase: &str, ciphertext: &[u8], id: &str) -> Key {
+    [&index_prefix(collection, usecase), ciphertext, id.as_bytes()].concat()
+}
+
+/// Encodes `integer` on [`CIPHERTEXT_WIDTH`] bytes, `None` if it doesn't fit.
+fn encode(integer: &Integer) -> Option<Vec<u8>> {
+    if *integer < 0 {
+        return None;
+    }
+    let digits: Vec<u8> = integer.to_digits(Order::Msf);
+    let padding = CIPHERTEXT_WIDTH.checked_sub(digits.len())?;
+    Some([vec![0; padding], digits].concat())
+}
+
+/// Reads a ciphertext as sent by the client, the decimal representation of the
+/// integer returned by the OPE. Signs, fractions and exponents are refused.
+fn parse_ciphertext(ciphertext: &[u8]) -> Result<Integer, Error> {
+    if ciphertext.is_empty() || !ciphertext.iter().all(u8::is_ascii_digit) {
+        return Err(Error::InvalidCiphertext);
+    }
+    let ciphertext = std::str::from_utf8(ciphertext).expect("digits are ASCII");
+    Integer::from_str_radix(ciphertext, 10).map_err(|_| Error::InvalidCiphertext)
+}
+
+/// Encodes a ciphertext as sent by the client for the range index.
+pub fn encode_ciphertext(ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+    encode(&parse_ciphertext(ciphertext)?).ok_or(Error::InvalidCiphertext)
+}
+
+/// Returns the data keys of the records of the usecase of `single_query` whose
+/// ciphertext is within its bounds. The entries which don't point to a record of the
+/// collection are skipped.
+pub async fn scan(
+    transaction: &mut dyn StorageTransaction,
+    single_query: &SingleQuery,
+) -> Result<Vec<String>, Error> {
+    let prefix = index_prefix(&single_query.collection, &single_query.usecase);
+    let lowest = match &single_query.lower_limit {
+        Some(bound) if bound.inclusive => parse_ciphertext(&bound.ciphertext)?,
+        Some(bound) => parse_ciphertext(&bound.ciphertext)? + 1u32,
+        None => Integer::new(),
+    };
+    // First ciphertext after the range, `None` if the range has no end.
+    let after = match &single_query.upper_limit {
+        Some(bound) if bound.inclusive => {
+            Some(parse_ciphertext(&bound.ciphertext)? + 1u32)
+        }
+        Some(bound) => Some(parse_ciphertext(&bound.ciphertext)?),
+        None => None,
+    };
+    if after.as_ref().is_some_and(|after| *after <= lowest) {
+        return Ok(Vec::new());
+    }
+
+    let Some(start) = encode(&lowest) else {
+        // No ciphertext of the index is that large.
+        return Ok(Vec::new());
+    };
+    let start = [&prefix[..], &start].concat();
+    let end = match after.as_ref().and_then(encode) {
+        Some(end) => [&prefix[..], &end].concat(),
+        None => {
+            // Right after every key of the prefix, which ends with `:`.
+            let mut end = prefix;
+            *end.last_mut().expect("prefix is not empty") += 1;
+            end
+        }
+    };
+    let collection = format!("{}:", single_query.collection);
+    let mut data_keys = Vec::new();
+    for (key, data_key) in transaction.scan(start, end, u32::MAX).await? {
+        let data_key = String::from_utf8_lossy(&data_key).to_string();
+        match data_key.strip_prefix(&collection) {
+            Some(id) if !id.is_empty() && !id.contains(':') => data_keys.push(data_key),
+            _ => warn!("skipping invalid OPE index entry {:?}", key),
+        }
+    }
+    Ok(data_keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use liserk_shared::query::RangeBound;
+
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+
+    #[test]
+    fn test_encoding_preserves_order() {
+        let ciphertexts = ["0", "7", "255", "256", "1000000000000", "123456789012345"];
+        let encoded: Vec<Vec<u8>> = ciphertexts
+            .iter()
+            .map(|ciphertext| encode_ciphertext(ciphertext.as_bytes()).unwrap())
+            .collect();
+        assert!(encoded.iter().all(|encoded| encoded.len() == CIPHERTEXT_WIDTH));
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for ciphertext in ["", "-1", "+1", "1.5", "1e40", "1_000", " 1", "twelve"] {
+            assert!(matches!(
+                encode_ciphertext(ciphertext.as_bytes()),
+                Err(Error::InvalidCiphertext)
+            ));
+        }
+    }
+
+    fn bound(ciphertext: &str, inclusive: bool) -> Option<RangeBound> {
+        let ciphertext = ciphertext.as_bytes().to_vec();
+        if inclusive {
+            Some(RangeBound::inclusive(ciphertext))
+        } else {
+            Some(RangeBound::exclusive(ciphertext))
+        }
+    }
+
+    /// Ids of the records with a ciphertext within the bounds.
+    async fn range(
+        transaction: &mut dyn StorageTransaction,
+        lower_limit: Option<RangeBound>,
+        upper_limit: Option<RangeBound>,
+    ) -> String {
+        let query = SingleQuery {
+            collection: String::from("prices"),
+            usecase: String::from("price"),
+            lower_limit,
+            upper_limit,
+        };
+        let keys = scan(transaction, &query).await.unwrap();
+        keys.iter().map(|key| &key["prices:".len()..]).collect()
+    }
+
+    #[tokio::test]
+    async fn test_scan_bounds() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        for (id, ciphertext) in [("a", "3"), ("b", "10"), ("c", "10"), ("d", "1000")] {
+            let ciphertext = encode_ciphertext(ciphertext.as_bytes()).unwrap();
+            let key = index_key("prices", "price", &ciphertext, id);
+            transaction
+                .put(key, format!("prices:{}", id).into_bytes())
+                .await
+                .unwrap();
+        }
+        let other = index_key("prices", "other", &encode_ciphertext(b"5").unwrap(), "e");
+        transaction.put(other, b"prices:e".to_vec()).await.unwrap();
+
+        let transaction = transaction.as_mut();
+        assert_eq!(range(transaction, None, None).await, "abcd");
+        assert_eq!(range(transaction, bound("10", true), None).await, "bcd");
+        assert_eq!(range(transaction, bound("10", false), None).await, "d");
+        assert_eq!(range(transaction, bound("9", false), bound("10", true)).await, "bc");
+        assert_eq!(range(transaction, bound("0", true), bound("10", false)).await, "a");
+        assert_eq!(range(transaction, bound("10", false), bound("11", false)).await, "");
+        assert_eq!(range(transaction, None, bound("3", false)).await, "");
+        assert_eq!(range(transaction, None, bound(&"9".repeat(30), true)).await, "abcd");
+        assert_eq!(range(transaction, bound(&"9".repeat(200), true), None).await, "");
+        assert!(matches!(
+            scan(
+                transaction,
+                &SingleQuery {
+                    lower_limit: bound("-3", true),
+                    ..SingleQuery::new(String::from("prices"), String::from("price"))
+                }
+            )
+            .await,
+            Err(Error::InvalidCiphertext)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_scan_skips_keys_of_other_collections() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        let ciphertext = encode_ciphertext(b"3").unwrap();
+        let key = index_key("prices", "price", &ciphertext, "a");
+        transaction.put(key, b"prices:a".to_vec()).await.unwrap();
+        // Data key of a record of a collection `prices:price:ope`, which is refused
+        // since collections can't contain `:`.
+        let foreign = b"prices:price:ope:5d9e3a4c-1f55-4c36-9b7e-2a7d1c0e8f11";
+        transaction.put(foreign.to_vec(), b"value".to_vec()).await.unwrap();
+        let key = index_key("prices", "price", &ciphertext, "b");
+        transaction.put(key, b"prices:b:nonce".to_vec()).await.unwrap();
+
+        assert_eq!(range(transaction.as_mut(), None, None).await, "a");
+    }
+}
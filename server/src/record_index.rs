@@ -0,0 +1,194 @@
+//! Index entries of each record, so they can be removed with it.
+//!
+//! A record `collection:id` is listed in `collection:usecase:usecase` for each of its
+//! usecases, and records inserted with OPE or ORE also have a key per usecase in the
+//! range index of the usecase. Both are recorded at insertion under
+//! `collection:id:usecases`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{Key, StorageTransaction};
+use crate::Error;
+
+/// Usecases of a record and its keys in the range indexes.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordIndex {
+    pub usecases: Vec<String>,
+    /// Key of the record in the range index of a usecase, by usecase.
+    pub range_keys: Vec<(String, Key)>,
+}
+
+fn record_index_key(data_key: &str) -> String {
+    format!("{}:usecases", data_key)
+}
+
+pub fn usecase_key(collection: &str, usecase: &str) -> String {
+    format!("{}:{}:usecase", collection, usecase)
+}
+
+/// Reads the index entries of the record stored at `data_key`.
+///
+/// Returns an empty index for the records inserted before the entries were recorded.
+pub async fn load(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+) -> Result<RecordIndex, Error> {
+    match transaction.get(record_index_key(data_key).into_bytes()).await? {
+        Some(index) => Ok(serde_cbor::from_slice(&index)?),
+        None => Ok(RecordIndex::default()),
+    }
+}
+
+pub async fn save(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+    index: &RecordIndex,
+) -> Result<(), Error> {
+    let index = serde_cbor::to_vec(index)?;
+    transaction
+        .put(record_index_key(data_key).into_bytes(), index)
+        .await?;
+    Ok(())
+}
+
+/// Lists the record stored at `data_key` in the index of `usecase`.
+pub async fn add_usecase(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    usecase: &str,
+    data_key: &str,
+) -> Result<(), Error> {
+    let key = usecase_key(collection, usecase).into_bytes();
+    let mut values: Vec<Vec<u8>> = match transaction.get(key.clone()).await? {
+        Some(value) => serde_cbor::from_slice(&value)?,
+        None => Vec::new(),
+    };
+    values.push(data_key.as_bytes().to_vec());
+    transaction.put(key, serde_cbor::to_vec(&values)?).await?;
+    Ok(())
+}
+
+/// Removes the record stored at `data_key` from the index of `usecase`, and the
+/// index itself once it is empty.
+pub async fn remove_usecase(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    usecase: &str,
+    data_key: &str,
+) -> Result<(), Error> {
+    let key = usecase_key(collection, usecase).into_bytes();
+    let Some(value) = transaction.get(key.clone()).await? else {
+        return Ok(());
+    };
+    let mut values: Vec<Vec<u8>> = serde_cbor::from_slice(&value)?;
+    values.retain(|value| value != data_key.as_bytes());
+    if values.is_empty() {
+        transaction.delete(key).await?;
+    } else {
+        transaction.put(key, serde_cbor::to_vec(&values)?).await?;
+    }
+    Ok(())
+}
+
+/// Removes the record stored at `data_key` from the indexes of `usecase`.
+///
+/// Returns `false` if the record doesn't have this usecase.
+pub async fn remove_from_usecase(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    usecase: &str,
+    data_key: &str,
+) -> Result<bool, Error> {
+    let mut index = load(transaction, data_key).await?;
+    if !index.usecases.iter().any(|other| other == usecase) {
+        return Ok(false);
+    }
+    remove_usecase(transaction, collection, usecase, data_key).await?;
+    for (_, key) in index.range_keys.iter().filter(|(other, _)| other == usecase) {
+        transaction.delete(key.clone()).await?;
+    }
+    index.usecases.retain(|other| other != usecase);
+    index.range_keys.retain(|(other, _)| other != usecase);
+    save(transaction, data_key, &index).await?;
+    Ok(true)
+}
+
+/// Removes the record stored at `data_key` from all its indexes.
+pub async fn remove(
+    transaction: &mut dyn StorageTransaction,
+    collection: &str,
+    data_key: &str,
+) -> Result<(), Error> {
+    let index = load(transaction, data_key).await?;
+    for usecase in &index.usecases {
+        remove_usecase(transaction, collection, usecase, data_key).await?;
+    }
+    for (_, key) in index.range_keys {
+        transaction.delete(key).await?;
+    }
+    transaction.delete(record_index_key(data_key).into_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+
+    async fn usecase_records(
+        transaction: &mut dyn StorageTransaction,
+        usecase: &str,
+    ) -> Option<Vec<Vec<u8>>> {
+        let key = usecase_key("fruits", usecase).into_bytes();
+        let value = transaction.get(key).await.unwrap()?;
+        Some(serde_cbor::from_slice(&value).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_remove_entries() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        let transaction = transaction.as_mut();
+        for data_key in ["fruits:1", "fruits:2"] {
+            let mut index = RecordIndex::default();
+            for usecase in ["red", "price"] {
+                add_usecase(transaction, "fruits", usecase, data_key).await.unwrap();
+                index.usecases.push(usecase.to_string());
+            }
+            let range_key = format!("fruits:price:ope:{}", data_key).into_bytes();
+            transaction.put(range_key.clone(), vec![]).await.unwrap();
+            index.range_keys.push((String::from("price"), range_key));
+            save(transaction, data_key, &index).await.unwrap();
+        }
+
+        assert!(remove_from_usecase(transaction, "fruits", "price", "fruits:1")
+            .await
+            .unwrap());
+        assert!(!remove_from_usecase(transaction, "fruits", "price", "fruits:1")
+            .await
+            .unwrap());
+        assert_eq!(
+            usecase_records(transaction, "price").await,
+            Some(vec![b"fruits:2".to_vec()])
+        );
+        assert!(transaction
+            .get(b"fruits:price:ope:fruits:1".to_vec())
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(load(transaction, "fruits:1").await.unwrap().usecases, ["red"]);
+
+        remove(transaction, "fruits", "fruits:2").await.unwrap();
+        assert_eq!(usecase_records(transaction, "price").await, None);
+        assert_eq!(
+            usecase_records(transaction, "red").await,
+            Some(vec![b"fruits:1".to_vec()])
+        );
+        assert!(transaction
+            .get(b"fruits:price:ope:fruits:2".to_vec())
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(load(transaction, "fruits:2").await.unwrap(), RecordIndex::default());
+    }
+}
@@ -0,0 +1,399 @@
+use std::env;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use config::{Config, ConfigError, Environment, File};
+use liserk_shared::codec::DEFAULT_MAX_FRAME_SIZE;
+use serde::Deserialize;
+use tracing::Level;
+
+use crate::BINDED_URL_PORT;
+
+/// Prefix of the environment variables overriding the configuration file,
+/// e.g. `LISERK_STORAGE__BACKEND=memory`.
+pub const ENV_PREFIX: &str = "LISERK";
+
+/// Administrator password of the former example configuration, refused like an
+/// empty password.
+pub const EXAMPLE_ADMIN_PASSWORD: &str = "change me";
+
+/// Command line of `liserk-server`, every flag overrides the configuration file.
+#[derive(Debug, Default, Parser)]
+#[command(
+    name = "liserk-server",
+    version,
+    about = "Liserk zero knowledge database server"
+)]
+pub struct Cli {
+    /// Configuration file, defaults to `config/<RUN_MODE>.toml`.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Address the server listens on.
+    #[arg(long)]
+    pub listen_address: Option<String>,
+
+    /// Storage backend.
+    #[arg(long)]
+    pub storage: Option<StorageKind>,
+
+    /// PD endpoint of the TiKV cluster, can be repeated.
+    #[arg(long = "tikv-endpoint")]
+    pub tikv_endpoints: Vec<String>,
+
+    /// File of the embedded storage.
+    #[arg(long)]
+    pub storage_path: Option<PathBuf>,
+
+    /// Log level.
+    #[arg(long)]
+    pub log_level: Option<LogLevel>,
+
+    /// Log format.
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+
+    /// Maximum number of clients connected at the same time.
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+
+    /// Largest message accepted from a client, in bytes.
+    #[arg(long)]
+    pub max_frame_size: Option<usize>,
+
+    /// Seconds without any message before a connection is closed.
+    #[arg(long)]
+    pub idle_timeout: Option<u64>,
+
+    /// Certificate issued by the certificate authority.
+    #[arg(long)]
+    pub certificate_path: Option<PathBuf>,
+
+    /// Kyber secret key matching the certificate, encrypted by the certificate
+    /// authority.
+    #[arg(long)]
+    pub kyber_secret_key_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// TiKV cluster reached through its PD endpoints.
+    Tikv,
+    /// In-process storage, lost when the server stops.
+    Memory,
+    /// In-process storage kept in `storage.path`.
+    Embedded,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageKind,
+    pub tikv_endpoints: Vec<String>,
+    /// Seconds between two checks of the TiKV connection, 0 disables them.
+    pub health_check_interval: u64,
+    pub path: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageKind::Tikv,
+            tikv_endpoints: vec![String::from("127.0.0.1:2379")],
+            health_check_interval: 10,
+            path: PathBuf::from("data/liserk.db"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => Level::TRACE,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Error => Level::ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self { level: LogLevel::Info, format: LogFormat::Full }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_connections: usize,
+    /// Largest message accepted from a client, in bytes.
+    pub max_frame_size: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeoutsConfig {
+    /// Seconds without any message before a connection is closed, 0 disables it.
+    pub idle: u64,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self { idle: 300 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CertificatesConfig {
+    /// Certificate issued by the certificate authority.
+    pub certificate_path: PathBuf,
+    /// Kyber secret key matching the certificate, encrypted by the certificate
+    /// authority.
+    pub kyber_secret_key_path: PathBuf,
+    /// AES-256 key the Kyber secret key is encrypted with.
+    pub aes_key: Vec<u8>,
+}
+
+impl Default for CertificatesConfig {
+    fn default() -> Self {
+        Self {
+            certificate_path: PathBuf::from("certificates/certificate.crt"),
+            kyber_secret_key_path: PathBuf::from("certificates/encrypted.kyber"),
+            aes_key: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Administrator created at startup if it does not exist, none if empty.
+    pub admin_username: String,
+    /// Initial password of the administrator, ignored once it exists. The
+    /// administrator is not created with an empty password or
+    /// [`EXAMPLE_ADMIN_PASSWORD`].
+    pub admin_password: String,
+    /// Failed authentications after which a connection is closed.
+    pub max_attempts: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            admin_username: String::new(),
+            admin_password: String::new(),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Whole configuration of the server.
+///
+/// Values come, by increasing priority, from the defaults, the configuration file,
+/// the `LISERK_` environment variables and the command line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub listen_address: String,
+    pub storage: StorageConfig,
+    pub logging: LoggingConfig,
+    pub limits: LimitsConfig,
+    pub timeouts: TimeoutsConfig,
+    pub certificates: CertificatesConfig,
+    pub auth: AuthConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: BINDED_URL_PORT.to_string(),
+            storage: StorageConfig::default(),
+            logging: LoggingConfig::default(),
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            certificates: CertificatesConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the configuration for the given command line.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let file = match &cli.config {
+            Some(path) => File::from(path.as_path()).required(true),
+            None => {
+                let run_mode =
+                    env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
+                File::with_name(&format!("config/{}", run_mode)).required(false)
+            }
+        };
+        let environment = Environment::with_prefix(ENV_PREFIX)
+            .separator("__")
+            .list_separator(",")
+            .with_list_parse_key("storage.tikv_endpoints")
+            .with_list_parse_key("certificates.aes_key")
+            .try_parsing(true);
+
+        let endpoints =
+            (!cli.tikv_endpoints.is_empty()).then(|| cli.tikv_endpoints.clone());
+        let settings = Config::builder()
+            .add_source(file)
+            .add_source(environment)
+            .set_override_option("listen_address", cli.listen_address.clone())?
+            .set_override_option("storage.backend", cli.storage.map(kind_name))?
+            .set_override_option("storage.tikv_endpoints", endpoints)?
+            .set_override_option(
+                "storage.path",
+                cli.storage_path.as_ref().map(|path| path.display().to_string()),
+            )?
+            .set_override_option("logging.level", cli.log_level.map(level_name))?
+            .set_override_option("logging.format", cli.log_format.map(format_name))?
+            .set_override_option(
+                "limits.max_connections",
+                cli.max_connections.map(|max| max as u64),
+            )?
+            .set_override_option(
+                "limits.max_frame_size",
+                cli.max_frame_size.map(|max| max as u64),
+            )?
+            .set_override_option("timeouts.idle", cli.idle_timeout)?
+            .set_override_option(
+                "certificates.certificate_path",
+                cli.certificate_path.as_ref().map(|path| path.display().to_string()),
+            )?
+            .set_override_option(
+                "certificates.kyber_secret_key_path",
+                cli.kyber_secret_key_path
+                    .as_ref()
+                    .map(|path| path.display().to_string()),
+            )?
+            .build()?;
+        settings.try_deserialize()
+    }
+}
+
+fn kind_name(kind: StorageKind) -> &'static str {
+    match kind {
+        StorageKind::Tikv => "tikv",
+        StorageKind::Memory => "memory",
+        StorageKind::Embedded => "embedded",
+    }
+}
+
+fn level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Trace => "trace",
+        LogLevel::Debug => "debug",
+        LogLevel::Info => "info",
+        LogLevel::Warn => "warn",
+        LogLevel::Error => "error",
+    }
+}
+
+fn format_name(format: LogFormat) -> &'static str {
+    match format {
+        LogFormat::Full => "full",
+        LogFormat::Compact => "compact",
+        LogFormat::Pretty => "pretty",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_without_file() {
+        let config = ServerConfig::load(&Cli::default()).unwrap();
+        assert_eq!(config.listen_address, BINDED_URL_PORT);
+        assert_eq!(config.storage.tikv_endpoints, vec!["127.0.0.1:2379".to_string()]);
+        assert_eq!(config.logging.level, LogLevel::Info);
+        assert_eq!(config.logging.format, LogFormat::Full);
+    }
+
+    #[test]
+    fn test_invalid_log_level_is_refused() {
+        let path = env::temp_dir().join(format!("liserk-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "[logging]\nlevel = \"warnn\"\n").unwrap();
+        let cli = Cli { config: Some(path.clone()), ..Cli::default() };
+        let result = ServerConfig::load(&cli);
+        std::fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+
+        let cli = Cli { log_level: Some(LogLevel::Debug), ..Cli::default() };
+        let config = ServerConfig::load(&cli).unwrap();
+        assert_eq!(config.logging.level, LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_file_then_cli_override() {
+        let path = env::temp_dir().join(format!("liserk-{}.toml", uuid::Uuid::new_v4()));
+        let content = "listen_address = \"0.0.0.0:6000\"\n\
+                       [storage]\nbackend = \"embedded\"\npath = \"/tmp/liserk.db\"\n\
+                       [limits]\nmax_connections = 8\n";
+        std::fs::write(&path, content).unwrap();
+
+        let cli = Cli {
+            config: Some(path.clone()),
+            storage: Some(StorageKind::Memory),
+            idle_timeout: Some(0),
+            certificate_path: Some(PathBuf::from("/etc/liserk/server.crt")),
+            ..Cli::default()
+        };
+        let config = ServerConfig::load(&cli).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(config.listen_address, "0.0.0.0:6000");
+        assert_eq!(config.storage.backend, StorageKind::Memory);
+        assert_eq!(config.storage.path, PathBuf::from("/tmp/liserk.db"));
+        assert_eq!(config.limits.max_connections, 8);
+        assert_eq!(config.timeouts.idle, 0);
+        assert_eq!(
+            config.certificates.certificate_path,
+            PathBuf::from("/etc/liserk/server.crt")
+        );
+        assert_eq!(
+            config.certificates.kyber_secret_key_path,
+            PathBuf::from("certificates/encrypted.kyber")
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write as _};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{Key, KvPair, StorageBackend, StorageError, StorageTransaction, Value};
+
+/// In-process backend, kept in memory and optionally persisted in an append-only file.
+///
+/// The file is compacted when it is opened: if it holds overwritten or deleted values,
+/// it is rewritten with only the live ones.
+///
+/// Transactions read a snapshot of the values committed when they started and buffer
+/// their writes. On commit, a transaction fails with [`StorageError::Conflict`] if
+/// another one committed a write on a key it wrote or read with `get_for_update` since
+/// it started, even if it wrote nothing itself.
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddedBackend {
+    store: Arc<Mutex<Store>>,
+}
+
+/// A committed value, `None` marks a deleted key.
+#[derive(Debug, Clone)]
+struct Cell {
+    value: Option<Value>,
+    version: u64,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    /// Committed values of each key, oldest first. Older values are kept while a
+    /// running transaction may still read them.
+    cells: BTreeMap<Key, Vec<Cell>>,
+    version: u64,
+    /// Number of running transactions by start version.
+    snapshots: BTreeMap<u64, usize>,
+    /// Keys with older values, pruned once no snapshot can read them.
+    stale: BTreeSet<Key>,
+    log: Option<File>,
+}
+
+/// The writes of one committed transaction, as stored in the storage file.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogBatch {
+    writes: Vec<(Key, Option<Value>)>,
+}
+
+impl Store {
+    fn apply(&mut self, writes: Vec<(Key, Option<Value>)>) {
+        self.version += 1;
+        for (key, value) in writes {
+            let cell = Cell { value, version: self.version };
+            self.cells.entry(key.clone()).or_default().push(cell);
+            self.stale.insert(key);
+        }
+        self.prune();
+    }
+
+    /// Drops the values no running transaction can read anymore.
+    fn prune(&mut self) {
+        let oldest = self.snapshots.keys().next().copied().unwrap_or(self.version);
+        let cells = &mut self.cells;
+        self.stale.retain(|key| {
+            let Some(cells) = cells.get_mut(key) else { return false };
+            // The last value committed before the oldest snapshot is still visible.
+            let visible = cells.iter().rposition(|cell| cell.version <= oldest);
+            cells.drain(..visible.unwrap_or(0));
+            cells.len() > 1
+        });
+    }
+
+    /// Last value committed at `version` or before.
+    fn read(&self, key: &Key, version: u64) -> Option<Value> {
+        visible(self.cells.get(key)?, version)
+    }
+
+    /// Version of the last write on `key`.
+    fn last_version(&self, key: &Key) -> Option<u64> {
+        self.cells.get(key)?.last().map(|cell| cell.version)
+    }
+
+    fn release(&mut self, version: u64) {
+        if let Some(count) = self.snapshots.get_mut(&version) {
+            *count -= 1;
+            if *count == 0 {
+                self.snapshots.remove(&version);
+                self.prune();
+            }
+        }
+    }
+}
+
+fn visible(cells: &[Cell], version: u64) -> Option<Value> {
+    let cell = cells.iter().rev().find(|cell| cell.version <= version)?;
+    cell.value.clone()
+}
+
+impl EmbeddedBackend {
+    /// Creates an empty backend whose data is lost when it is dropped.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Opens the storage file at `path`, creating it if needed, replays it and compacts
+    /// it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let path = path.as_ref();
+        if let Some(directory) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(directory)?;
+        }
+        let mut store = Store::default();
+        let mut log =
+            OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let mut replayed = 0;
+
+        let reader = BufReader::new(File::open(path)?);
+        let mut batches =
+            serde_cbor::Deserializer::from_reader(reader).into_iter::<LogBatch>();
+        loop {
+            let valid_length = batches.byte_offset() as u64;
+            match batches.next() {
+                Some(Ok(batch)) => {
+                    replayed += batch.writes.len();
+                    store.apply(batch.writes);
+                }
+                Some(Err(err)) if err.is_eof() => {
+                    warn!("truncating incomplete batch at the end of {:?}", path);
+                    log.set_len(valid_length)?;
+                    break;
+                }
+                Some(Err(err)) => return Err(err.into()),
+                None => break,
+            }
+        }
+        store.cells.retain(|_, cells| {
+            cells.drain(..cells.len() - 1);
+            cells[0].value.is_some()
+        });
+        if replayed > store.cells.len() {
+            drop(log);
+            log = compact(path, &store)?;
+        }
+        info!("embedded storage opened {:?}, {} keys", path, store.cells.len());
+
+        store.log = Some(log);
+        Ok(Self { store: Arc::new(Mutex::new(store)) })
+    }
+}
+
+/// Rewrites the storage file at `path` with the live values of `store` only, so the
+/// overwritten and deleted ones are not replayed anymore, and opens it for appending.
+fn compact(path: &Path, store: &Store) -> Result<File, StorageError> {
+    let mut compacted = path.as_os_str().to_owned();
+    compacted.push(".compact");
+    let writes = store
+        .cells
+        .iter()
+        .map(|(key, cells)| (key.clone(), cells[0].value.clone()))
+        .collect();
+    let mut file = File::create(&compacted)?;
+    file.write_all(&serde_cbor::to_vec(&LogBatch { writes })?)?;
+    file.sync_all()?;
+    std::fs::rename(&compacted, path)?;
+    info!("embedded storage compacted {:?}", path);
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+fn lock(store: &Mutex<Store>) -> MutexGuard<'_, Store> {
+    store.lock().expect("embedded storage mutex poisoned")
+}
+
+#[async_trait]
+impl StorageBackend for EmbeddedBackend {
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, StorageError> {
+        let mut store = lock(&self.store);
+        let start_version = store.version;
+        *store.snapshots.entry(start_version).or_default() += 1;
+        drop(store);
+        Ok(Box::new(EmbeddedTransaction {
+            store: self.store.clone(),
+            start_version,
+            writes: BTreeMap::new(),
+            locked: HashSet::new(),
+            closed: false,
+        }))
+    }
+}
+
+#[derive(Debug)]
+enum Write {
+    Put(Value),
+    Insert(Value),
+    Delete,
+}
+
+struct EmbeddedTransaction {
+    store: Arc<Mutex<Store>>,
+    start_version: u64,
+    writes: BTreeMap<Key, Write>,
+    locked: HashSet<Key>,
+    closed: bool,
+}
+
+impl EmbeddedTransaction {
+    fn ensure_open(&self) -> Result<(), StorageError> {
+        if self.closed {
+            return Err(StorageError::TransactionClosed);
+        }
+        Ok(())
+    }
+
+    /// Ends the transaction, its snapshot no longer needs to be kept.
+    fn close(&mut self, store: &mut Store) {
+        if !self.closed {
+            self.closed = true;
+            store.release(self.start_version);
+        }
+    }
+
+    fn read(&self, key: &Key) -> Option<Value> {
+        match self.writes.get(key) {
+            Some(Write::Put(value)) | Some(Write::Insert(value)) => Some(value.clone()),
+            Some(Write::Delete) => None,
+            None => lock(&self.store).read(key, self.start_version),
+        }
+    }
+}
+
+impl Drop for EmbeddedTransaction {
+    fn drop(&mut self) {
+        let store = self.store.clone();
+        self.close(&mut lock(&store));
+    }
+}
+
+#[async_trait]
+impl StorageTransaction for EmbeddedTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>, StorageError> {
+        self.ensure_open()?;
+        Ok(self.read(&key))
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>, StorageError> {
+        self.ensure_open()?;
+        let value = self.read(&key);
+        self.locked.insert(key);
+        Ok(value)
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>, StorageError> {
+        self.ensure_open()?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| self.read(&key).map(|value| (key, value)))
+            .collect())
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<(), StorageError> {
+        self.ensure_open()?;
+        self.writes.insert(key, Write::Put(value));
+        Ok(())
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<(), StorageError> {
+        self.ensure_open()?;
+        self.writes.insert(key, Write::Insert(value));
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<(), StorageError> {
+        self.ensure_open()?;
+        self.writes.insert(key, Write::Delete);
+        Ok(())
+    }
+
+    async fn scan(
+        &mut self,
+        start: Key,
+        end: Key,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, StorageError> {
+        self.ensure_open()?;
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let mut pairs: BTreeMap<Key, Value> = lock(&self.store)
+            .cells
+            .range(start.clone()..end.clone())
+            .filter_map(|(key, cells)| {
+                visible(cells, self.start_version).map(|value| (key.clone(), value))
+            })
+            .collect();
+        for (key, write) in self.writes.range(start..end) {
+            match write {
+                Write::Put(value) | Write::Insert(value) => {
+                    pairs.insert(key.clone(), value.clone());
+                }
+                Write::Delete => {
+                    pairs.remove(key);
+                }
+            }
+        }
+        Ok(pairs.into_iter().take(limit as usize).collect())
+    }
+
+    async fn commit(&mut self) -> Result<(), StorageError> {
+        self.ensure_open()?;
+        let store = self.store.clone();
+        let mut store = lock(&store);
+        self.close(&mut store);
+
+        for key in self.writes.keys().chain(self.locked.iter()) {
+            let Some(version) = store.last_version(key) else { continue };
+            if version > self.start_version {
+                return Err(StorageError::Conflict(key.clone()));
+            }
+        }
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        for (key, write) in self.writes.iter() {
+            let exists = store.read(key, store.version).is_some();
+            if matches!(write, Write::Insert(_)) && exists {
+                return Err(StorageError::AlreadyExists(key.clone()));
+            }
+        }
+
+        let writes: Vec<(Key, Option<Value>)> = std::mem::take(&mut self.writes)
+            .into_iter()
+            .map(|(key, write)| match write {
+                Write::Put(value) | Write::Insert(value) => (key, Some(value)),
+                Write::Delete => (key, None),
+            })
+            .collect();
+        if let Some(log) = store.log.as_mut() {
+            let batch = LogBatch { writes };
+            log.write_all(&serde_cbor::to_vec(&batch)?)?;
+            log.sync_data()?;
+            store.apply(batch.writes);
+        } else {
+            store.apply(writes);
+        }
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), StorageError> {
+        self.ensure_open()?;
+        let store = self.store.clone();
+        self.close(&mut lock(&store));
+        self.writes.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> Key {
+        key.as_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_put_then_get_in_other_transaction() {
+        let backend = EmbeddedBackend::in_memory();
+        let mut transaction = backend.begin().await.unwrap();
+        transaction.put(key("users:1"), vec![1, 2]).await.unwrap();
+        assert_eq!(transaction.get(key("users:1")).await.unwrap(), Some(vec![1, 2]));
+        transaction.commit().await.unwrap();
+
+        let mut transaction = backend.begin().await.unwrap();
+        assert_eq!(transaction.get(key("users:1")).await.unwrap(), Some(vec![1, 2]));
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_discards_writes() {
+        let backend = EmbeddedBackend::in_memory();
+        let mut transaction = backend.begin().await.unwrap();
+        transaction.put(key("users:1"), vec![1]).await.unwrap();
+        transaction.rollback().await.unwrap();
+
+        let mut transaction = backend.begin().await.unwrap();
+        assert_eq!(transaction.get(key("users:1")).await.unwrap(), None);
+        assert!(transaction.rollback().await.is_ok());
+        assert!(transaction.commit().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_insert_existing_key_fails() {
+        let backend = EmbeddedBackend::in_memory();
+        let mut transaction = backend.begin().await.unwrap();
+        transaction.insert(key("users:1"), vec![1]).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let mut transaction = backend.begin().await.unwrap();
+        transaction.insert(key("users:1"), vec![2]).await.unwrap();
+        let result = transaction.commit().await;
+        assert!(matches!(result, Err(StorageError::AlreadyExists(_))));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_conflict() {
+        let backend = EmbeddedBackend::in_memory();
+        let mut first = backend.begin().await.unwrap();
+        let mut second = backend.begin().await.unwrap();
+        first.get_for_update(key("users:1")).await.unwrap();
+        second.put(key("users:1"), vec![2]).await.unwrap();
+        second.commit().await.unwrap();
+
+        first.put(key("users:2"), vec![1]).await.unwrap();
+        let result = first.commit().await;
+        assert!(matches!(result, Err(StorageError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_transaction_conflicts() {
+        let backend = EmbeddedBackend::in_memory();
+        let mut transaction = backend.begin().await.unwrap();
+        transaction.put(key("users:1"), vec![1]).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let mut first = backend.begin().await.unwrap();
+        let mut second = backend.begin().await.unwrap();
+        assert_eq!(first.get_for_update(key("users:1")).await.unwrap(), Some(vec![1]));
+        second.put(key("users:1"), vec![2]).await.unwrap();
+        second.put(key("users:2"), vec![2]).await.unwrap();
+        second.commit().await.unwrap();
+
+        // The first transaction still reads the values of its snapshot.
+        assert_eq!(first.get(key("users:1")).await.unwrap(), Some(vec![1]));
+        assert_eq!(first.get(key("users:2")).await.unwrap(), None);
+        let pairs = first.scan(key("users:"), key("users;"), 10).await.unwrap();
+        assert_eq!(pairs, vec![(key("users:1"), vec![1])]);
+        let result = first.commit().await;
+        assert!(matches!(result, Err(StorageError::Conflict(_))));
+
+        let mut transaction = backend.begin().await.unwrap();
+        assert_eq!(transaction.get(key("users:1")).await.unwrap(), Some(vec![2]));
+        transaction.commit().await.unwrap();
+        let store = lock(&backend.store);
+        assert!(store.snapshots.is_empty() && store.stale.is_empty());
+        assert!(store.cells.values().all(|cells| cells.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn test_scan_merges_pending_writes() {
+        let backend = EmbeddedBackend::in_memory();
+        let mut transaction = backend.begin().await.unwrap();
+        for id in ["a", "b", "c"] {
+            transaction.put(key(&format!("users:{}", id)), vec![0]).await.unwrap();
+        }
+        transaction.put(key("posts:a"), vec![0]).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let mut transaction = backend.begin().await.unwrap();
+        transaction.delete(key("users:b")).await.unwrap();
+        transaction.put(key("users:d"), vec![1]).await.unwrap();
+        let pairs = transaction.scan(key("users:"), key("users;"), 10).await.unwrap();
+        let keys: Vec<Key> = pairs.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![key("users:a"), key("users:c"), key("users:d")]);
+
+        let pairs = transaction.scan(key("users:"), key("users;"), 1).await.unwrap();
+        assert_eq!(pairs.len(), 1);
+        transaction.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reopen_file_keeps_data() {
+        let path =
+            std::env::temp_dir().join(format!("liserk-{}.db", uuid::Uuid::new_v4()));
+        {
+            let backend = EmbeddedBackend::open(&path).unwrap();
+            let mut transaction = backend.begin().await.unwrap();
+            transaction.put(key("users:1"), vec![1]).await.unwrap();
+            transaction.put(key("users:2"), vec![2]).await.unwrap();
+            transaction.commit().await.unwrap();
+            let mut transaction = backend.begin().await.unwrap();
+            transaction.delete(key("users:1")).await.unwrap();
+            transaction.put(key("users:2"), vec![3]).await.unwrap();
+            transaction.commit().await.unwrap();
+        }
+        let length = std::fs::metadata(&path).unwrap().len();
+
+        for expected in [vec![3], vec![4]] {
+            let backend = EmbeddedBackend::open(&path).unwrap();
+            // The deleted key and the overwritten value were compacted away.
+            assert!(std::fs::metadata(&path).unwrap().len() < length);
+            let mut transaction = backend.begin().await.unwrap();
+            assert_eq!(transaction.get(key("users:1")).await.unwrap(), None);
+            assert_eq!(transaction.get(key("users:2")).await.unwrap(), Some(expected));
+            transaction.put(key("users:2"), vec![4]).await.unwrap();
+            transaction.commit().await.unwrap();
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+//! Storage layer of the server.
+//!
+//! The mutation and query code only talks to a [`StorageBackend`], which hands out
+//! [`StorageTransaction`]s. Two backends exist: [`TikvBackend`] for a real TiKV/PD
+//! cluster and [`EmbeddedBackend`], an in-process engine kept in memory and optionally
+//! persisted to a file, used for development and tests.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::settings::{StorageConfig, StorageKind};
+
+mod embedded;
+mod tikv;
+
+pub use embedded::EmbeddedBackend;
+pub use tikv::TikvBackend;
+
+/// Raw key of the key-value store.
+pub type Key = Vec<u8>;
+
+/// Raw value of the key-value store.
+pub type Value = Vec<u8>;
+
+/// A key and its value, as returned by `batch_get` and `scan`.
+pub type KvPair = (Key, Value);
+
+/// Errors raised by a storage backend.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    /// Any other failure of TiKV, its write conflicts are reported as
+    /// [`StorageError::Conflict`] and [`StorageError::AlreadyExists`].
+    #[error("tikv error: {0}")]
+    Tikv(#[source] tikv_client::Error),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("corrupted storage file: {0}")]
+    Corrupted(#[from] serde_cbor::Error),
+
+    /// Another transaction committed a write on the same key first.
+    #[error("write conflict on key {0:?}")]
+    Conflict(Key),
+
+    /// `insert` was used on a key that already exists.
+    #[error("key already exists {0:?}")]
+    AlreadyExists(Key),
+
+    /// The transaction was already committed or rolled back.
+    #[error("transaction is closed")]
+    TransactionClosed,
+}
+
+/// Opens the backend selected in the configuration.
+pub async fn open(
+    config: &StorageConfig,
+) -> Result<Arc<dyn StorageBackend>, StorageError> {
+    let backend: Arc<dyn StorageBackend> = match config.backend {
+        StorageKind::Tikv => {
+            let backend = TikvBackend::connect(config.tikv_endpoints.clone()).await?;
+            if config.health_check_interval > 0 {
+                backend.spawn_health_check(Duration::from_secs(
+                    config.health_check_interval,
+                ));
+            }
+            Arc::new(backend)
+        }
+        StorageKind::Memory => Arc::new(EmbeddedBackend::in_memory()),
+        StorageKind::Embedded => Arc::new(EmbeddedBackend::open(&config.path)?),
+    };
+    Ok(backend)
+}
+
+/// A backend able to start transactions on a key-value store.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Starts a new optimistic transaction.
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, StorageError>;
+}
+
+/// An optimistic transaction on a [`StorageBackend`].
+///
+/// Writes are buffered until [`StorageTransaction::commit`]. A transaction must end
+/// with either `commit` or `rollback`.
+#[async_trait]
+pub trait StorageTransaction: Send {
+    /// Reads the value of a key.
+    async fn get(&mut self, key: Key) -> Result<Option<Value>, StorageError>;
+
+    /// Reads the value of a key and makes the commit fail if another transaction
+    /// writes it in the meantime.
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>, StorageError>;
+
+    /// Reads several keys at once, missing keys are not part of the result.
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>, StorageError>;
+
+    /// Writes a value, overwriting any previous one.
+    async fn put(&mut self, key: Key, value: Value) -> Result<(), StorageError>;
+
+    /// Writes a value, the commit fails if the key already exists.
+    async fn insert(&mut self, key: Key, value: Value) -> Result<(), StorageError>;
+
+    /// Removes a key.
+    async fn delete(&mut self, key: Key) -> Result<(), StorageError>;
+
+    /// Returns at most `limit` pairs with `start <= key < end`, ordered by key.
+    async fn scan(
+        &mut self,
+        start: Key,
+        end: Key,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, StorageError>;
+
+    /// Applies every buffered write atomically.
+    async fn commit(&mut self) -> Result<(), StorageError>;
+
+    /// Drops every buffered write.
+    async fn rollback(&mut self) -> Result<(), StorageError>;
+}
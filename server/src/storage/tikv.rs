@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tikv_client::{Transaction, TransactionClient};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use super::{Key, KvPair, StorageBackend, StorageError, StorageTransaction, Value};
+
+impl From<tikv_client::Error> for StorageError {
+    fn from(err: tikv_client::Error) -> Self {
+        tikv_conflict(&err).unwrap_or(StorageError::Tikv(err))
+    }
+}
+
+/// Conflict reported by TiKV in `err`, such as the write conflict of an optimistic
+/// transaction failing to commit.
+fn tikv_conflict(err: &tikv_client::Error) -> Option<StorageError> {
+    match err {
+        tikv_client::Error::KeyError(key_error) => {
+            if let Some(conflict) = &key_error.conflict {
+                return Some(StorageError::Conflict(conflict.key.clone()));
+            }
+            let already_exist = key_error.already_exist.as_ref()?;
+            Some(StorageError::AlreadyExists(already_exist.key.clone()))
+        }
+        tikv_client::Error::MultipleKeyErrors(errors) => {
+            errors.iter().find_map(tikv_conflict)
+        }
+        _ => None,
+    }
+}
+
+/// Backend storing data in a TiKV cluster reached through its PD endpoints.
+///
+/// A single long-lived [`TransactionClient`] is shared by every connection, it already
+/// multiplexes its requests over the connections it keeps to PD and TiKV. When the
+/// client fails, it is replaced by a fresh one.
+#[derive(Clone)]
+pub struct TikvBackend {
+    pd_endpoints: Vec<String>,
+    client: Arc<RwLock<Arc<TransactionClient>>>,
+}
+
+impl TikvBackend {
+    /// Connects to the cluster.
+    pub async fn connect(pd_endpoints: Vec<String>) -> Result<Self, StorageError> {
+        let client = TransactionClient::new(pd_endpoints.clone()).await?;
+        info!("connected to tikv {:?}", pd_endpoints);
+        Ok(Self {
+            pd_endpoints,
+            client: Arc::new(RwLock::new(Arc::new(client))),
+        })
+    }
+
+    async fn client(&self) -> Arc<TransactionClient> {
+        self.client.read().await.clone()
+    }
+
+    /// Replaces `failed` by a new client, unless another task already did it.
+    async fn reconnect(
+        &self,
+        failed: &Arc<TransactionClient>,
+    ) -> Result<Arc<TransactionClient>, StorageError> {
+        let mut client = self.client.write().await;
+        if !Arc::ptr_eq(&client, failed) {
+            return Ok(client.clone());
+        }
+        warn!("reconnecting to tikv {:?}", self.pd_endpoints);
+        let fresh = Arc::new(TransactionClient::new(self.pd_endpoints.clone()).await?);
+        *client = fresh.clone();
+        Ok(fresh)
+    }
+
+    /// Asks PD for a timestamp and reconnects if it fails.
+    pub async fn check_health(&self) -> Result<(), StorageError> {
+        let client = self.client().await;
+        if let Err(err) = client.current_timestamp().await {
+            warn!("tikv health check failed: {}", err);
+            self.reconnect(&client).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs [`TikvBackend::check_health`] every `interval` in a background task.
+    pub fn spawn_health_check(&self, interval: Duration) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = backend.check_health().await {
+                    error!("tikv is unreachable: {}", err);
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for TikvBackend {
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, StorageError> {
+        let client = self.client().await;
+        let transaction = match client.begin_optimistic().await {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                warn!("failed to begin tikv transaction: {}", err);
+                self.reconnect(&client).await?.begin_optimistic().await?
+            }
+        };
+        Ok(Box::new(TikvTransaction { transaction }))
+    }
+}
+
+struct TikvTransaction {
+    transaction: Transaction,
+}
+
+#[async_trait]
+impl StorageTransaction for TikvTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>, StorageError> {
+        Ok(self.transaction.get(key).await?)
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>, StorageError> {
+        Ok(self.transaction.get_for_update(key).await?)
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>, StorageError> {
+        let pairs = self.transaction.batch_get(keys).await?;
+        Ok(pairs.map(|pair| (pair.0.into(), pair.1)).collect())
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<(), StorageError> {
+        Ok(self.transaction.put(key, value).await?)
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<(), StorageError> {
+        match self.transaction.insert(key.clone(), value).await {
+            Err(tikv_client::Error::DuplicateKeyInsertion) => {
+                Err(StorageError::AlreadyExists(key))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<(), StorageError> {
+        Ok(self.transaction.delete(key).await?)
+    }
+
+    async fn scan(
+        &mut self,
+        start: Key,
+        end: Key,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, StorageError> {
+        let pairs = self.transaction.scan(start..end, limit).await?;
+        Ok(pairs.map(|pair| (pair.0.into(), pair.1)).collect())
+    }
+
+    async fn commit(&mut self) -> Result<(), StorageError> {
+        self.transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), StorageError> {
+        Ok(self.transaction.rollback().await?)
+    }
+}
@@ -0,0 +1,36 @@
+//! Users and sessions shared by the tests of the server modules.
+
+use liserk_shared::message::NewUser;
+
+use crate::auth::{self, ClientSession, User};
+use crate::storage::EmbeddedBackend;
+
+/// Password of the users created by [`create_user`].
+pub const PASSWORD: &str = "secret";
+
+pub async fn create_user(
+    storage: &EmbeddedBackend,
+    username: &str,
+    admin: bool,
+    groups: &[&str],
+) -> User {
+    let new_user = NewUser {
+        username: username.into(),
+        password: String::from(PASSWORD),
+        admin,
+        groups: groups.iter().map(|group| group.to_string()).collect(),
+    };
+    auth::create_user(storage, new_user).await.unwrap()
+}
+
+/// A connection authenticated as the new administrator `root`, or the new user
+/// `alice`.
+pub async fn authenticated_session(
+    storage: &EmbeddedBackend,
+    admin: bool,
+) -> ClientSession {
+    let username = if admin { "root" } else { "alice" };
+    let mut session = ClientSession::new(3);
+    session.authenticate(create_user(storage, username, admin, &[]).await);
+    session
+}
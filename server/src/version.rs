@@ -0,0 +1,139 @@
+//! Versions of the records and history of their previous values.
+//!
+//! The version of the record `collection:id` is kept under `collection:id:version`.
+//! Records are inserted at [`FIRST_VERSION`] and each update increments it, the
+//! records inserted before versions were recorded are at version 0. When the history
+//! of the collection is enabled, an update keeps the encrypted value it replaces under
+//! `collection:id:history:<version>`, up to the number of versions of the collection.
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{Key, StorageTransaction};
+use crate::Error;
+
+pub const FIRST_VERSION: u64 = 1;
+
+/// Encrypted value of a previous version of a record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub value: Vec<u8>,
+    pub nonce: Option<Vec<u8>>,
+}
+
+fn version_key(data_key: &str) -> String {
+    format!("{}:version", data_key)
+}
+
+fn history_prefix(data_key: &str) -> String {
+    format!("{}:history:", data_key)
+}
+
+/// Versions are padded so the history is scanned in order.
+fn history_key(data_key: &str, version: u64) -> Key {
+    format!("{}{:020}", history_prefix(data_key), version).into_bytes()
+}
+
+async fn scan_history(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+) -> Result<Vec<Key>, Error> {
+    let start = history_prefix(data_key).into_bytes();
+    let mut end = start.clone();
+    // Right after every key of the prefix, which ends with `:`.
+    *end.last_mut().expect("prefix is not empty") += 1;
+    let pairs = transaction.scan(start, end, u32::MAX).await?;
+    Ok(pairs.into_iter().map(|(key, _)| key).collect())
+}
+
+/// Version of the record stored at `data_key`.
+pub async fn load(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+) -> Result<u64, Error> {
+    match transaction.get(version_key(data_key).into_bytes()).await? {
+        Some(version) => Ok(serde_cbor::from_slice(&version)?),
+        None => Ok(0),
+    }
+}
+
+pub async fn save(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+    version: u64,
+) -> Result<(), Error> {
+    let version = serde_cbor::to_vec(&version)?;
+    transaction.put(version_key(data_key).into_bytes(), version).await?;
+    Ok(())
+}
+
+/// Keeps `entry` as the value of the record at `version`, and removes the oldest
+/// entries so at most `versions` are kept.
+pub async fn archive(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+    version: u64,
+    entry: &HistoryEntry,
+    versions: u32,
+) -> Result<(), Error> {
+    let entry = serde_cbor::to_vec(entry)?;
+    transaction.put(history_key(data_key, version), entry).await?;
+    let keys = scan_history(transaction, data_key).await?;
+    let outdated = keys.len().saturating_sub(versions as usize);
+    for key in keys.into_iter().take(outdated) {
+        transaction.delete(key).await?;
+    }
+    Ok(())
+}
+
+/// Previous value of the record stored at `data_key`, if it is still kept.
+pub async fn get(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+    version: u64,
+) -> Result<Option<HistoryEntry>, Error> {
+    match transaction.get(history_key(data_key, version)).await? {
+        Some(entry) => Ok(Some(serde_cbor::from_slice(&entry)?)),
+        None => Ok(None),
+    }
+}
+
+/// Removes the version and the history of the record stored at `data_key`.
+pub async fn remove(
+    transaction: &mut dyn StorageTransaction,
+    data_key: &str,
+) -> Result<(), Error> {
+    for key in scan_history(transaction, data_key).await? {
+        transaction.delete(key).await?;
+    }
+    transaction.delete(version_key(data_key).into_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EmbeddedBackend, StorageBackend};
+
+    #[tokio::test]
+    async fn test_history_keeps_last_versions() {
+        let storage = EmbeddedBackend::in_memory();
+        let mut transaction = storage.begin().await.unwrap();
+        let transaction = transaction.as_mut();
+        assert_eq!(load(transaction, "fruits:1").await.unwrap(), 0);
+        for version in FIRST_VERSION..=10 {
+            let entry = HistoryEntry { value: vec![version as u8], nonce: None };
+            archive(transaction, "fruits:1", version, &entry, 3).await.unwrap();
+            save(transaction, "fruits:1", version + 1).await.unwrap();
+        }
+
+        assert_eq!(load(transaction, "fruits:1").await.unwrap(), 11);
+        assert_eq!(get(transaction, "fruits:1", 7).await.unwrap(), None);
+        for version in 8..=10 {
+            let entry = get(transaction, "fruits:1", version).await.unwrap().unwrap();
+            assert_eq!(entry.value, [version as u8]);
+        }
+        remove(transaction, "fruits:1").await.unwrap();
+        assert_eq!(load(transaction, "fruits:1").await.unwrap(), 0);
+        assert!(scan_history(transaction, "fruits:1").await.unwrap().is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use futures::future::join_all;
-use liserk_shared::message_type::MessageType;
+use liserk_shared::message_type::{MessageType, PROTOCOL_VERSION};
 use tokio::io::{self, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tracing::{debug, info, Level};
@@ -29,8 +29,8 @@ async fn connect_client() -> io::Result<()> {
     let request = message.as_bytes();
     let request_length = request.len() as u32;
     let request_length = request_length.to_be_bytes();
-    let message_type_as_bytes = [message_type];
-    let full_request = &[&message_type_as_bytes[..], &request_length, request].concat();
+    let header = [PROTOCOL_VERSION, message_type];
+    let full_request = &[&header[..], &request_length, request].concat();
     info!("request: {:?}", full_request);
     let x = stream.write(full_request).await?;
     debug!("trace: Result {}", x);
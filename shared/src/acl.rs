@@ -0,0 +1,82 @@
+//! Access control lists attached to every record.
+//!
+//! The ACL of a record is sent with its insertion and stored next to it. The server
+//! checks it against the authenticated user before reading, updating or deleting the
+//! record.
+
+use serde::{Deserialize, Serialize};
+
+/// What a principal is allowed to do with a record, each level includes the lower
+/// ones.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Permission {
+    /// Read the record, directly or through a query.
+    Read,
+    /// Update or delete the record.
+    Write,
+    /// Full control over the record, given to its creator.
+    Admin,
+}
+
+/// Who an [`AclEntry`] applies to.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum Principal {
+    User(String),
+    Group(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AclEntry {
+    pub principal: Principal,
+    pub permission: Permission,
+}
+
+impl AclEntry {
+    pub fn user(username: impl Into<String>, permission: Permission) -> Self {
+        Self {
+            principal: Principal::User(username.into()),
+            permission,
+        }
+    }
+
+    pub fn group(group: impl Into<String>, permission: Permission) -> Self {
+        Self {
+            principal: Principal::Group(group.into()),
+            permission,
+        }
+    }
+
+    /// Whether this entry allows `username`, member of `groups`, to do `permission`.
+    pub fn grants(
+        &self,
+        username: &str,
+        groups: &[String],
+        permission: Permission,
+    ) -> bool {
+        let applies = match &self.principal {
+            Principal::User(user) => user == username,
+            Principal::Group(group) => groups.contains(group),
+        };
+        applies && self.permission >= permission
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grants() {
+        let groups = vec![String::from("staff")];
+        let entry = AclEntry::user("alice", Permission::Write);
+        assert!(entry.grants("alice", &[], Permission::Read));
+        assert!(entry.grants("alice", &[], Permission::Write));
+        assert!(!entry.grants("alice", &[], Permission::Admin));
+        assert!(!entry.grants("bob", &groups, Permission::Read));
+
+        let entry = AclEntry::group("staff", Permission::Read);
+        assert!(entry.grants("bob", &groups, Permission::Read));
+        assert!(!entry.grants("bob", &groups, Permission::Write));
+        assert!(!entry.grants("staff", &[], Permission::Read));
+    }
+}
@@ -0,0 +1,48 @@
+//! Metadata the server keeps about each collection, returned for
+//! `Message::DescribeCollection` and `Message::SetHistory`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How the records of a usecase are encrypted.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum EncryptionKind {
+    /// AES-GCM-SIV blobs, inserted with `Insert`.
+    Aes,
+    /// OPE ciphertexts, inserted with `InsertOpe`.
+    Ope,
+    /// AES-GCM-SIV blobs with an ORE ciphertext, inserted with `InsertOre`.
+    Ore,
+    /// AES-GCM-SIV blobs whose usecases are searchable tokens.
+    SearchableToken,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct UsecaseInfo {
+    pub name: String,
+    /// Kind of the first record inserted with the usecase.
+    pub kind: EncryptionKind,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CollectionInfo {
+    pub name: String,
+    /// Time of the first insertion into the collection.
+    pub created_at: DateTime<Utc>,
+    /// User who made the first insertion.
+    pub owner: String,
+    pub usecases: Vec<UsecaseInfo>,
+    pub record_count: u64,
+    /// Size of the data of the records, without their nonces, ACLs and indexes.
+    pub byte_size: u64,
+    /// Number of previous versions kept for each record, set with
+    /// `Message::SetHistory`. No history is kept by default.
+    #[serde(default)]
+    pub history: u32,
+}
+
+impl CollectionInfo {
+    pub fn usecase(&self, name: &str) -> Option<&UsecaseInfo> {
+        self.usecases.iter().find(|usecase| usecase.name == name)
+    }
+}
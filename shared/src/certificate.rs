@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Certificate of a server, issued by the certificate authority.
+///
+/// `public_key` is the Kyber public key of the server, clients encapsulate their part
+/// of the session key with it during the handshake. `signature` is the Falcon signature
+/// of [`Certificate::signed_content`] by the certificate authority.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Certificate {
+    pub public_key: Vec<u8>,
+    pub identity_info: String,
+    pub issuer_info: String,
+    pub signature: Vec<u8>,
+    pub valid_from: DateTime<Utc>,
+    pub valid_to: DateTime<Utc>,
+    pub serial_number: Uuid,
+    pub cipher_suits: Vec<String>,
+}
+
+impl Certificate {
+    /// Bytes covered by `signature`: every other field, CBOR encoded.
+    pub fn signed_content(&self) -> Vec<u8> {
+        let content = (
+            &self.public_key,
+            &self.identity_info,
+            &self.issuer_info,
+            &self.valid_from,
+            &self.valid_to,
+            &self.serial_number,
+            &self.cipher_suits,
+        );
+        serde_cbor::to_vec(&content).expect("certificate fields are serializable")
+    }
+}
@@ -0,0 +1,247 @@
+//! Framing of [`Message`]s on a byte stream.
+//!
+//! A frame is made of the protocol version and the message type on one byte each, the
+//! length of the payload as a big-endian `u32` and the CBOR encoded message. Once the
+//! handshake is done, the payload is sealed by a [`SessionCipher`] which also
+//! authenticates the version and type bytes.
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::message::Message;
+use crate::message_type::{MessageType, PROTOCOL_VERSION};
+use crate::session::SessionCipher;
+
+/// Size of the version byte, the type byte and the length of a frame.
+pub const HEADER_SIZE: usize = 6;
+
+/// Largest payload accepted by [`LiserkCodec::default`], 16 MiB.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The peer speaks another version of the protocol.
+    #[error("unsupported protocol version {0}, expected {PROTOCOL_VERSION}")]
+    UnsupportedVersion(u8),
+
+    #[error("unknown message type {0}")]
+    UnknownMessageType(u8),
+
+    /// The announced payload is bigger than the maximum frame size.
+    #[error("frame of {size} bytes exceeds the maximum of {max} bytes")]
+    FrameTooLarge { size: usize, max: usize },
+
+    /// The type byte of the header is not the type of the decoded message.
+    #[error("header announces {header} but payload is {payload}")]
+    TypeMismatch { header: MessageType, payload: MessageType },
+
+    /// The payload could not be sealed, or was not sealed by the peer's session key.
+    #[error("frame encryption or authentication failed")]
+    Sealing,
+
+    #[error("invalid cbor payload: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+
+    /// The stream ended in the middle of a frame.
+    #[error("stream closed with {0} bytes of an incomplete frame")]
+    Truncated(usize),
+}
+
+/// Encodes and decodes [`Message`]s, to be used with `FramedRead` and `FramedWrite`.
+#[derive(Debug, Clone)]
+pub struct LiserkCodec {
+    max_frame_size: usize,
+    encrypt: Option<SessionCipher>,
+    decrypt: Option<SessionCipher>,
+}
+
+impl LiserkCodec {
+    /// Creates a codec rejecting payloads bigger than `max_frame_size` bytes.
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size, encrypt: None, decrypt: None }
+    }
+
+    /// Seals every frame encoded from now on.
+    pub fn encrypt_with(&mut self, cipher: SessionCipher) {
+        self.encrypt = Some(cipher);
+    }
+
+    /// Opens every frame decoded from now on, unsealed frames are rejected.
+    pub fn decrypt_with(&mut self, cipher: SessionCipher) {
+        self.decrypt = Some(cipher);
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), CodecError> {
+        if size > self.max_frame_size {
+            return Err(CodecError::FrameTooLarge { size, max: self.max_frame_size });
+        }
+        Ok(())
+    }
+}
+
+impl Default for LiserkCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl Decoder for LiserkCodec {
+    type Item = Message;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, CodecError> {
+        if src.len() < HEADER_SIZE {
+            src.reserve(HEADER_SIZE - src.len());
+            return Ok(None);
+        }
+        if src[0] != PROTOCOL_VERSION {
+            return Err(CodecError::UnsupportedVersion(src[0]));
+        }
+        let header = MessageType::try_from(src[1])
+            .map_err(|_| CodecError::UnknownMessageType(src[1]))?;
+        let size = u32::from_be_bytes([src[2], src[3], src[4], src[5]]) as usize;
+        self.check_size(size)?;
+
+        let frame_size = HEADER_SIZE + size;
+        if src.len() < frame_size {
+            src.reserve(frame_size - src.len());
+            return Ok(None);
+        }
+        let aad = [src[0], src[1]];
+        src.advance(HEADER_SIZE);
+        let mut payload = src.split_to(size).to_vec();
+        if let Some(cipher) = &mut self.decrypt {
+            payload = cipher.open(&aad, &payload).ok_or(CodecError::Sealing)?;
+        }
+        let message: Message = serde_cbor::from_slice(&payload)?;
+        let payload = message.message_type();
+        if payload != header {
+            return Err(CodecError::TypeMismatch { header, payload });
+        }
+        Ok(Some(message))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Message>, CodecError> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if src.is_empty() => Ok(None),
+            None => Err(CodecError::Truncated(src.len())),
+        }
+    }
+}
+
+impl Encoder<Message> for LiserkCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let aad = [PROTOCOL_VERSION, message.message_type().into()];
+        let mut payload = serde_cbor::to_vec(&message)?;
+        if let Some(cipher) = &mut self.encrypt {
+            payload = cipher.seal(&aad, &payload).ok_or(CodecError::Sealing)?;
+        }
+        self.check_size(payload.len())?;
+        dst.reserve(HEADER_SIZE + payload.len());
+        dst.extend_from_slice(&aad);
+        dst.put_u32(payload.len() as u32);
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Delete;
+
+    fn delete() -> Message {
+        Message::Delete(Delete {
+            collection: "users".into(),
+            id: "42".into(),
+            expected_version: None,
+        })
+    }
+
+    #[test]
+    fn test_round_trip_with_partial_reads() {
+        let mut codec = LiserkCodec::default();
+        let mut encoded = BytesMut::new();
+        codec.encode(delete(), &mut encoded).unwrap();
+        assert_eq!(encoded.to_vec(), delete().setup_for_network().unwrap());
+
+        let mut src = BytesMut::new();
+        for byte in encoded.iter() {
+            assert_eq!(codec.decode(&mut src).unwrap(), None);
+            src.put_u8(*byte);
+        }
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(delete()));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_reject_oversized_frame_before_reading_it() {
+        let mut codec = LiserkCodec::new(16);
+        let mut src = BytesMut::new();
+        src.put_u8(PROTOCOL_VERSION);
+        src.put_u8(MessageType::Insert.into());
+        src.put_u32(u32::MAX);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(CodecError::FrameTooLarge { size, max: 16 }) if size == u32::MAX as usize
+        ));
+    }
+
+    #[test]
+    fn test_reject_type_mismatch() {
+        let mut codec = LiserkCodec::default();
+        let mut src = BytesMut::from(&delete().setup_for_network().unwrap()[..]);
+        src[1] = MessageType::Insert.into();
+        assert!(matches!(codec.decode(&mut src), Err(CodecError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_reject_other_protocol_version() {
+        let mut codec = LiserkCodec::default();
+        let mut src = BytesMut::from(&delete().setup_for_network().unwrap()[..]);
+        src[0] = PROTOCOL_VERSION + 1;
+        assert!(matches!(codec.decode(&mut src), Err(CodecError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_sealed_frames() {
+        let key = [3; 32];
+        let mut client = LiserkCodec::default();
+        client.encrypt_with(SessionCipher::new(&key));
+        let mut server = LiserkCodec::default();
+        server.decrypt_with(SessionCipher::new(&key));
+
+        let mut src = BytesMut::new();
+        client.encode(delete(), &mut src).unwrap();
+        client.encode(delete(), &mut src).unwrap();
+        assert_ne!(
+            src[HEADER_SIZE..],
+            delete().setup_for_network().unwrap()[HEADER_SIZE..]
+        );
+        assert_eq!(server.decode(&mut src).unwrap(), Some(delete()));
+        assert_eq!(server.decode(&mut src).unwrap(), Some(delete()));
+
+        let mut plain = BytesMut::from(&delete().setup_for_network().unwrap()[..]);
+        assert!(matches!(server.decode(&mut plain), Err(CodecError::Sealing)));
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut codec = LiserkCodec::default();
+        assert_eq!(codec.decode_eof(&mut BytesMut::new()).unwrap(), None);
+
+        let encoded = delete().setup_for_network().unwrap();
+        let mut src = BytesMut::from(&encoded[..encoded.len() - 1]);
+        assert!(matches!(codec.decode_eof(&mut src), Err(CodecError::Truncated(_))));
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Stable code of an error reported by the server in `Message::Error`.
+///
+/// The numeric value returned by [`ErrorCode::code`] never changes for a given
+/// variant, new variants only get new numbers.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ErrorCode {
+    /// The requested record, collection or usecase does not exist.
+    NotFound,
+    /// The caller is not allowed to perform the request.
+    PermissionDenied,
+    /// The request conflicts with a concurrent modification or existing data.
+    Conflict,
+    /// The request could not be decoded or is not valid for the server.
+    MalformedRequest,
+    /// The storage layer could not be reached.
+    StorageUnavailable,
+    /// The request is valid but not supported by this server.
+    Unsupported,
+    /// Any other failure of the server.
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            ErrorCode::NotFound => 404,
+            ErrorCode::PermissionDenied => 403,
+            ErrorCode::Conflict => 409,
+            ErrorCode::MalformedRequest => 400,
+            ErrorCode::StorageUnavailable => 503,
+            ErrorCode::Unsupported => 501,
+            ErrorCode::Internal => 500,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::NotFound => write!(f, "not found"),
+            ErrorCode::PermissionDenied => write!(f, "permission denied"),
+            ErrorCode::Conflict => write!(f, "conflict"),
+            ErrorCode::MalformedRequest => write!(f, "malformed request"),
+            ErrorCode::StorageUnavailable => write!(f, "storage unavailable"),
+            ErrorCode::Unsupported => write!(f, "unsupported"),
+            ErrorCode::Internal => write!(f, "internal error"),
+        }
+    }
+}
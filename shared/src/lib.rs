@@ -1,3 +1,9 @@
+pub mod acl;
+pub mod catalog;
+pub mod certificate;
+pub mod codec;
+pub mod error;
 pub mod message;
 pub mod message_type;
 pub mod query;
+pub mod session;
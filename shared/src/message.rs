@@ -1,8 +1,18 @@
-use crate::{message_type::MessageType, query::Query};
+use crate::{
+    acl::AclEntry,
+    catalog::CollectionInfo,
+    certificate::Certificate,
+    error::ErrorCode,
+    message_type::{MessageType, PROTOCOL_VERSION},
+    query::{Query, QueryPlan},
+    session::CipherSuite,
+};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 ///
-/// QueryOutput is a serialized output of the query
-pub type QueryOutput = (Vec<Vec<u8>>, Option<Vec<Vec<u8>>>);
+/// QueryOutput is a serialized output of the query, with the nonce of each record
+/// if they are encrypted with AES. OPE records have no nonce.
+pub type QueryOutput = (Vec<Vec<u8>>, Option<Vec<Option<Vec<u8>>>>);
 
 /// Enum representing different types of messages exchanged between the client and server.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -11,10 +21,33 @@ pub enum Message {
     /// The associated `ClientSetupSecureConnection` contains the necessary information for establishing the secure connection.
     ClientSetup(ClientSetupSecureConnection),
 
+    /// Sent by the server in response to `ClientSetup`, see [`crate::session`].
+    ServerSetup(Box<ServerSetupSecureConnection>),
+
+    /// Last message of the handshake, sent by the client, see [`crate::session`].
+    ClientKeyExchange(ClientKeyExchange),
+
     /// Message used for client authentication.
     /// The associated `ClientAuthentication` typically contains the credentials needed for authentication.
     ClientAuthentification(ClientAuthentication),
 
+    /// Sent by the server in response to `ClientAuthentification`.
+    AuthenticationResponse(AuthenticationResponse),
+
+    /// Used by an administrator to create a user.
+    CreateUser(NewUser),
+
+    /// Used by an administrator to delete a user.
+    DeleteUser { username: String },
+
+    /// Used by a user to change their password, or by an administrator to reset the
+    /// password of any user.
+    ChangePassword(PasswordChange),
+
+    /// Sent by the server when a `CreateUser`, `DeleteUser` or `ChangePassword` request
+    /// succeeded. Contains the name of the user concerned.
+    UserResponse { username: String },
+
     /// Used by the client to insert data into the database.
     /// The `Insertion` structure typically contains the data to be inserted along with metadata such as the collection in which the data should be stored.
     Insert(Insertion),
@@ -22,9 +55,17 @@ pub enum Message {
     /// Similar to `Insert`, but used specifically for inserting data that is encrypted using Order-Preserving Encryption (OPE).
     InsertOpe(InsertionOpe),
 
+    /// Similar to `Insert`, with the order revealing encryption (ORE) of the value
+    /// which range queries are evaluated on.
+    InsertOre(InsertionOre),
+
     /// Sent by the server in response to an `Insert` message to acknowledge that the data has been inserted.
-    /// Contains the ID of the inserted data.
-    InsertResponse { inserted_id: String },
+    /// Contains the ID of the inserted data and its version.
+    InsertResponse {
+        inserted_id: String,
+        #[serde(default)]
+        version: u64,
+    },
 
     /// Used by the client to query data from the database.
     /// The `Query` structure contains the necessary information to perform the data query.
@@ -35,8 +76,16 @@ pub enum Message {
     QueryResponse(QueryOutput),
 
     /// Sent by the server in response to a query that requests a single value.
-    /// Contains the requested data, or None if it doesn't exist.
-    SingleValueResponse { data: Option<Vec<u8>>, nonce: Option<Vec<u8>> },
+    /// Contains the requested data, or None if it doesn't exist, and its version.
+    SingleValueResponse {
+        data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        #[serde(default)]
+        version: Option<u64>,
+    },
+
+    /// Sent by the server in response to a `Query::Explain`.
+    ExplainResponse(QueryPlan),
 
     /// Message sent by the client to request a count of documents that meet certain criteria.
     /// The `CountSubject` structure defines the criteria for counting.
@@ -57,20 +106,53 @@ pub enum Message {
     /// The `Delete` structure contains the details of what data should be deleted.
     Delete(Delete),
 
-    /// Sent by the server to indicate the result of a deletion request.
-    DeleteResult(bool),
+    /// Sent by the server in response to a `Delete` or `DeleteForUsecase` message to
+    /// indicate the status of the deletion.
+    DeleteResult(DeleteStatus),
 
-    /// Message sent by the client to delete data for a specific use case.
-    /// Contains the collection name and the ID of the document to be deleted.
-    DeleteForUsecase { collection: String, id: String },
+    /// Message sent by the client to remove a document from a specific use case.
+    /// The document is no longer returned by the queries on the use case, but is kept.
+    DeleteForUsecase { collection: String, usecase: String, id: String },
 
     /// Message sent by the client to request the deletion of an entire collection or use case.
     /// The `DropSubject` structure defines what should be dropped.
     Drop(DropSubject),
 
-    /// Sent by the server to indicate the result of a drop request.
+    /// Sent by the server while a drop request is in progress, after each batch of
+    /// deletions. Contains the number of keys, or of records for a usecase, dropped so
+    /// far.
+    DropProgress { dropped: u64 },
+
+    /// Sent by the server to indicate the result of a drop request, `true` if
+    /// something was dropped.
     DropResult(bool),
 
+    /// Sent by the server when a request fails.
+    /// `request_id` is the position of the failed request among the messages received on
+    /// the connection after the handshake, starting at 0.
+    Error { code: ErrorCode, message: String, request_id: u64 },
+
+    /// Message sent by the client to request the names of the collections it owns, or
+    /// of every collection for an administrator.
+    ListCollections,
+
+    /// Sent by the server in response to `ListCollections`.
+    CollectionList(Vec<String>),
+
+    /// Message sent by the client to request the metadata of a collection it owns, or
+    /// of any collection for an administrator.
+    DescribeCollection { collection: String },
+
+    /// Sent by the server in response to `DescribeCollection`, `None` if the
+    /// collection doesn't exist, and to `SetHistory`.
+    CollectionDescription(Option<CollectionInfo>),
+
+    /// Message sent by the client to keep the last `versions` previous values of each
+    /// record of a collection, readable with `Query::GetVersion`. 0 disables the
+    /// history, the versions already kept are removed by the next update of their
+    /// record.
+    SetHistory { collection: String, versions: u32 },
+
     /// Message indicating the end of a communication sequence.
     EndOfCommunication,
 
@@ -82,22 +164,38 @@ impl Message {
     pub fn message_type(&self) -> MessageType {
         match self {
             Message::ClientSetup(_) => MessageType::Setup,
+            Message::ServerSetup(_) => MessageType::ServerSetup,
+            Message::ClientKeyExchange(_) => MessageType::ClientKeyExchange,
             Message::ClientAuthentification(_) => MessageType::Authentification,
+            Message::AuthenticationResponse(_) => MessageType::AuthenticationResponse,
+            Message::CreateUser(_) => MessageType::CreateUser,
+            Message::DeleteUser { .. } => MessageType::DeleteUser,
+            Message::ChangePassword(_) => MessageType::ChangePassword,
+            Message::UserResponse { .. } => MessageType::UserResponse,
             Message::Insert(_) => MessageType::Insert,
             Message::InsertOpe(_) => MessageType::InsertOpe,
+            Message::InsertOre(_) => MessageType::InsertOre,
             Message::InsertResponse { .. } => MessageType::InsertResponse,
             Message::Query(_) => MessageType::Query,
             Message::QueryResponse { .. } => MessageType::QueryResponse,
             Message::SingleValueResponse { .. } => MessageType::SingleValueResponse,
+            Message::ExplainResponse(_) => MessageType::ExplainResponse,
             Message::Count(_) => MessageType::Count,
-            Message::CountResponse(_) => todo!(),
+            Message::CountResponse(_) => MessageType::CountResponse,
             Message::Update { .. } => MessageType::Update,
             Message::UpdateResponse { .. } => MessageType::UpdateResponse,
             Message::Delete(_) => MessageType::Delete,
             Message::DeleteResult(_) => MessageType::DeleteResult,
             Message::DeleteForUsecase { .. } => MessageType::DeleteForUsecase,
             Message::Drop(_) => MessageType::Drop,
+            Message::DropProgress { .. } => MessageType::DropProgress,
             Message::DropResult(_) => MessageType::DropResult,
+            Message::ListCollections => MessageType::ListCollections,
+            Message::CollectionList(_) => MessageType::CollectionList,
+            Message::DescribeCollection { .. } => MessageType::DescribeCollection,
+            Message::CollectionDescription(_) => MessageType::CollectionDescription,
+            Message::SetHistory { .. } => MessageType::SetHistory,
+            Message::Error { .. } => MessageType::Error,
             Message::EndOfCommunication => MessageType::EndOfCommunication,
             Message::CloseCommunication => MessageType::CloseCommunication,
         }
@@ -110,8 +208,8 @@ impl Message {
         let message_length = message.len() as u32;
         let message_length = message_length.to_be_bytes();
 
-        let message_type_as_bytes = [message_type];
-        Ok([&message_type_as_bytes[..], &message_length, &message].concat())
+        let header = [PROTOCOL_VERSION, message_type];
+        Ok([&header[..], &message_length, &message].concat())
     }
 }
 
@@ -123,68 +221,208 @@ pub enum CountSubject {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub enum DropSubject {
+    /// Every key of the collection.
     Collection(String),
-    Usecase { collection: String, usecase: String },
+    /// The index of the usecase. With `records`, the records which have no other
+    /// usecase are deleted too, the others are only removed from the usecase.
+    Usecase { collection: String, usecase: String, records: bool },
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ClientSetupSecureConnection {
-    protocol_version: String,
-    client_public_key: Vec<u8>,
-    cipher_suits: Vec<String>,
-    compression: String,
+    pub protocol_version: u32,
+    /// Ephemeral Kyber public key of the client.
+    pub client_public_key: Vec<u8>,
+    /// Names of the cipher suites supported by the client, by order of preference.
+    pub cipher_suits: Vec<String>,
+    pub compression: String,
 }
 
 impl ClientSetupSecureConnection {
     pub fn new(public_key: Vec<u8>) -> Self {
         Self {
-            protocol_version: String::from("0.1.0"),
+            protocol_version: PROTOCOL_VERSION as u32,
             client_public_key: public_key,
-            cipher_suits: vec![String::from("kyber768"), String::from("falcon")],
+            cipher_suits: CipherSuite::SUPPORTED
+                .iter()
+                .map(|suite| suite.name().to_string())
+                .collect(),
             compression: String::from("0"),
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ServerSetupSecureConnection {
+    pub protocol_version: u32,
+    pub certificate: Certificate,
+    /// Kyber encapsulation of the first session secret to the client public key.
+    pub ciphertext: Vec<u8>,
+    pub session_id: Uuid,
+    /// Cipher suite chosen by the server among the ones offered by the client.
+    pub cipher_suit: String,
+    pub compression: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ClientKeyExchange {
+    /// Kyber encapsulation of the second session secret to the certificate key.
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ClientAuthentication {
     pub username: String,
     pub password: String,
 }
 
+impl std::fmt::Debug for ClientAuthentication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientAuthentication")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum AuthenticationResponse {
+    /// The credentials are valid, every request of the connection is now made as this
+    /// user.
+    Success { session_id: Uuid },
+    /// The credentials are not valid, the connection stays unauthenticated.
+    Failure,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    /// Administrators can manage the other users.
+    pub admin: bool,
+    /// Groups the user belongs to, which ACL entries can refer to.
+    pub groups: Vec<String>,
+}
+
+impl std::fmt::Debug for NewUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewUser")
+            .field("username", &self.username)
+            .field("admin", &self.admin)
+            .field("groups", &self.groups)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct PasswordChange {
+    pub username: String,
+    /// Required when users change their own password, ignored when an administrator
+    /// resets the password of someone else.
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl std::fmt::Debug for PasswordChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordChange")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Replaces the value of a record.
+///
+/// The records inserted with `InsertOpe` or `InsertOre` can't be updated, as their
+/// range index entries can't follow a value encrypted with AES. The server refuses
+/// them with an `ErrorCode::Unsupported` error.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Update {
     pub collection: String,
     pub id: String,
+    /// Value encrypted with AES under `nonce`, which replaces the nonce of the record.
     pub new_value: Vec<u8>,
+    pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub options: UpdateOptions,
+}
+
+/// Changes made by an `Update` besides the new value.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct UpdateOptions {
+    pub add_usecases: Vec<String>,
+    pub remove_usecases: Vec<String>,
+    /// Whether the added usecases are searchable tokens computed by the client.
+    pub searchable: bool,
+    /// Replaces the ACL of the record, which requires `Permission::Admin` on it.
+    pub acl: Option<Vec<AclEntry>>,
+    /// The update is refused with `UpdateStatus::Conflict` unless the record is at
+    /// this version. Records are inserted at version 1 and each update increments it.
+    pub expected_version: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub enum UpdateStatus {
     Success,
+    /// Not sent anymore, the update of a record indexed by OPE or ORE is refused with
+    /// an `ErrorCode::Unsupported` error.
     Failure,
     KeyNotFound,
+    /// The record is not at the expected version, `version` is its current version.
+    Conflict {
+        version: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum DeleteStatus {
+    Success,
+    /// The document doesn't exist, or doesn't have the use case of a
+    /// `DeleteForUsecase`.
+    KeyNotFound,
+    /// The document is not at the expected version, `version` is its current version.
+    Conflict {
+        version: u64,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Insertion {
     pub collection: String,
-    pub acl: Vec<String>,
+    pub acl: Vec<AclEntry>,
     pub data: Vec<u8>,
     pub usecases: Vec<String>,
     pub nonce: Vec<u8>,
+    /// Whether the usecases are searchable tokens computed by the client.
+    #[serde(default)]
+    pub searchable: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct InsertionOpe {
     pub collection: String,
-    pub acl: Vec<String>,
+    pub acl: Vec<AclEntry>,
     pub data: Vec<u8>,
     pub usecases: Vec<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct InsertionOre {
+    pub collection: String,
+    pub acl: Vec<AclEntry>,
+    /// Value encrypted with AES, returned by the queries.
+    pub data: Vec<u8>,
+    pub usecases: Vec<String>,
+    pub nonce: Vec<u8>,
+    /// ORE right ciphertext of the value, compared to the bounds of range queries.
+    pub ciphertext: Vec<u8>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Delete {
     pub collection: String,
     pub id: String,
+    /// The deletion is refused with `DeleteStatus::Conflict` unless the record is at
+    /// this version.
+    #[serde(default)]
+    pub expected_version: Option<u64>,
 }
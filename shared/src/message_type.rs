@@ -1,53 +1,118 @@
 use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of the wire protocol, sent as the first byte of every frame.
+///
+/// It must be incremented whenever the encoding of an existing message changes.
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// Declares `MessageType` with its discriminants, which are the type byte of a frame.
+///
+/// Every conversion (from a byte, to and from a name) is generated from this list, so
+/// adding a message only takes a new line with a new number. Numbers already used
+/// must never be changed nor reused.
+macro_rules! message_types {
+    ($($variant:ident = $value:literal,)*) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+        #[repr(u8)]
+        pub enum MessageType {
+            $($variant = $value,)*
+        }
+
+        impl MessageType {
+            /// Every message type, ordered by discriminant.
+            pub const ALL: &'static [MessageType] = &[$(MessageType::$variant,)*];
+
+            /// Name of the message type, as used by `Display` and serde.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(MessageType::$variant => stringify!($variant),)*
+                }
+            }
+        }
+
+        impl TryFrom<u8> for MessageType {
+            type Error = MessageTypeError;
+
+            fn try_from(v: u8) -> Result<Self, MessageTypeError> {
+                match v {
+                    $($value => Ok(MessageType::$variant),)*
+                    _ => Err(MessageTypeError::default()),
+                }
+            }
+        }
+
+        impl FromStr for MessageType {
+            type Err = MessageTypeError;
+
+            fn from_str(s: &str) -> Result<Self, MessageTypeError> {
+                match s {
+                    $(stringify!($variant) => Ok(MessageType::$variant),)*
+                    _ => Err(MessageTypeError::default()),
+                }
+            }
+        }
+    };
+}
+
+message_types! {
+    Setup = 0,
+    Authentification = 1,
+    Insert = 2,
+    InsertResponse = 3,
+    Query = 4,
+    QueryResponse = 5,
+    SingleValueResponse = 6,
+    Count = 7,
+    Update = 8,
+    UpdateResponse = 9,
+    Delete = 10,
+    DeleteResult = 11,
+    DeleteForUsecase = 12,
+    Drop = 13,
+    DropResult = 14,
+    EndOfCommunication = 15,
+    CloseCommunication = 16,
+    InsertOpe = 17,
+    Error = 18,
+    CountResponse = 19,
+    ServerSetup = 20,
+    ClientKeyExchange = 21,
+    AuthenticationResponse = 22,
+    CreateUser = 23,
+    DeleteUser = 24,
+    ChangePassword = 25,
+    UserResponse = 26,
+    ExplainResponse = 27,
+    InsertOre = 28,
+    DropProgress = 29,
+    ListCollections = 30,
+    CollectionList = 31,
+    DescribeCollection = 32,
+    CollectionDescription = 33,
+    SetHistory = 34,
+}
 
-use serde::{Deserialize, Deserializer, Serialize};
-use tracing::debug;
-
-#[derive(Debug, Serialize)]
-#[repr(u8)]
-pub enum MessageType {
-    Setup,
-    Authentification,
-    Insert,
-    InsertOpe,
-    InsertResponse,
-    Query,
-    QueryResponse,
-    SingleValueResponse,
-    Count,
-    Update,
-    UpdateResponse,
-    Delete,
-    DeleteResult,
-    DeleteForUsecase,
-    Drop,
-    DropResult,
-    EndOfCommunication,
-    CloseCommunication,
+impl From<MessageType> for u8 {
+    fn from(message_type: MessageType) -> u8 {
+        message_type as u8
+    }
 }
 
 impl Display for MessageType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MessageType::Setup => write!(f, "Setup communcication"),
-            MessageType::Authentification => write!(f, "Authentification"),
-            MessageType::Insert => write!(f, "Insert"),
-            MessageType::InsertOpe => write!(f, "InsertOpe"),
-            MessageType::InsertResponse => write!(f, "InsertResponse"),
-            MessageType::Query => write!(f, "Query"),
-            MessageType::QueryResponse => write!(f, "QueryResponse"),
-            MessageType::SingleValueResponse => write!(f, "SingleValueResponse"),
-            MessageType::Count => todo!(),
-            MessageType::Update => write!(f, "Update"),
-            MessageType::UpdateResponse => write!(f, "UpdateResponse"),
-            MessageType::Delete => write!(f, "Delete"),
-            MessageType::DeleteResult => write!(f, "DeleteResult"),
-            MessageType::DeleteForUsecase => write!(f, "DeleteForUsecase"),
-            MessageType::Drop => write!(f, "Drop"),
-            MessageType::DropResult => write!(f, "DropResult"),
-            MessageType::EndOfCommunication => write!(f, "EndOfCommunication"),
-            MessageType::CloseCommunication => write!(f, "CloseCommunication"),
-        }
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Serialize for MessageType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
     }
 }
 
@@ -57,80 +122,8 @@ impl<'de> Deserialize<'de> for MessageType {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        debug!("parsing message type: {}", s);
-
-        if s == "Setup" {
-            return Ok(MessageType::Setup);
-        }
-
-        if s == "Authentification" {
-            return Ok(MessageType::Authentification);
-        }
-
-        if s == "Insert" {
-            return Ok(MessageType::Insert);
-        }
-
-        if s == "InsertOpe" {
-            return Ok(MessageType::InsertOpe);
-        }
-
-        if s == "InsertResponse" {
-            return Ok(MessageType::InsertResponse);
-        }
-
-        if s == "Query" {
-            return Ok(MessageType::Query);
-        }
-
-        if s == "QueryResponse" {
-            return Ok(MessageType::QueryResponse);
-        }
-
-        if s == "SingleValueResponse" {
-            return Ok(MessageType::SingleValueResponse);
-        }
-
-        if s == "Count" {
-            return Ok(MessageType::Count);
-        }
-
-        if s == "Update" {
-            return Ok(MessageType::Update);
-        }
-
-        if s == "UpdateResponse" {
-            return Ok(MessageType::UpdateResponse);
-        }
-
-        if s == "Delete" {
-            return Ok(MessageType::Delete);
-        }
-
-        if s == "DeleteResult" {
-            return Ok(MessageType::DeleteResult);
-        }
-
-        if s == "DeleteForUsecase" {
-            return Ok(MessageType::DeleteForUsecase);
-        }
-
-        if s == "Drop" {
-            return Ok(MessageType::Drop);
-        }
-
-        if s == "DeleteResult" {
-            return Ok(MessageType::DropResult);
-        }
-
-        if s == "EndOfCommunication" {
-            return Ok(MessageType::EndOfCommunication);
-        }
-
-        if s == "CloseCommunication" {
-            return Ok(MessageType::CloseCommunication);
-        }
-        panic!("panic deserialize message type");
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("unknown message type {}", s)))
     }
 }
 
@@ -138,30 +131,32 @@ impl<'de> Deserialize<'de> for MessageType {
 #[error("fail to parse MessageType")]
 pub struct MessageTypeError {}
 
-impl TryFrom<u8> for MessageType {
-    type Error = MessageTypeError;
-
-    fn try_from(v: u8) -> Result<Self, Self::Error> {
-        match v {
-            0 => Ok(MessageType::Setup),
-            1 => Ok(MessageType::Authentification),
-            2 => Ok(MessageType::Insert),
-            3 => Ok(MessageType::InsertResponse),
-            4 => Ok(MessageType::Query),
-            5 => Ok(MessageType::QueryResponse),
-            6 => Ok(MessageType::SingleValueResponse),
-            7 => Ok(MessageType::Count),
-            8 => Ok(MessageType::Update),
-            9 => Ok(MessageType::UpdateResponse),
-            10 => Ok(MessageType::Delete),
-            11 => Ok(MessageType::DeleteResult),
-            12 => Ok(MessageType::DeleteForUsecase),
-            13 => Ok(MessageType::Drop),
-            14 => Ok(MessageType::DropResult),
-            15 => Ok(MessageType::EndOfCommunication),
-            16 => Ok(MessageType::CloseCommunication),
-            17 => Ok(MessageType::InsertOpe),
-            _ => Err(MessageTypeError::default()),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_are_consistent() {
+        for message_type in MessageType::ALL {
+            let byte = u8::from(*message_type);
+            assert_eq!(MessageType::try_from(byte).unwrap(), *message_type);
+            assert_eq!(
+                message_type.to_string().parse::<MessageType>().unwrap(),
+                *message_type
+            );
+        }
+        assert_eq!(MessageType::InsertOpe as u8, 17);
+        assert!(MessageType::try_from(MessageType::ALL.len() as u8).is_err());
+        assert!("Unknown".parse::<MessageType>().is_err());
+    }
+
+    #[test]
+    fn test_serde_uses_names() {
+        let encoded = serde_cbor::to_vec(&MessageType::DeleteResult).unwrap();
+        let decoded: MessageType = serde_cbor::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, MessageType::DeleteResult);
+
+        let unknown = serde_cbor::to_vec(&"Unknown").unwrap();
+        assert!(serde_cbor::from_slice::<MessageType>(&unknown).is_err());
     }
 }
@@ -12,8 +12,32 @@ pub enum QueryType {
 pub enum Query {
     Single(SingleQuery),
     Compound(CompoundQuery),
-    GetById { id: String, collection: String },
-    GetByIds { ids: Vec<String>, collection: String },
+    GetById {
+        id: String,
+        collection: String,
+    },
+    GetByIds {
+        ids: Vec<String>,
+        collection: String,
+    },
+    /// Reads a record at `version`, its current version or a previous one kept by the
+    /// history of the collection.
+    GetVersion {
+        id: String,
+        collection: String,
+        version: u64,
+    },
+    /// Matches the records of `collection` inserted with `token` among their
+    /// usecases. Tokens are computed by the client so the server can't tell which
+    /// usecase or value they stand for.
+    Equals {
+        collection: String,
+        token: String,
+    },
+    /// Returns the [`QueryPlan`] chosen by the server for the query instead of its
+    /// results. Only the owners of the collections of the query and the
+    /// administrators can explain it.
+    Explain(Box<Query>),
 }
 
 impl PartialEq for Query {
@@ -29,6 +53,23 @@ impl PartialEq for Query {
                 Self::GetByIds { ids: l_ids, collection: l_collection },
                 Self::GetByIds { ids: r_ids, collection: r_collection },
             ) => l_ids == r_ids && l_collection == r_collection,
+            (
+                Self::GetVersion {
+                    id: l_id,
+                    collection: l_collection,
+                    version: l_version,
+                },
+                Self::GetVersion {
+                    id: r_id,
+                    collection: r_collection,
+                    version: r_version,
+                },
+            ) => l_id == r_id && l_collection == r_collection && l_version == r_version,
+            (
+                Self::Equals { collection: l_collection, token: l_token },
+                Self::Equals { collection: r_collection, token: r_token },
+            ) => l_collection == r_collection && l_token == r_token,
+            (Self::Explain(l0), Self::Explain(r0)) => l0 == r0,
             _ => false,
         }
     }
@@ -44,8 +85,68 @@ impl Eq for Query {}
 pub struct SingleQuery {
     pub collection: String,
     pub usecase: String,
-    pub upper_limit: Option<f64>,
-    pub lower_limit: Option<f64>,
+    pub upper_limit: Option<RangeBound>,
+    pub lower_limit: Option<RangeBound>,
+}
+
+/// Largest OPE ciphertext the server indexes, in bytes of its big-endian unsigned
+/// integer.
+pub const OPE_CIPHERTEXT_WIDTH: usize = 64;
+
+/// Scheme the values of a usecase are encrypted with for range queries.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum RangeScheme {
+    /// Order preserving encryption, inserted with `InsertOpe`. The server compares
+    /// the ciphertexts directly and keeps them sorted in an index.
+    #[default]
+    Ope,
+    /// Order revealing encryption, inserted with `InsertOre`. The server compares the
+    /// left ciphertext of a bound to the right ciphertext of each record.
+    Ore,
+}
+
+/// Bound of a range query, compared to the ciphertexts of the records so the server
+/// never sees the plaintext value.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct RangeBound {
+    /// OPE ciphertext of the bound, encoded like the data of an `InsertOpe`, or its
+    /// ORE left ciphertext.
+    pub ciphertext: Vec<u8>,
+    /// Whether the records equal to the bound match.
+    pub inclusive: bool,
+    #[serde(default)]
+    pub scheme: RangeScheme,
+}
+
+impl RangeBound {
+    pub fn inclusive(ciphertext: Vec<u8>) -> Self {
+        Self {
+            ciphertext,
+            inclusive: true,
+            scheme: RangeScheme::Ope,
+        }
+    }
+
+    pub fn exclusive(ciphertext: Vec<u8>) -> Self {
+        Self {
+            ciphertext,
+            inclusive: false,
+            scheme: RangeScheme::Ope,
+        }
+    }
+
+    pub fn with_scheme(mut self, scheme: RangeScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Ciphertext as displayed in a [`QueryPlan`], ORE ciphertexts are not text.
+    fn display_ciphertext(&self) -> String {
+        match self.scheme {
+            RangeScheme::Ope => String::from_utf8_lossy(&self.ciphertext).to_string(),
+            RangeScheme::Ore => format!("ore({} bytes)", self.ciphertext.len()),
+        }
+    }
 }
 
 impl PartialEq for SingleQuery {
@@ -103,8 +204,8 @@ impl Eq for CompoundQuery {}
 pub struct SingleQueryBuilder {
     collection: String,
     usecase: String,
-    upper_limit: Option<f64>,
-    lower_limit: Option<f64>,
+    upper_limit: Option<RangeBound>,
+    lower_limit: Option<RangeBound>,
 }
 
 impl SingleQueryBuilder {
@@ -118,13 +219,15 @@ impl SingleQueryBuilder {
         self
     }
 
-    pub fn with_encrypted_field_less_than(mut self, value: f64) -> Self {
-        self.upper_limit = Some(value);
+    /// Only matches the records whose ciphertext is below `bound`.
+    pub fn with_upper_bound(mut self, bound: RangeBound) -> Self {
+        self.upper_limit = Some(bound);
         self
     }
 
-    pub fn with_encrypted_field_higher_than(mut self, value: f64) -> Self {
-        self.lower_limit = Some(value);
+    /// Only matches the records whose ciphertext is above `bound`.
+    pub fn with_lower_bound(mut self, bound: RangeBound) -> Self {
+        self.lower_limit = Some(bound);
         self
     }
 
@@ -185,3 +288,71 @@ impl CompoundQueryBuilder {
         CompoundQuery { query_type: self.query_type, queries: self.queries }
     }
 }
+
+/// How the server evaluates a query, as returned for [`Query::Explain`].
+///
+/// The server reads the usecase indexes to estimate how many records each step
+/// matches, the sub-queries of an `And` are evaluated from the most selective one.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum QueryPlan {
+    /// Reads the usecase index, or the range index of the usecase if the query has
+    /// bounds, which is scanned in full for ORE bounds. A `Query::Equals` reads the
+    /// usecase index of its token.
+    Usecase { query: SingleQuery, estimated_rows: u32 },
+    /// Reads records by id.
+    Ids { collection: String, ids: Vec<String> },
+    /// Evaluates the steps in order and combines their records. An `And` stops as soon
+    /// as no record is left.
+    Compound { query_type: QueryType, steps: Vec<QueryPlan>, estimated_rows: u32 },
+}
+
+impl Eq for QueryPlan {}
+
+impl QueryPlan {
+    /// Upper estimate of the number of records matched by the plan.
+    pub fn estimated_rows(&self) -> u32 {
+        match self {
+            QueryPlan::Usecase { estimated_rows, .. }
+            | QueryPlan::Compound { estimated_rows, .. } => *estimated_rows,
+            QueryPlan::Ids { ids, .. } => ids.len() as u32,
+        }
+    }
+
+    fn fmt_indented(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        depth: usize,
+    ) -> std::fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            QueryPlan::Usecase { query, estimated_rows } => {
+                write!(f, "{}usecase {}:{}", indent, query.collection, query.usecase)?;
+                if let Some(bound) = &query.lower_limit {
+                    let operator = if bound.inclusive { ">=" } else { ">" };
+                    write!(f, " {} {}", operator, bound.display_ciphertext())?;
+                }
+                if let Some(bound) = &query.upper_limit {
+                    let operator = if bound.inclusive { "<=" } else { "<" };
+                    write!(f, " {} {}", operator, bound.display_ciphertext())?;
+                }
+                writeln!(f, " (~{} rows)", estimated_rows)
+            }
+            QueryPlan::Ids { collection, ids } => {
+                writeln!(f, "{}ids {} ({} rows)", indent, collection, ids.len())
+            }
+            QueryPlan::Compound { query_type, steps, estimated_rows } => {
+                writeln!(f, "{}{:?} (~{} rows)", indent, query_type, estimated_rows)?;
+                for step in steps {
+                    step.fmt_indented(f, depth + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
@@ -0,0 +1,182 @@
+//! Secure session established by the handshake.
+//!
+//! 1. The client sends `Message::ClientSetup` with an ephemeral Kyber public key and the
+//!    cipher suites it supports.
+//! 2. The server picks a cipher suite and answers `Message::ServerSetup` with its
+//!    certificate, a fresh `session_id` and a Kyber encapsulation to the client key.
+//! 3. The client encapsulates a second secret to the Kyber key of the certificate and
+//!    sends it in `Message::ClientKeyExchange`. Only the owner of the certificate can
+//!    decapsulate it.
+//! 4. Both sides derive one key per direction from the two secrets with
+//!    [`SessionKeys::derive`], every later frame is sealed by a [`SessionCipher`].
+
+use std::fmt::Display;
+
+use aes_gcm_siv::{
+    aead::{generic_array::GenericArray, Aead, Payload},
+    Aes256GcmSiv, KeyInit,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::message_type::MessageType;
+
+/// Length of the nonce of a sealed frame.
+const NONCE_SIZE: usize = 12;
+
+/// Algorithms protecting a session.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CipherSuite {
+    /// Kyber768 key encapsulation, HKDF-SHA256 and AES-256-GCM-SIV.
+    Kyber768Aes256GcmSiv,
+}
+
+impl CipherSuite {
+    /// Cipher suites supported by this version, by order of preference.
+    pub const SUPPORTED: &'static [CipherSuite] = &[CipherSuite::Kyber768Aes256GcmSiv];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CipherSuite::Kyber768Aes256GcmSiv => "kyber768-aes256gcmsiv-sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::SUPPORTED.iter().copied().find(|suite| suite.name() == name)
+    }
+
+    /// Picks the first suite offered by the peer that is also supported here.
+    pub fn negotiate(offered: &[String]) -> Option<Self> {
+        offered.iter().find_map(|name| Self::from_name(name))
+    }
+}
+
+impl Display for CipherSuite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("no common cipher suite in {0:?}")]
+    NoCommonCipherSuite(Vec<String>),
+
+    #[error("kyber key encapsulation failed")]
+    KeyEncapsulation,
+
+    #[error("unexpected message {0} during the handshake")]
+    UnexpectedMessage(MessageType),
+
+    #[error("connection closed during the handshake")]
+    ConnectionClosed,
+}
+
+/// Keys of both directions of a session.
+pub struct SessionKeys {
+    pub client_to_server: [u8; 32],
+    pub server_to_client: [u8; 32],
+}
+
+impl SessionKeys {
+    /// Derives the keys from the shared secrets of the handshake, in the order they
+    /// were established.
+    pub fn derive(secrets: &[&[u8]], session_id: Uuid) -> Self {
+        let secret = secrets.concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(session_id.as_bytes()), &secret);
+        let mut keys = Self {
+            client_to_server: [0; 32],
+            server_to_client: [0; 32],
+        };
+        hkdf.expand(b"liserk client to server", &mut keys.client_to_server)
+            .expect("32 bytes is a valid hkdf output length");
+        hkdf.expand(b"liserk server to client", &mut keys.server_to_client)
+            .expect("32 bytes is a valid hkdf output length");
+        keys
+    }
+}
+
+/// Seals or opens the frames of one direction of a session.
+///
+/// The nonce of a frame is its sequence number in that direction, so a frame that is
+/// replayed, dropped or reordered fails to open.
+#[derive(Clone)]
+pub struct SessionCipher {
+    cipher: Aes256GcmSiv,
+    sequence: u64,
+}
+
+impl SessionCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256GcmSiv::new(GenericArray::from_slice(key)),
+            sequence: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> Option<[u8; NONCE_SIZE]> {
+        let mut nonce = [0; NONCE_SIZE];
+        nonce[NONCE_SIZE - 8..].copy_from_slice(&self.sequence.to_be_bytes());
+        self.sequence = self.sequence.checked_add(1)?;
+        Some(nonce)
+    }
+
+    /// Encrypts the payload of the next frame, authenticating `aad` with it.
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let payload = Payload { msg: plaintext, aad };
+        self.cipher.encrypt(GenericArray::from_slice(&nonce), payload).ok()
+    }
+
+    /// Decrypts the payload of the next frame, `None` if it was tampered with.
+    pub fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let payload = Payload { msg: ciphertext, aad };
+        self.cipher.decrypt(GenericArray::from_slice(&nonce), payload).ok()
+    }
+}
+
+impl std::fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionCipher")
+            .field("sequence", &self.sequence)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let offered =
+            vec![String::from("rot13"), CipherSuite::Kyber768Aes256GcmSiv.to_string()];
+        assert_eq!(
+            CipherSuite::negotiate(&offered),
+            Some(CipherSuite::Kyber768Aes256GcmSiv)
+        );
+        assert_eq!(CipherSuite::negotiate(&[String::from("rot13")]), None);
+    }
+
+    #[test]
+    fn test_frames_must_be_opened_in_order() {
+        let session_id = Uuid::new_v4();
+        let keys = SessionKeys::derive(&[&[1; 32], &[2; 32]], session_id);
+        assert_ne!(keys.client_to_server, keys.server_to_client);
+
+        let mut client = SessionCipher::new(&keys.client_to_server);
+        let mut server = SessionCipher::new(&keys.client_to_server);
+        let first = client.seal(b"aad", b"first").unwrap();
+        let second = client.seal(b"aad", b"second").unwrap();
+
+        assert_eq!(server.clone().open(b"aad", &second), None);
+        assert_eq!(server.clone().open(b"other", &first), None);
+        assert_eq!(server.open(b"aad", &first).unwrap(), b"first");
+        assert_eq!(server.open(b"aad", &second).unwrap(), b"second");
+    }
+}
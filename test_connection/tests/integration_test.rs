@@ -2,21 +2,38 @@
 #[cfg(test)]
 mod tests {
     use serial_test::serial;
-    use std::{assert, sync::Once};
+    use std::ops::Bound;
+    use std::{assert, sync::Arc, sync::Once, sync::OnceLock, thread, time::Duration};
 
     use liserk_shared::query::{
-        CompoundQueryBuilder, Query, QueryType, SingleQueryBuilder,
+        CompoundQueryBuilder, Query, QueryPlan, QueryType, RangeScheme,
+        SingleQueryBuilder,
     };
     use tracing::{error, info, Level};
     use tracing_subscriber::FmtSubscriber;
 
-    use liserk_client::{AuthenticatedClient, UnconnectedClient};
-    use liserk_server::BINDED_URL_PORT;
+    use liserk_client::error::{CertificateError, Error, ServerError};
+    use liserk_client::ope::OpeSettings;
+    use liserk_client::stream::{AuthenticatedClient, QueryResult, UnconnectedClient};
+    use liserk_client::trust::TrustAnchor;
+    use liserk_server::handshake::ServerIdentity;
+    use liserk_server::settings::ServerConfig;
+    use liserk_server::storage::EmbeddedBackend;
+    use liserk_server::{run_app_with_storage, BINDED_URL_PORT};
+    use liserk_shared::acl::{AclEntry, Permission};
+    use liserk_shared::catalog::EncryptionKind;
+    use liserk_shared::error::ErrorCode;
     use liserk_shared::message::Message;
-    use liserk_shared::message::UpdateStatus;
+    use liserk_shared::message::{
+        DeleteStatus, DropSubject, UpdateOptions, UpdateStatus,
+    };
+    use pqcrypto_falcon::falcon512;
+    use pqcrypto_traits::sign::{DetachedSignature, PublicKey};
 
     pub const USERNAME: &str = "Bob";
     pub const PASSWORD: &str = "Pomme";
+    /// Every test shares the same storage, so they all encrypt with the same key.
+    pub const KEY: [u8; 32] = [7; 32];
 
     pub trait ToStringVec {
         fn to_string_vec(&self) -> Vec<String>;
@@ -31,9 +48,9 @@ mod tests {
     pub async fn connect_and_auth_client(
         client: UnconnectedClient,
     ) -> AuthenticatedClient {
-        let client = client.connect(BINDED_URL_PORT).await.unwrap();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
         client
-            .authenticate(USERNAME.to_string(), PASSWORD.to_string())
+            .authenticate(USERNAME.to_string(), PASSWORD.to_string(), KEY)
             .await
             .unwrap()
     }
@@ -43,7 +60,8 @@ mod tests {
             .insert(
                 "users".to_string(),
                 [12, 112, 29, 176].to_vec(),
-                ["read", "write"].to_string_vec(),
+                vec![],
+                vec![],
                 ["authentification", "authorization"].to_string_vec(),
             )
             .await
@@ -53,7 +71,8 @@ mod tests {
             .insert(
                 "users".to_string(),
                 [12, 1, 2, 178, 76, 23, 145].to_vec(),
-                ["read"].to_string_vec(),
+                vec![],
+                vec![],
                 ["search"].to_string_vec(),
             )
             .await
@@ -63,7 +82,8 @@ mod tests {
             .insert(
                 "".to_string(),
                 [12, 122, 221, 234, 178, 76, 23, 178, 97, 23, 18, 7, 6, 23, 145].to_vec(),
-                ["read"].to_string_vec(),
+                vec![],
+                vec![],
                 ["logging"].to_string_vec(),
             )
             .await
@@ -73,6 +93,7 @@ mod tests {
             .insert(
                 "posts".to_string(),
                 [76, 231, 15, 13, 42, 54, 78].to_vec(),
+                vec![],
                 [].to_vec(),
                 [].to_vec(),
             )
@@ -83,7 +104,8 @@ mod tests {
             .insert(
                 "documents".to_string(),
                 [1, 2, 3, 4, 65, 68, 67].to_vec(),
-                ["read", "write", "delete"].to_string_vec(),
+                vec![],
+                vec![],
                 ["storage", "search"].to_string_vec(),
             )
             .await
@@ -93,38 +115,79 @@ mod tests {
         let product_data = vec![5, 6, 7, 8]; // Some binary data for a product
         let order_data = vec![9, 10, 11, 12]; // Some binary data for an order
 
-        let acl = ["read:all", "write:all"].to_string_vec(); // Access control list for the data
+        let acl = vec![AclEntry::group("all", Permission::Write)]; // Access control list for the data
         let user_usecases = ["filter", "another_usecase"].to_string_vec();
         let product_usecases = ["filter", "yet_another_usecase"].to_string_vec();
         let order_usecases = ["filter", "different_usecase"].to_string_vec();
 
         // Insert user data
         client
-            .insert("users".to_string(), user_data, acl.clone(), user_usecases)
+            .insert("users".to_string(), user_data, vec![], acl.clone(), user_usecases)
             .await
             .unwrap();
 
         // Insert product data
         client
-            .insert("products".to_string(), product_data, acl.clone(), product_usecases)
+            .insert(
+                "products".to_string(),
+                product_data,
+                vec![],
+                acl.clone(),
+                product_usecases,
+            )
             .await
             .unwrap();
 
         // Insert order data
         client
-            .insert("orders".to_string(), order_data, acl.clone(), order_usecases)
+            .insert("orders".to_string(), order_data, vec![], acl.clone(), order_usecases)
             .await
             .unwrap();
     }
 
     static INIT: Once = Once::new();
+    static AUTHORITY: OnceLock<(falcon512::PublicKey, falcon512::SecretKey)> =
+        OnceLock::new();
+
+    /// Falcon keys of the certificate authority which signs the server certificate.
+    fn authority() -> &'static (falcon512::PublicKey, falcon512::SecretKey) {
+        AUTHORITY.get_or_init(falcon512::keypair)
+    }
+
+    pub fn trust_anchor() -> TrustAnchor {
+        TrustAnchor::new(authority().0.as_bytes(), "Server").unwrap()
+    }
 
     pub fn initialize() {
         INIT.call_once(|| {
             setup_logger();
+            start_server();
         });
     }
 
+    /// Runs the server on an in-memory storage in its own runtime, so it outlives the
+    /// runtime of the test which started it.
+    fn start_server() {
+        thread::spawn(|| {
+            let runtime =
+                tokio::runtime::Runtime::new().expect("failed to build runtime");
+            let storage = Arc::new(EmbeddedBackend::in_memory());
+            let identity = ServerIdentity::generate(|content| {
+                falcon512::detached_sign(content, &authority().1).as_bytes().to_vec()
+            });
+            let mut config = ServerConfig::default();
+            config.auth.admin_username = USERNAME.to_string();
+            config.auth.admin_password = PASSWORD.to_string();
+            let server = run_app_with_storage(config, storage, identity);
+            if let Err(err) = runtime.block_on(server) {
+                error!("server stopped: {:?}", err);
+            }
+        });
+        while std::net::TcpStream::connect(BINDED_URL_PORT).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
     fn setup_logger() {
         let subscriber = FmtSubscriber::builder().with_max_level(Level::TRACE).finish();
         tracing::subscriber::set_global_default(subscriber)
@@ -137,9 +200,9 @@ mod tests {
         initialize();
 
         let client = UnconnectedClient::default();
-        let client = client.connect(BINDED_URL_PORT).await.unwrap();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
         let mut client = client
-            .authenticate(USERNAME.to_string(), PASSWORD.to_string())
+            .authenticate(USERNAME.to_string(), PASSWORD.to_string(), KEY)
             .await
             .unwrap();
         assert!(client.is_alive());
@@ -148,6 +211,177 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_refuse_wrong_password() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
+        let result = client
+            .authenticate(USERNAME.to_string(), "Poire".to_string(), KEY)
+            .await;
+        assert!(matches!(result, Err(Error::AuthenticationFailed)));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_manage_users() {
+        initialize();
+
+        let mut admin = connect_and_auth_client(UnconnectedClient::default()).await;
+        admin
+            .create_user("Alice".to_string(), "Fraise".to_string(), false, vec![])
+            .await
+            .unwrap();
+
+        let client = UnconnectedClient::default();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
+        let mut alice = client
+            .authenticate("Alice".to_string(), "Fraise".to_string(), KEY)
+            .await
+            .unwrap();
+        let result = alice
+            .create_user("Eve".to_string(), "Eve".to_string(), true, vec![])
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::Server(ServerError { code: ErrorCode::PermissionDenied, .. }))
+        ));
+        alice
+            .change_password(
+                "Alice".to_string(),
+                "Fraise".to_string(),
+                "Cerise".to_string(),
+            )
+            .await
+            .unwrap();
+        alice.terminate_connection().await.unwrap();
+
+        admin.delete_user("Alice".to_string()).await.unwrap();
+        let client = UnconnectedClient::default();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
+        let result = client
+            .authenticate("Alice".to_string(), "Cerise".to_string(), KEY)
+            .await;
+        assert!(matches!(result, Err(Error::AuthenticationFailed)));
+        admin.terminate_connection().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_record_acl() {
+        initialize();
+
+        let mut admin = connect_and_auth_client(UnconnectedClient::default()).await;
+        admin
+            .create_user(
+                "Carol".to_string(),
+                "Kiwi".to_string(),
+                false,
+                vec!["readers".to_string()],
+            )
+            .await
+            .unwrap();
+        admin
+            .create_user("Dave".to_string(), "Mangue".to_string(), false, vec![])
+            .await
+            .unwrap();
+        let acl = vec![AclEntry::group("readers", Permission::Read)];
+        let shared_id = admin
+            .insert("acl".to_string(), vec![1], vec![], acl, ["acl"].to_string_vec())
+            .await
+            .unwrap();
+        let private_id = admin
+            .insert("acl".to_string(), vec![2], vec![], vec![], ["acl"].to_string_vec())
+            .await
+            .unwrap();
+
+        let client = UnconnectedClient::default();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
+        let mut carol = client
+            .authenticate("Carol".to_string(), "Kiwi".to_string(), KEY)
+            .await
+            .unwrap();
+        let query = Query::GetById { id: shared_id.clone(), collection: "acl".into() };
+        assert!(matches!(
+            carol.query(query).await.unwrap(),
+            QueryResult::SingleValue(data) if data == vec![1]
+        ));
+        let query = Query::GetById { id: private_id.clone(), collection: "acl".into() };
+        assert!(matches!(
+            carol.query(query).await,
+            Err(Error::Server(ServerError { code: ErrorCode::PermissionDenied, .. }))
+        ));
+        let query = Query::GetByIds {
+            ids: vec![shared_id.clone(), private_id.clone()],
+            collection: "acl".into(),
+        };
+        match carol.query(query).await.unwrap() {
+            QueryResult::MultipleValues(data) => assert_eq!(data, vec![vec![1]]),
+            result => panic!("unexpected result {:?}", result),
+        }
+        let result = carol.modify(shared_id.clone(), "acl".into(), vec![3]).await;
+        assert!(matches!(
+            result,
+            Err(Error::Server(ServerError { code: ErrorCode::PermissionDenied, .. }))
+        ));
+        carol.terminate_connection().await.unwrap();
+
+        let client = UnconnectedClient::default();
+        let client = client.connect(BINDED_URL_PORT, &trust_anchor()).await.unwrap();
+        let mut dave = client
+            .authenticate("Dave".to_string(), "Mangue".to_string(), KEY)
+            .await
+            .unwrap();
+        let query = SingleQueryBuilder::default()
+            .with_collection("acl".to_owned())
+            .with_usecase("acl".to_owned())
+            .build();
+        match dave.query(Query::Single(query)).await.unwrap() {
+            QueryResult::MultipleValues(data) => assert!(data.is_empty()),
+            result => panic!("unexpected result {:?}", result),
+        }
+        let result = dave.delete(shared_id.clone(), "acl".into()).await;
+        assert!(matches!(
+            result,
+            Err(Error::Server(ServerError { code: ErrorCode::PermissionDenied, .. }))
+        ));
+        dave.terminate_connection().await.unwrap();
+
+        for id in [shared_id, private_id] {
+            admin.delete(id, "acl".into()).await.unwrap();
+        }
+        admin.delete_user("Carol".to_string()).await.unwrap();
+        admin.delete_user("Dave".to_string()).await.unwrap();
+        admin.terminate_connection().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_refuse_server_of_other_authority() {
+        initialize();
+
+        let (other_key, _) = falcon512::keypair();
+        let trust_anchor = TrustAnchor::new(other_key.as_bytes(), "Server").unwrap();
+        let result = UnconnectedClient::default()
+            .connect(BINDED_URL_PORT, &trust_anchor)
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::CertificateError(CertificateError::BadSignature))
+        ));
+
+        let trust_anchor = TrustAnchor::new(authority().0.as_bytes(), "Other").unwrap();
+        let result = UnconnectedClient::default()
+            .connect(BINDED_URL_PORT, &trust_anchor)
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::CertificateError(CertificateError::IdentityMismatch { .. }))
+        ));
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_insert() {
@@ -164,6 +398,7 @@ mod tests {
                     76, 23, 145,
                 ]
                 .to_vec(),
+                vec![],
                 [].to_vec(),
                 ["Tomate"].to_string_vec(),
             )
@@ -173,7 +408,7 @@ mod tests {
         assert!(x.is_ok());
         let result = client.delete(x.unwrap(), "table".into()).await.unwrap();
         match result {
-            Message::DeleteResult(status) => assert!(status),
+            Message::DeleteResult(status) => assert_eq!(status, DeleteStatus::Success),
             _ => assert!(false),
         };
 
@@ -182,6 +417,194 @@ mod tests {
         }
     }
 
+    async fn pantry_records(client: &mut AuthenticatedClient, usecase: &str) -> usize {
+        let query = SingleQueryBuilder::default()
+            .with_collection("pantry".into())
+            .with_usecase(usecase.into())
+            .build();
+        match client.query(Query::Single(query)).await.unwrap() {
+            QueryResult::MultipleValues(data) => data.len(),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_delete_cleans_up_indexes() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        let mut ids = Vec::new();
+        for data in [1, 2] {
+            let id = client
+                .insert(
+                    "pantry".into(),
+                    vec![data],
+                    vec![],
+                    vec![],
+                    ["fruit", "red"].to_string_vec(),
+                )
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        let weight = client
+            .insert_ope(12.5, vec![], ["weight"].to_string_vec(), "pantry".into())
+            .await
+            .unwrap();
+
+        let result = client.delete(ids[0].clone(), "pantry".into()).await.unwrap();
+        assert_eq!(result, Message::DeleteResult(DeleteStatus::Success));
+        assert_eq!(pantry_records(&mut client, "fruit").await, 1);
+        // The plan reads the size of the usecase index.
+        let red = SingleQueryBuilder::default()
+            .with_collection("pantry".into())
+            .with_usecase("red".into())
+            .build();
+        let plan = client.explain(Query::Single(red.clone())).await.unwrap();
+        assert_eq!(plan.estimated_rows(), 1);
+        let result = client.delete(ids[0].clone(), "pantry".into()).await.unwrap();
+        assert_eq!(result, Message::DeleteResult(DeleteStatus::KeyNotFound));
+
+        let result = client
+            .delete_for_usecase(ids[1].clone(), "pantry".into(), "red".into())
+            .await
+            .unwrap();
+        assert_eq!(result, Message::DeleteResult(DeleteStatus::Success));
+        assert_eq!(pantry_records(&mut client, "red").await, 0);
+        let plan = client.explain(Query::Single(red)).await.unwrap();
+        assert_eq!(plan.estimated_rows(), 0);
+        assert_eq!(pantry_records(&mut client, "fruit").await, 1);
+        let result = client
+            .delete_for_usecase(ids[1].clone(), "pantry".into(), "red".into())
+            .await
+            .unwrap();
+        assert_eq!(result, Message::DeleteResult(DeleteStatus::KeyNotFound));
+
+        client.delete(weight, "pantry".into()).await.unwrap();
+        let weights: Vec<f64> = client
+            .query_range(
+                "pantry".into(),
+                "weight".into(),
+                Bound::Unbounded,
+                Bound::Unbounded,
+            )
+            .await
+            .unwrap();
+        assert!(weights.is_empty());
+        client.delete(ids[1].clone(), "pantry".into()).await.unwrap();
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    async fn attic_records(client: &mut AuthenticatedClient, usecase: &str) -> usize {
+        let query = SingleQueryBuilder::default()
+            .with_collection("attic".into())
+            .with_usecase(usecase.into())
+            .build();
+        match client.query(Query::Single(query)).await.unwrap() {
+            QueryResult::MultipleValues(data) => data.len(),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_drop() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        for usecases in [["box"].to_string_vec(), ["box", "lamp"].to_string_vec()] {
+            client
+                .insert("attic".into(), vec![1], vec![], vec![], usecases)
+                .await
+                .unwrap();
+        }
+
+        let subject = DropSubject::Usecase {
+            collection: "attic".into(),
+            usecase: "box".into(),
+            records: false,
+        };
+        let mut progress = Vec::new();
+        let dropped = client.drop(subject, |dropped| progress.push(dropped)).await;
+        assert!(dropped.unwrap());
+        assert_eq!(progress, vec![2]);
+        assert_eq!(attic_records(&mut client, "box").await, 0);
+        assert_eq!(attic_records(&mut client, "lamp").await, 1);
+
+        let subject = DropSubject::Collection("attic".into());
+        assert!(client.drop(subject.clone(), |_| {}).await.unwrap());
+        assert_eq!(attic_records(&mut client, "lamp").await, 0);
+        assert!(!client.drop(subject, |_| {}).await.unwrap());
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_collection_catalog() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        let book = client
+            .insert(
+                "library".into(),
+                vec![1, 2, 3],
+                vec![],
+                vec![],
+                ["book"].to_string_vec(),
+            )
+            .await
+            .unwrap();
+        client
+            .insert_ope(1.5, vec![], ["pages"].to_string_vec(), "library".into())
+            .await
+            .unwrap();
+        client
+            .insert_searchable(
+                "library".into(),
+                vec![4],
+                vec![],
+                vec![],
+                ["shelf"].to_string_vec(),
+                vec![],
+            )
+            .await
+            .unwrap();
+
+        let collections = client.list_collections().await.unwrap();
+        assert!(collections.contains(&"library".to_string()));
+        let info = client.describe_collection("library".into()).await.unwrap().unwrap();
+        assert_eq!(info.owner, USERNAME);
+        assert_eq!(info.record_count, 3);
+        assert_eq!(info.usecase("book").unwrap().kind, EncryptionKind::Aes);
+        assert_eq!(info.usecase("pages").unwrap().kind, EncryptionKind::Ope);
+        let shelf = client.sse.usecase_token("shelf");
+        assert_eq!(info.usecase(&shelf).unwrap().kind, EncryptionKind::SearchableToken);
+
+        let byte_size = info.byte_size;
+        client.delete(book, "library".into()).await.unwrap();
+        let info = client.describe_collection("library".into()).await.unwrap().unwrap();
+        assert_eq!(info.record_count, 2);
+        assert_eq!(info.byte_size, byte_size - 16 - 3);
+
+        let subject = DropSubject::Collection("library".into());
+        client.drop(subject, |_| {}).await.unwrap();
+        assert_eq!(client.describe_collection("library".into()).await.unwrap(), None);
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_simple_query() {
@@ -193,7 +616,13 @@ mod tests {
         let user_data = vec![122, 122, 122, 122, 211]; // Some binary data for a user
 
         let _inserted_id = client
-            .insert("users".to_string(), user_data, vec![], ["filter"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                user_data,
+                vec![],
+                vec![],
+                ["filter"].to_string_vec(),
+            )
             .await
             .unwrap();
 
@@ -243,13 +672,266 @@ mod tests {
 
         let main_query = CompoundQueryBuilder::default()
             .with_query_type(QueryType::And)
-            .with_query(Query::Single(order_filter))
-            .with_query(Query::Compound(sub_query))
+            .with_query(Query::Single(order_filter.clone()))
+            .with_query(Query::Compound(sub_query.clone()))
             .build();
 
         let x = client.query(Query::Compound(main_query)).await;
-        assert!(x.is_ok());
         info!("query result {:?}", x);
+        // Orders and users or products are stored under different keys.
+        assert!(
+            matches!(x, Ok(QueryResult::MultipleValues(values)) if values.is_empty())
+        );
+
+        let main_query = CompoundQueryBuilder::default()
+            .with_query_type(QueryType::Or)
+            .with_query(Query::Single(order_filter))
+            .with_query(Query::Compound(sub_query))
+            .build();
+        match client.query(Query::Compound(main_query)).await.unwrap() {
+            QueryResult::MultipleValues(values) => {
+                assert!(values.contains(&vec![1, 2, 3, 4]));
+                assert!(values.contains(&vec![5, 6, 7, 8]));
+                assert!(values.contains(&vec![9, 10, 11, 12]));
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_explain() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        insert_some_data(&mut client).await;
+
+        let filter = SingleQueryBuilder::default()
+            .with_collection("users".to_owned())
+            .with_usecase("filter".to_owned())
+            .build();
+        let unknown = SingleQueryBuilder::default()
+            .with_collection("users".to_owned())
+            .with_usecase("unknown".to_owned())
+            .build();
+        let query = CompoundQueryBuilder::default()
+            .with_query_type(QueryType::And)
+            .with_query(Query::Single(filter.clone()))
+            .with_query(Query::Single(unknown.clone()))
+            .build();
+
+        let plan = client.explain(Query::Compound(query)).await.unwrap();
+        info!("plan\n{}", plan);
+        match plan {
+            QueryPlan::Compound { steps, estimated_rows, .. } => {
+                assert_eq!(estimated_rows, 0);
+                assert_eq!(
+                    steps[0],
+                    QueryPlan::Usecase { query: unknown, estimated_rows: 0 }
+                );
+                assert!(matches!(&steps[1], QueryPlan::Usecase { query, estimated_rows }
+                    if *query == filter && *estimated_rows > 0));
+            }
+            plan => panic!("unexpected plan {:?}", plan),
+        }
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    async fn range(
+        client: &mut AuthenticatedClient,
+        lower: Bound<f64>,
+        upper: Bound<f64>,
+    ) -> Vec<f64> {
+        let mut numbers = client
+            .query_range("measures".into(), "size".into(), lower, upper)
+            .await
+            .unwrap();
+        numbers.sort_by(f64::total_cmp);
+        numbers
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_query_range() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        for number in [1.0, 5.0, 9.0] {
+            client
+                .insert_ope(number, vec![], ["size"].to_string_vec(), "measures".into())
+                .await
+                .unwrap();
+        }
+
+        let numbers =
+            range(&mut client, Bound::Included(2.0), Bound::Excluded(9.0)).await;
+        assert_eq!(numbers, vec![5.0]);
+        let numbers =
+            range(&mut client, Bound::Included(1.0), Bound::Included(9.0)).await;
+        assert_eq!(numbers, vec![1.0, 5.0, 9.0]);
+        let numbers = range(&mut client, Bound::Excluded(1.0), Bound::Unbounded).await;
+        assert_eq!(numbers, vec![5.0, 9.0]);
+        let numbers = range(&mut client, Bound::Unbounded, Bound::Excluded(1.0)).await;
+        assert!(numbers.is_empty());
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_query_range_typed_values() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        for (name, age) in [("alice", -3i64), ("bob", 40), ("carol", 1 << 40)] {
+            client
+                .insert_ope(
+                    name.to_string(),
+                    vec![],
+                    ["name"].to_string_vec(),
+                    "people".into(),
+                )
+                .await
+                .unwrap();
+            client
+                .insert_ope(age, vec![], ["age"].to_string_vec(), "people".into())
+                .await
+                .unwrap();
+        }
+
+        let mut names = client
+            .query_range(
+                "people".into(),
+                "name".into(),
+                Bound::Included("b".to_string()),
+                Bound::Unbounded,
+            )
+            .await
+            .unwrap();
+        names.sort();
+        assert_eq!(names, vec!["bob", "carol"]);
+
+        let mut ages = client
+            .query_range(
+                "people".into(),
+                "age".into(),
+                Bound::Unbounded,
+                Bound::Excluded(40),
+            )
+            .await
+            .unwrap();
+        ages.sort();
+        assert_eq!(ages, vec![-3]);
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_query_range_ore() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        let settings = OpeSettings { scheme: RangeScheme::Ore, ..Default::default() };
+        client.ope.set_settings("rating", settings).unwrap();
+        for rating in [-2.5, 0.0, 3.25, 4.5] {
+            client
+                .insert_ope(rating, vec![], ["rating"].to_string_vec(), "reviews".into())
+                .await
+                .unwrap();
+        }
+
+        let mut ratings = client
+            .query_range(
+                "reviews".into(),
+                "rating".into(),
+                Bound::Excluded(-2.5),
+                Bound::Included(4.0),
+            )
+            .await
+            .unwrap();
+        ratings.sort_by(f64::total_cmp);
+        assert_eq!(ratings, vec![0.0, 3.25]);
+
+        let mut ratings = client
+            .query_range(
+                "reviews".into(),
+                "rating".into(),
+                Bound::Unbounded,
+                Bound::Unbounded,
+            )
+            .await
+            .unwrap();
+        ratings.sort_by(f64::total_cmp);
+        assert_eq!(ratings, vec![-2.5, 0.0, 3.25, 4.5]);
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_query_equals() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        for (data, city) in [(1, "Paris"), (2, "Lyon"), (3, "Paris")] {
+            client
+                .insert_searchable(
+                    "residents".into(),
+                    vec![data],
+                    vec![],
+                    vec![],
+                    ["resident"].to_string_vec(),
+                    vec![("city".into(), city.as_bytes().to_vec())],
+                )
+                .await
+                .unwrap();
+        }
+
+        match client
+            .query_equals("residents".into(), "city", b"Paris")
+            .await
+            .unwrap()
+        {
+            QueryResult::MultipleValues(mut data) => {
+                data.sort();
+                assert_eq!(data, vec![vec![1], vec![3]]);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        let query = client.sse.usecase_query("residents".into(), "resident");
+        match client.query(query).await.unwrap() {
+            QueryResult::MultipleValues(data) => assert_eq!(data.len(), 3),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // The server only knows the tokens, not the usecase names.
+        let query = SingleQueryBuilder::default()
+            .with_collection("residents".into())
+            .with_usecase("resident".into())
+            .build();
+        match client.query(Query::Single(query)).await.unwrap() {
+            QueryResult::MultipleValues(data) => assert!(data.is_empty()),
+            result => panic!("unexpected result {:?}", result),
+        }
 
         if let Err(err) = client.terminate_connection().await {
             error!("{:?}", err);
@@ -267,7 +949,13 @@ mod tests {
         let user_data = vec![212]; // Some binary data for a user
 
         let inserted_id = client
-            .insert("users".to_string(), user_data, vec![], ["filter"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                user_data,
+                vec![],
+                vec![],
+                ["filter"].to_string_vec(),
+            )
             .await
             .unwrap();
 
@@ -275,8 +963,8 @@ mod tests {
         let result = client.query(query).await.unwrap();
         info!("query result {:?}", result);
         match result {
-            Message::SingleValueResponse { data } => {
-                assert_eq!(data.unwrap()[0], 212);
+            QueryResult::SingleValue(data) => {
+                assert_eq!(data[0], 212);
             }
             _ => assert!(false),
         }
@@ -295,19 +983,43 @@ mod tests {
         let mut client = connect_and_auth_client(client).await;
 
         let inserted_id_1 = client
-            .insert("users".to_string(), vec![1], vec![], ["filter"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                vec![1],
+                vec![],
+                vec![],
+                ["filter"].to_string_vec(),
+            )
             .await
             .unwrap();
         let inserted_id_2 = client
-            .insert("users".to_string(), vec![2], vec![], ["filter"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                vec![2],
+                vec![],
+                vec![],
+                ["filter"].to_string_vec(),
+            )
             .await
             .unwrap();
         let inserted_id_3 = client
-            .insert("users".to_string(), vec![3], vec![], ["filter"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                vec![3],
+                vec![],
+                vec![],
+                ["filter"].to_string_vec(),
+            )
             .await
             .unwrap();
         let inserted_id_4 = client
-            .insert("users".to_string(), vec![4], vec![], ["filter"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                vec![4],
+                vec![],
+                vec![],
+                ["filter"].to_string_vec(),
+            )
             .await
             .unwrap();
 
@@ -319,7 +1031,7 @@ mod tests {
         info!("query result {:?}", result);
 
         match result {
-            Message::QueryResponse { data } => {
+            QueryResult::MultipleValues(data) => {
                 assert_eq!(data.len(), 4);
             }
             _ => assert!(false),
@@ -339,7 +1051,13 @@ mod tests {
         let mut client = connect_and_auth_client(client).await;
 
         let inserted_id = client
-            .insert("users".to_string(), vec![1], vec![], ["users"].to_string_vec())
+            .insert(
+                "users".to_string(),
+                vec![1],
+                vec![],
+                vec![],
+                ["users"].to_string_vec(),
+            )
             .await
             .unwrap();
         client
@@ -351,8 +1069,8 @@ mod tests {
         let result = client.query(query).await.unwrap();
         info!("query result {:?}", result);
         match result {
-            Message::SingleValueResponse { data } => {
-                assert_eq!(data.unwrap()[0], 2);
+            QueryResult::SingleValue(data) => {
+                assert_eq!(data[0], 2);
             }
             _ => assert!(false),
         }
@@ -362,6 +1080,93 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_modify_usecases_and_version() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        let id = client
+            .insert("orchard".into(), vec![1], vec![], vec![], ["apple"].to_string_vec())
+            .await
+            .unwrap();
+
+        let mut options = UpdateOptions {
+            add_usecases: ["pear"].to_string_vec(),
+            remove_usecases: ["apple"].to_string_vec(),
+            expected_version: Some(1),
+            ..UpdateOptions::default()
+        };
+        for status in [UpdateStatus::Success, UpdateStatus::Conflict { version: 2 }] {
+            let result = client
+                .modify_with(id.clone(), "orchard".into(), vec![2], options.clone())
+                .await
+                .unwrap();
+            assert_eq!(result, Message::UpdateResponse { status });
+        }
+        options.expected_version = Some(2);
+        let result = client
+            .modify_with(id.clone(), "orchard".into(), vec![3], options)
+            .await
+            .unwrap();
+        assert_eq!(result, Message::UpdateResponse { status: UpdateStatus::Success });
+
+        for (usecase, expected) in [("apple", vec![]), ("pear", vec![vec![3]])] {
+            let query = SingleQueryBuilder::default()
+                .with_collection("orchard".into())
+                .with_usecase(usecase.into())
+                .build();
+            match client.query(Query::Single(query)).await.unwrap() {
+                QueryResult::MultipleValues(data) => assert_eq!(data, expected),
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_record_history() {
+        initialize();
+
+        let client = UnconnectedClient::default();
+        let mut client = connect_and_auth_client(client).await;
+        let info = client.set_history("ledger".into(), 2).await.unwrap();
+        assert_eq!((info.owner.as_str(), info.history), (USERNAME, 2));
+        let id = client
+            .insert("ledger".into(), vec![1], vec![], vec![], vec![])
+            .await
+            .unwrap();
+        for value in [2, 3, 4] {
+            client.modify(id.clone(), "ledger".into(), vec![value]).await.unwrap();
+        }
+
+        let current = client.get_with_version(id.clone(), "ledger".into()).await;
+        assert_eq!(current.unwrap(), Some((vec![4], 4)));
+        for (version, expected) in
+            [(4, Some(vec![4])), (3, Some(vec![3])), (2, Some(vec![2])), (1, None)]
+        {
+            let value = client.get_version(id.clone(), "ledger".into(), version).await;
+            assert_eq!(value.unwrap(), expected);
+        }
+
+        let result = client.delete_at_version(id.clone(), "ledger".into(), 3).await;
+        let status = DeleteStatus::Conflict { version: 4 };
+        assert_eq!(result.unwrap(), Message::DeleteResult(status));
+        let result = client.delete_at_version(id.clone(), "ledger".into(), 4).await;
+        assert_eq!(result.unwrap(), Message::DeleteResult(DeleteStatus::Success));
+        let value = client.get_version(id, "ledger".into(), 3).await.unwrap();
+        assert_eq!(value, None);
+
+        if let Err(err) = client.terminate_connection().await {
+            error!("{:?}", err);
+        }
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_modify_non_existing_data() {